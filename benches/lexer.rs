@@ -0,0 +1,425 @@
+//! Lexing throughput of the byte-oriented lexer against the previous
+//! char-based implementation. Run with `cargo bench --bench lexer`.
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use interpreter::{
+    lexer::{Lexer, TokenStream},
+    token::TokenKind,
+};
+
+/// The lexer as it was before it scanned bytes in place: the input is
+/// copied into a `Vec<char>` and every token gets an owned literal.
+mod baseline {
+    use interpreter::{
+        lexer::LexError,
+        token::{Span, Token, TokenKind},
+    };
+
+    pub struct Lexer {
+        input: Vec<char>,
+        position: usize,
+        read_position: usize,
+        ch: char,
+        // Byte offset, line and column of `ch`.
+        offset: usize,
+        line: usize,
+        column: usize,
+        errors: Vec<LexError>,
+    }
+
+    impl Lexer {
+        pub fn new(input: &str) -> Self {
+            let mut lex = Lexer {
+                input: input.chars().collect(),
+                position: 0,
+                read_position: 0,
+                ch: Default::default(),
+                offset: 0,
+                line: 1,
+                column: 1,
+                errors: vec![],
+            };
+
+            lex.read_char();
+
+            lex
+        }
+
+        fn peek_char(&self) -> char {
+            if self.read_position >= self.input.len() {
+                '\0'
+            } else {
+                self.input[self.read_position]
+            }
+        }
+
+        fn read_char(&mut self) {
+            if self.read_position > 0 && self.position < self.input.len() {
+                self.offset += self.ch.len_utf8();
+                if self.ch == '\n' {
+                    self.line += 1;
+                    self.column = 1;
+                } else {
+                    self.column += 1;
+                }
+            }
+
+            if self.read_position >= self.input.len() {
+                self.ch = '\0';
+            } else {
+                self.ch = self.input[self.read_position]
+            }
+            self.position = self.read_position;
+            self.read_position += 1;
+        }
+
+        fn at_eof(&self) -> bool {
+            self.position >= self.input.len()
+        }
+
+        fn should_roll_fwd(tk: &TokenKind) -> bool {
+            tk == &TokenKind::Eq || tk == &TokenKind::NotEq
+        }
+
+        pub fn next_token(&mut self) -> Token {
+            self.skip_whitespace();
+            let (start, line, column) = (self.offset, self.line, self.column);
+
+            if self.ch == '"' {
+                return self.read_string(start, line, column);
+            }
+
+            let next_char = &self.peek_char().to_string();
+            let kind = to_tok(&self.ch.to_string(), next_char);
+            let literal = to_literal(&kind, self.ch);
+
+            if Self::should_roll_fwd(&kind) {
+                self.read_char();
+            }
+            // Clean this later
+            if kind == TokenKind::Illegal && Self::is_letter(self.ch) {
+                let literal = self.read_identifier();
+                let kind = TokenKind::lookup_ident(&literal);
+                return self.token(kind, literal, start, line, column);
+            }
+
+            if kind == TokenKind::Illegal && Self::is_num(self.ch) {
+                let literal = self.read_num();
+                let kind = TokenKind::Int;
+                return self.token(kind, literal, start, line, column);
+            }
+            self.read_char();
+
+            let tok = self.token(kind, literal, start, line, column);
+            if tok.kind == TokenKind::Illegal {
+                self.errors.push(LexError::IllegalCharacter {
+                    ch: tok.literal.chars().next().unwrap_or_default(),
+                    span: tok.span,
+                });
+            }
+            tok
+        }
+
+        /// Reads a `"` delimited string starting at the opening quote and
+        /// returns it with escapes already resolved in the literal.
+        fn read_string(&mut self, start: usize, line: usize, column: usize) -> Token {
+            let mut value = String::new();
+
+            loop {
+                self.read_char();
+                match self.ch {
+                    _ if self.at_eof() => {
+                        let span = Span::new(start, self.offset, line, column);
+                        self.errors.push(LexError::UnterminatedString { span });
+                        return self.token(
+                            TokenKind::Illegal,
+                            format!("\"{}", value),
+                            start,
+                            line,
+                            column,
+                        );
+                    }
+                    '"' => break,
+                    '\\' => {
+                        if let Some(ch) = self.read_escape() {
+                            value.push(ch);
+                        }
+                    }
+                    ch => value.push(ch),
+                }
+            }
+            self.read_char();
+
+            self.token(TokenKind::String, value, start, line, column)
+        }
+
+        /// Reads the escape sequence starting at the current `\\`, leaving the
+        /// lexer on its last character. Unknown escapes are reported and dropped.
+        fn read_escape(&mut self) -> Option<char> {
+            let (start, line, column) = (self.offset, self.line, self.column);
+
+            if self.peek_char() != 'u' {
+                self.read_char();
+                let escaped = match self.ch {
+                    'n' => '\n',
+                    't' => '\t',
+                    '"' => '"',
+                    '\\' => '\\',
+                    // Leave the end of input for `read_string` to report.
+                    _ if self.at_eof() => return None,
+                    ch => {
+                        let span = Span::new(start, self.offset + ch.len_utf8(), line, column);
+                        self.errors.push(LexError::InvalidEscape {
+                            sequence: format!("\\{}", ch),
+                            span,
+                        });
+                        return None;
+                    }
+                };
+                return Some(escaped);
+            }
+
+            self.read_char();
+            let mut sequence = String::from("\\u");
+            let mut digits = String::new();
+            let mut closed = false;
+
+            if self.peek_char() == '{' {
+                self.read_char();
+                sequence.push('{');
+                while self.peek_char().is_ascii_hexdigit() {
+                    self.read_char();
+                    digits.push(self.ch);
+                }
+                sequence.push_str(&digits);
+                if self.peek_char() == '}' {
+                    self.read_char();
+                    sequence.push('}');
+                    closed = true;
+                }
+            }
+
+            let escaped = if closed && digits.len() <= 6 {
+                u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+            } else {
+                None
+            };
+
+            if escaped.is_none() {
+                let span = Span::new(start, self.offset + self.ch.len_utf8(), line, column);
+                self.errors.push(LexError::InvalidEscape { sequence, span });
+            }
+            escaped
+        }
+
+        /// Builds a token that started at the given position and ends at the current one.
+        fn token(
+            &self,
+            kind: TokenKind,
+            literal: String,
+            start: usize,
+            line: usize,
+            column: usize,
+        ) -> Token {
+            Token {
+                kind,
+                literal,
+                span: Span::new(start, self.offset, line, column),
+            }
+        }
+
+        fn skip_whitespace(&mut self) {
+            while self.ch.is_ascii_whitespace() {
+                self.read_char();
+            }
+        }
+
+        fn is_letter(ch: char) -> bool {
+            ch.is_ascii_alphabetic() || ch == '_'
+        }
+
+        fn is_num(ch: char) -> bool {
+            ch.is_numeric()
+        }
+
+        fn read_num(&mut self) -> String {
+            let mut num = String::new();
+
+            while Self::is_num(self.ch) {
+                num.push(self.ch);
+                self.read_char();
+            }
+
+            num
+        }
+
+        fn read_identifier(&mut self) -> String {
+            let mut ident = String::new();
+
+            while Self::is_letter(self.ch) {
+                ident.push(self.ch);
+                self.read_char();
+            }
+
+            ident
+        }
+    }
+
+    fn to_literal(kind: &TokenKind, literal: char) -> String {
+        if &TokenKind::Eq == kind {
+            return "==".to_string();
+        } else if &TokenKind::NotEq == kind {
+            return "!=".to_string();
+        }
+        literal.to_string()
+    }
+
+    fn to_tok(s: &str, next_char: &str) -> TokenKind {
+        match s {
+            "=" => {
+                if next_char == "=" {
+                    return TokenKind::Eq;
+                }
+                TokenKind::Assign
+            }
+            "+" => TokenKind::Plus,
+            "(" => TokenKind::Lparen,
+            ")" => TokenKind::Rparen,
+            "{" => TokenKind::Lbrace,
+            "}" => TokenKind::Rbrace,
+            "[" => TokenKind::Lbracket,
+            "]" => TokenKind::Rbracket,
+            "fn" => TokenKind::Function,
+            "let" => TokenKind::Let,
+            "if" => TokenKind::If,
+            "else" => TokenKind::Else,
+            "return" => TokenKind::Return,
+            "," => TokenKind::Comma,
+            ";" => TokenKind::Semicolon,
+            ":" => TokenKind::Colon,
+            "\0" => TokenKind::Eof,
+            "/" => TokenKind::Slash,
+            "-" => TokenKind::Minus,
+            "!" => {
+                if next_char == "=" {
+                    return TokenKind::NotEq;
+                }
+                TokenKind::Bang
+            }
+            "*" => TokenKind::Asterisk,
+            "<" => TokenKind::Lt,
+            ">" => TokenKind::Gt,
+            _ => TokenKind::Illegal,
+        }
+    }
+}
+
+const SAMPLE: &str = r#"
+let fibonacci = fn(x) {
+  if (x < 2) { return x; }
+  fibonacci(x - 1) + fibonacci(x - 2)
+};
+let people = [{"name": "Anna", "age": 24}, {"name": "Bob", "age": 99}];
+let greet = fn(person) { "Hello, " + person["name"] + "!\n" };
+let map = fn(arr, f) {
+  let iter = fn(arr, acc) {
+    if (len(arr) == 0) { acc } else { iter(rest(arr), push(acc, f(first(arr)))) }
+  };
+  iter(arr, [])
+};
+map(people, greet);
+!(fibonacci(15) != 610) == true;
+"#;
+
+/// Repetitions of `SAMPLE` in the benchmarked script.
+const COPIES: usize = 2_000;
+const ROUNDS: u32 = 10;
+
+/// Lexes a whole script and returns the number of tokens.
+type LexFn = fn(&str) -> usize;
+
+fn count_new(source: &str) -> usize {
+    let mut lexer = Lexer::new(source);
+    let mut count = 0;
+    while lexer.next_borrowed().kind != TokenKind::Eof {
+        count += 1;
+    }
+    count
+}
+
+fn count_new_owned(source: &str) -> usize {
+    let mut lexer = Lexer::new(source);
+    let mut count = 0;
+    while lexer.next_token().kind != TokenKind::Eof {
+        count += 1;
+    }
+    count
+}
+
+/// What the parser reads: borrowed tokens behind the lookahead buffer.
+fn count_stream(source: &str) -> usize {
+    let mut tokens = TokenStream::new(Lexer::new(source));
+    let mut count = 0;
+    while tokens.next_token().kind != TokenKind::Eof {
+        count += 1;
+    }
+    count
+}
+
+fn count_baseline(source: &str) -> usize {
+    let mut lexer = baseline::Lexer::new(source);
+    let mut count = 0;
+    while lexer.next_token().kind != TokenKind::Eof {
+        count += 1;
+    }
+    count
+}
+
+/// Best time of `ROUNDS` runs, which is the least disturbed by noise.
+fn measure(source: &str, lex: LexFn) -> (usize, Duration) {
+    let mut best = Duration::MAX;
+    let mut tokens = 0;
+    for _ in 0..ROUNDS {
+        let started = Instant::now();
+        tokens = black_box(lex(black_box(source)));
+        best = best.min(started.elapsed());
+    }
+    (tokens, best)
+}
+
+fn main() {
+    let source = SAMPLE.repeat(COPIES);
+    let megabytes = source.len() as f64 / (1024.0 * 1024.0);
+    println!("lexing {:.1} MiB, best of {} rounds", megabytes, ROUNDS);
+
+    let runs: [(&str, LexFn); 4] = [
+        ("baseline (Vec<char>, owned)", count_baseline),
+        ("bytes, owned tokens", count_new_owned),
+        ("bytes, borrowed tokens", count_new),
+        ("token stream, borrowed", count_stream),
+    ];
+
+    let mut expected = None;
+    for (name, lex) in runs {
+        let (tokens, time) = measure(&source, lex);
+        assert_eq!(
+            *expected.get_or_insert(tokens),
+            tokens,
+            "{} disagrees",
+            name
+        );
+        println!(
+            "{:<30} {:>9.2?} {:>8.1} MiB/s {:>12.0} tokens/s",
+            name,
+            time,
+            megabytes / time.as_secs_f64(),
+            tokens as f64 / time.as_secs_f64()
+        );
+    }
+}
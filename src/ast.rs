@@ -1,60 +1,129 @@
-
-
-use crate::token::Token;
+use crate::token::{Span, Token};
 
 pub trait Node {
     fn token_literal(&self) -> String;
     fn print_string(&self) -> String;
+    /// Source range covered by the node, from its first token to the end of its last child.
+    fn span(&self) -> Span;
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum StatementNode {
   Let(LetStatement),
+  Return(ReturnStatement),
+  Expression(ExpressionStatement),
 }
 
 impl Node for StatementNode {
   fn token_literal(&self) -> String {
     match self {
       Self::Let(stmt) => stmt.token_literal(),
+      Self::Return(stmt) => stmt.token_literal(),
+      Self::Expression(stmt) => stmt.token_literal(),
     }
   }
   fn print_string(&self) -> String {
     match self {
       Self::Let(stmt) => stmt.print_string(),
+      Self::Return(stmt) => stmt.print_string(),
+      Self::Expression(stmt) => stmt.print_string(),
+    }
+  }
+  fn span(&self) -> Span {
+    match self {
+      Self::Let(stmt) => stmt.span(),
+      Self::Return(stmt) => stmt.span(),
+      Self::Expression(stmt) => stmt.span(),
     }
   }
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum ExpressionNode {
   IdentifierNode(Identifier),
+  Integer(IntegerLiteral),
+  Float(FloatLiteral),
+  StringNode(StringLiteral),
+  BooleanNode(Boolean),
+  Prefix(PrefixExpression),
+  Infix(InfixExpression),
+  Assign(AssignExpression),
+  IfExpressionNode(IfExpression),
+  Function(FunctionLiteral),
+  Call(CallExpression),
+  Array(ArrayLiteral),
+  Index(IndexExpression),
+  Hash(HashLiteral),
 }
 
 impl Node for ExpressionNode {
   fn token_literal(&self) -> String {
     match self {
       Self::IdentifierNode(identifier) => identifier.token_literal(),
+      Self::Integer(integer) => integer.token_literal(),
+      Self::Float(float) => float.token_literal(),
+      Self::StringNode(string) => string.token_literal(),
+      Self::BooleanNode(boolean) => boolean.token_literal(),
+      Self::Prefix(prefix) => prefix.token_literal(),
+      Self::Infix(infix) => infix.token_literal(),
+      Self::Assign(assign) => assign.token_literal(),
+      Self::IfExpressionNode(if_exp) => if_exp.token_literal(),
+      Self::Function(function) => function.token_literal(),
+      Self::Call(call) => call.token_literal(),
+      Self::Array(array) => array.token_literal(),
+      Self::Index(index) => index.token_literal(),
+      Self::Hash(hash) => hash.token_literal(),
     }
   }
   fn print_string(&self) -> String {
     match self {
       Self::IdentifierNode(identifier) => identifier.print_string(),
+      Self::Integer(integer) => integer.print_string(),
+      Self::Float(float) => float.print_string(),
+      Self::StringNode(string) => string.print_string(),
+      Self::BooleanNode(boolean) => boolean.print_string(),
+      Self::Prefix(prefix) => prefix.print_string(),
+      Self::Infix(infix) => infix.print_string(),
+      Self::Assign(assign) => assign.print_string(),
+      Self::IfExpressionNode(if_exp) => if_exp.print_string(),
+      Self::Function(function) => function.print_string(),
+      Self::Call(call) => call.print_string(),
+      Self::Array(array) => array.print_string(),
+      Self::Index(index) => index.print_string(),
+      Self::Hash(hash) => hash.print_string(),
+    }
+  }
+  fn span(&self) -> Span {
+    match self {
+      Self::IdentifierNode(identifier) => identifier.span(),
+      Self::Integer(integer) => integer.span(),
+      Self::Float(float) => float.span(),
+      Self::StringNode(string) => string.span(),
+      Self::BooleanNode(boolean) => boolean.span(),
+      Self::Prefix(prefix) => prefix.span(),
+      Self::Infix(infix) => infix.span(),
+      Self::Assign(assign) => assign.span(),
+      Self::IfExpressionNode(if_exp) => if_exp.span(),
+      Self::Function(function) => function.span(),
+      Self::Call(call) => call.span(),
+      Self::Array(array) => array.span(),
+      Self::Index(index) => index.span(),
+      Self::Hash(hash) => hash.span(),
     }
   }
 }
 
+#[derive(Debug, Clone, PartialEq, Default)]
 pub struct Program {
   pub statements: Vec<StatementNode>,
 }
 
 impl Node for Program {
   fn token_literal(&self) -> String {
-    return if !self.statements.is_empty() { 
-      match self.statements.first() {
-        Some(StatementNode::Let(stmt) ) => stmt.token_literal(),
-        None   => String::from("")
-      }
-    } else {
-      String::from("")
-    };
+    match self.statements.first() {
+      Some(stmt) => stmt.token_literal(),
+      None => String::from(""),
+    }
   }
   fn print_string(&self) -> String {
     let mut out = String::from("");
@@ -65,16 +134,26 @@ impl Node for Program {
 
     out
   }
+  fn span(&self) -> Span {
+    match (self.statements.first(), self.statements.last()) {
+      (Some(first), Some(last)) => first.span().merge(last.span()),
+      _ => Span::default(),
+    }
+  }
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct LetStatement {
-  token: Token, 
+  pub token: Token,
   pub name: Identifier,
-  value: Option<ExpressionNode>,
+  pub value: ExpressionNode,
+  /// The `///` comments before the `let`, for documentation tooling.
+  pub doc: Option<String>,
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct Identifier {
-  token: Token,
+  pub token: Token,
   pub value: String
 }
 
@@ -84,20 +163,27 @@ impl Node for LetStatement {
   }
 
   fn print_string(&self) -> String {
-    let mut out = String::from("");
-    out.push_str(&self.token.literal);
-    out.push_str(" ");
-    out.push_str(self.name.print_string().as_str());
-    out.push_str(" = ");
-
-    if let Some(value) = &self.value {
-      out.push_str(value.print_string().as_str());
-    } 
+    let mut out = String::new();
 
-    out.push_str(";");
+    for line in self.doc.iter().flat_map(|doc| doc.lines()) {
+      out.push_str("///");
+      if !line.is_empty() {
+        out.push(' ');
+        out.push_str(line);
+      }
+      out.push('\n');
+    }
 
-    out
+    out + &format!(
+      "{} {} = {};",
+      self.token_literal(),
+      self.name.print_string(),
+      self.value.print_string()
+    )
+  }
 
+  fn span(&self) -> Span {
+    self.token.span.merge(self.value.span())
   }
 }
 
@@ -109,5 +195,536 @@ impl Node for Identifier {
   fn print_string(&self) -> String {
     self.value.clone()
   }
+
+  fn span(&self) -> Span {
+    self.token.span
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReturnStatement {
+  pub token: Token,
+  pub return_value: ExpressionNode,
+}
+
+impl Node for ReturnStatement {
+  fn token_literal(&self) -> String {
+    self.token.literal.clone()
+  }
+
+  fn print_string(&self) -> String {
+    format!("{} {};", self.token_literal(), self.return_value.print_string())
+  }
+
+  fn span(&self) -> Span {
+    self.token.span.merge(self.return_value.span())
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpressionStatement {
+  pub token: Token,
+  pub expression: ExpressionNode,
+}
+
+impl Node for ExpressionStatement {
+  fn token_literal(&self) -> String {
+    self.token.literal.clone()
+  }
+
+  fn print_string(&self) -> String {
+    format!("{};", self.expression.print_string())
+  }
+
+  fn span(&self) -> Span {
+    self.expression.span()
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockStatement {
+  pub token: Token,
+  pub statements: Vec<StatementNode>,
+  /// From the opening `{` through the closing `}`.
+  pub span: Span,
+}
+
+impl Node for BlockStatement {
+  fn token_literal(&self) -> String {
+    self.token.literal.clone()
+  }
+
+  fn print_string(&self) -> String {
+    let mut out = String::from("{");
+
+    for stmt in self.statements.as_slice() {
+      out.push(' ');
+      out.push_str(stmt.print_string().as_str());
+    }
+
+    out.push_str(" }");
+
+    out
+  }
+
+  fn span(&self) -> Span {
+    self.span
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IntegerLiteral {
+  pub token: Token,
+  pub value: i64,
+}
+
+impl Node for IntegerLiteral {
+  fn token_literal(&self) -> String {
+    self.token.literal.clone()
+  }
+
+  fn print_string(&self) -> String {
+    self.token.literal.clone()
+  }
+
+  fn span(&self) -> Span {
+    self.token.span
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FloatLiteral {
+  pub token: Token,
+  pub value: f64,
+}
+
+impl Node for FloatLiteral {
+  fn token_literal(&self) -> String {
+    self.token.literal.clone()
+  }
+
+  fn print_string(&self) -> String {
+    self.token.literal.clone()
+  }
+
+  fn span(&self) -> Span {
+    self.token.span
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StringLiteral {
+  pub token: Token,
+  pub value: String,
+}
+
+impl Node for StringLiteral {
+  fn token_literal(&self) -> String {
+    self.token.literal.clone()
+  }
+
+  /// Quotes the value again, escaping whatever the lexer would not read back verbatim.
+  fn print_string(&self) -> String {
+    let mut out = String::from("\"");
+
+    for ch in self.value.chars() {
+      match ch {
+        '"' => out.push_str("\\\""),
+        '\\' => out.push_str("\\\\"),
+        '\n' => out.push_str("\\n"),
+        '\t' => out.push_str("\\t"),
+        ch if ch.is_control() => out.push_str(&format!("\\u{{{:x}}}", ch as u32)),
+        ch => out.push(ch),
+      }
+    }
+
+    out.push('"');
+    out
+  }
+
+  fn span(&self) -> Span {
+    self.token.span
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Boolean {
+  pub token: Token,
+  pub value: bool,
+}
+
+impl Node for Boolean {
+  fn token_literal(&self) -> String {
+    self.token.literal.clone()
+  }
+
+  fn print_string(&self) -> String {
+    self.token.literal.clone()
+  }
+
+  fn span(&self) -> Span {
+    self.token.span
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrefixExpression {
+  pub token: Token,
+  pub operator: String,
+  pub right: Box<ExpressionNode>,
+}
+
+impl Node for PrefixExpression {
+  fn token_literal(&self) -> String {
+    self.token.literal.clone()
+  }
+
+  fn print_string(&self) -> String {
+    format!("({}{})", self.operator, self.right.print_string())
+  }
+
+  fn span(&self) -> Span {
+    self.token.span.merge(self.right.span())
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InfixExpression {
+  pub token: Token,
+  pub left: Box<ExpressionNode>,
+  pub operator: String,
+  pub right: Box<ExpressionNode>,
+}
+
+impl Node for InfixExpression {
+  fn token_literal(&self) -> String {
+    self.token.literal.clone()
+  }
+
+  fn print_string(&self) -> String {
+    format!(
+      "({} {} {})",
+      self.left.print_string(),
+      self.operator,
+      self.right.print_string()
+    )
+  }
+
+  fn span(&self) -> Span {
+    self.left.span().merge(self.right.span())
+  }
+}
+
+/// Compound assignment such as `x += 1`, which rebinds `name` to the result
+/// of applying the operator to its current value and `value`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssignExpression {
+  pub token: Token,
+  pub name: Identifier,
+  pub operator: String,
+  pub value: Box<ExpressionNode>,
+}
+
+impl AssignExpression {
+  /// The arithmetic operator applied before assigning, `+` for `+=`.
+  pub fn binary_operator(&self) -> &str {
+    self.operator.strip_suffix('=').unwrap_or(&self.operator)
+  }
+}
+
+impl Node for AssignExpression {
+  fn token_literal(&self) -> String {
+    self.token.literal.clone()
+  }
+
+  fn print_string(&self) -> String {
+    format!(
+      "({} {} {})",
+      self.name.print_string(),
+      self.operator,
+      self.value.print_string()
+    )
+  }
+
+  fn span(&self) -> Span {
+    self.name.span().merge(self.value.span())
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IfExpression {
+  pub token: Token,
+  pub condition: Box<ExpressionNode>,
+  pub consequence: BlockStatement,
+  pub alternative: Option<BlockStatement>,
+}
+
+impl Node for IfExpression {
+  fn token_literal(&self) -> String {
+    self.token.literal.clone()
+  }
+
+  fn print_string(&self) -> String {
+    let mut out = String::from("");
+    out.push_str("if (");
+    out.push_str(self.condition.print_string().as_str());
+    out.push_str(") ");
+    out.push_str(self.consequence.print_string().as_str());
+
+    if let Some(alternative) = &self.alternative {
+      out.push_str(" else ");
+      out.push_str(alternative.print_string().as_str());
+    }
+
+    out
+  }
+
+  fn span(&self) -> Span {
+    let span = self.token.span.merge(self.consequence.span());
+    match &self.alternative {
+      Some(alternative) => span.merge(alternative.span()),
+      None => span,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionLiteral {
+  pub token: Token,
+  pub parameters: Vec<Identifier>,
+  pub body: BlockStatement,
+  /// Set when the literal is the value of a `let`, so the function can refer to itself.
+  pub name: Option<String>,
+}
+
+impl Node for FunctionLiteral {
+  fn token_literal(&self) -> String {
+    self.token.literal.clone()
+  }
+
+  fn print_string(&self) -> String {
+    let params: Vec<String> = self
+      .parameters
+      .iter()
+      .map(|param| param.print_string())
+      .collect();
+
+    format!(
+      "{}({}) {}",
+      self.token_literal(),
+      params.join(", "),
+      self.body.print_string()
+    )
+  }
+
+  fn span(&self) -> Span {
+    self.token.span.merge(self.body.span())
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallExpression {
+  pub token: Token,
+  pub function: Box<ExpressionNode>,
+  pub arguments: Vec<ExpressionNode>,
+  /// From the start of the callee through the closing `)`.
+  pub span: Span,
+}
+
+impl Node for CallExpression {
+  fn token_literal(&self) -> String {
+    self.token.literal.clone()
+  }
+
+  fn print_string(&self) -> String {
+    let args: Vec<String> = self
+      .arguments
+      .iter()
+      .map(|arg| arg.print_string())
+      .collect();
+
+    format!("{}({})", self.function.print_string(), args.join(", "))
+  }
+
+  fn span(&self) -> Span {
+    self.span
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArrayLiteral {
+  pub token: Token,
+  pub elements: Vec<ExpressionNode>,
+  /// From the opening `[` through the closing `]`.
+  pub span: Span,
+}
+
+impl Node for ArrayLiteral {
+  fn token_literal(&self) -> String {
+    self.token.literal.clone()
+  }
+
+  fn print_string(&self) -> String {
+    let elements: Vec<String> = self
+      .elements
+      .iter()
+      .map(|element| element.print_string())
+      .collect();
+
+    format!("[{}]", elements.join(", "))
+  }
+
+  fn span(&self) -> Span {
+    self.span
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexExpression {
+  pub token: Token,
+  pub left: Box<ExpressionNode>,
+  pub index: Box<ExpressionNode>,
+  /// From the start of `left` through the closing `]`.
+  pub span: Span,
+}
+
+impl Node for IndexExpression {
+  fn token_literal(&self) -> String {
+    self.token.literal.clone()
+  }
+
+  fn print_string(&self) -> String {
+    format!("({}[{}])", self.left.print_string(), self.index.print_string())
+  }
+
+  fn span(&self) -> Span {
+    self.span
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HashLiteral {
+  pub token: Token,
+  /// Key and value expressions in source order.
+  pub pairs: Vec<(ExpressionNode, ExpressionNode)>,
+  /// From the opening `{` through the closing `}`.
+  pub span: Span,
+}
+
+impl Node for HashLiteral {
+  fn token_literal(&self) -> String {
+    self.token.literal.clone()
+  }
+
+  fn print_string(&self) -> String {
+    let pairs: Vec<String> = self
+      .pairs
+      .iter()
+      .map(|(key, value)| format!("{}: {}", key.print_string(), value.print_string()))
+      .collect();
+
+    format!("{{{}}}", pairs.join(", "))
+  }
+
+  fn span(&self) -> Span {
+    self.span
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use crate::{
+    lexer::Lexer,
+    parser::Parser,
+    token::{Token, TokenKind},
+  };
+
+  use super::{ExpressionNode, Identifier, LetStatement, Node, Program, StatementNode};
+
+  #[test]
+  fn test_print_string() {
+    let program = Program {
+      statements: vec![StatementNode::Let(LetStatement {
+        token: Token {
+          kind: TokenKind::Let,
+          literal: "let".to_string(),
+          ..Default::default()
+        },
+        name: Identifier {
+          token: Token {
+            kind: TokenKind::Ident,
+            literal: "myVar".to_string(),
+            ..Default::default()
+          },
+          value: "myVar".to_string(),
+        },
+        value: ExpressionNode::IdentifierNode(Identifier {
+          token: Token {
+            kind: TokenKind::Ident,
+            literal: "anotherVar".to_string(),
+            ..Default::default()
+          },
+          value: "anotherVar".to_string(),
+        }),
+        doc: None,
+      })],
+    };
+
+    assert_eq!(program.print_string(), "let myVar = anotherVar;");
+  }
+
+  #[test]
+  fn test_print_string_round_trips() {
+    let tests = vec![
+      ("let x = 5", "let x = 5;"),
+      ("return a + b * c", "return (a + (b * c));"),
+      ("-a * !b", "((-a) * (!b));"),
+      ("if (x < y) { x } else { y }", "if ((x < y)) { x; } else { y; };"),
+      ("if (ok) { return 1; }", "if (ok) { return 1; };"),
+      ("let add = fn(x, y) { x + y; };", "let add = fn(x, y) { (x + y); };"),
+      ("fn() { }", "fn() { };"),
+      ("[1, a * 2][0 + 1]", "([1, (a * 2)][(0 + 1)]);"),
+      (r#"{"a": 1, true: fn() { 2 }}"#, r#"{"a": 1, true: fn() { 2; }};"#),
+      ("{}", "{};"),
+      ("add(1, 2 * 3)(4)", "add(1, (2 * 3))(4);"),
+      (r#"let s = "say \"hi\"\n\u{7}";"#, r#"let s = "say \"hi\"\n\u{7}";"#),
+    ];
+
+    for (input, expected) in tests {
+      let printed = parse(input).print_string();
+      assert_eq!(printed, expected, "input {}", input);
+      assert_eq!(parse(&printed).print_string(), printed, "reparsing {}", printed);
+    }
+  }
+
+  #[test]
+  fn test_node_spans() {
+    let input = "let x = 1 + foo(2);\nif (x) { -x } else { y }";
+    let program = parse(input);
+
+    let spans: Vec<&str> = program
+      .statements
+      .iter()
+      .map(|stmt| {
+        let span = stmt.span();
+        &input[span.start..span.end]
+      })
+      .collect();
+    assert_eq!(spans, vec!["let x = 1 + foo(2)", "if (x) { -x } else { y }"]);
+
+    match &program.statements[1] {
+      StatementNode::Expression(stmt) => {
+        let span = stmt.span();
+        assert_eq!((span.line, span.column), (2, 1));
+      }
+      other => panic!("expected expression statement, got {:?}", other),
+    }
+    assert_eq!(program.span().start, 0);
+  }
+
+  fn parse(input: &str) -> Program {
+    let (program, errors) = Parser::new(Lexer::new(input)).parse_program();
+    assert!(errors.is_empty(), "parser errors: {:?}", errors);
+    program
+  }
 }
-    
\ No newline at end of file
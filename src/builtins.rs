@@ -0,0 +1,117 @@
+use std::rc::Rc;
+
+use crate::object::{Builtin, BuiltinFunction, Object, RuntimeError};
+
+/// Functions available in every program. Lookups fall back to this list
+/// after the environment, so user bindings can shadow them.
+pub const BUILTINS: &[(&str, BuiltinFunction)] = &[
+    ("len", len),
+    ("first", first),
+    ("last", last),
+    ("rest", rest),
+    ("push", push),
+];
+
+pub fn lookup(name: &str) -> Option<Object> {
+    BUILTINS
+        .iter()
+        .find(|(builtin_name, _)| *builtin_name == name)
+        .map(|(name, func)| Object::Builtin(Builtin::new(name, *func)))
+}
+
+fn new_error(message: String) -> Object {
+    Object::Error(RuntimeError::new(message))
+}
+
+pub(crate) fn check_arity(name: &str, args: &[Object], want: usize) -> Option<Object> {
+    if args.len() != want {
+        return Some(new_error(format!(
+            "wrong number of arguments to `{}`: want={}, got={}",
+            name,
+            want,
+            args.len()
+        )));
+    }
+    None
+}
+
+/// Number of elements in an array, or of characters in a string.
+fn len(args: Vec<Object>) -> Object {
+    if let Some(error) = check_arity("len", &args, 1) {
+        return error;
+    }
+
+    match &args[0] {
+        Object::String(value) => Object::Integer(value.chars().count() as i64),
+        Object::Array(elements) => Object::Integer(elements.len() as i64),
+        other => new_error(format!(
+            "argument to `len` not supported, got {}",
+            other.type_name()
+        )),
+    }
+}
+
+/// First element of an array, or null when it is empty.
+fn first(args: Vec<Object>) -> Object {
+    if let Some(error) = check_arity("first", &args, 1) {
+        return error;
+    }
+
+    match &args[0] {
+        Object::Array(elements) => elements.first().cloned().unwrap_or(Object::Null),
+        other => new_error(format!(
+            "argument to `first` must be ARRAY, got {}",
+            other.type_name()
+        )),
+    }
+}
+
+/// Last element of an array, or null when it is empty.
+fn last(args: Vec<Object>) -> Object {
+    if let Some(error) = check_arity("last", &args, 1) {
+        return error;
+    }
+
+    match &args[0] {
+        Object::Array(elements) => elements.last().cloned().unwrap_or(Object::Null),
+        other => new_error(format!(
+            "argument to `last` must be ARRAY, got {}",
+            other.type_name()
+        )),
+    }
+}
+
+/// A new array without the first element, or null when it is empty.
+fn rest(args: Vec<Object>) -> Object {
+    if let Some(error) = check_arity("rest", &args, 1) {
+        return error;
+    }
+
+    match &args[0] {
+        Object::Array(elements) if elements.is_empty() => Object::Null,
+        Object::Array(elements) => Object::Array(Rc::new(elements[1..].to_vec())),
+        other => new_error(format!(
+            "argument to `rest` must be ARRAY, got {}",
+            other.type_name()
+        )),
+    }
+}
+
+/// A new array with the value appended. The original array is left untouched.
+fn push(args: Vec<Object>) -> Object {
+    if let Some(error) = check_arity("push", &args, 2) {
+        return error;
+    }
+
+    match &args[0] {
+        Object::Array(elements) => {
+            let mut elements = elements.to_vec();
+            elements.push(args[1].clone());
+            Object::Array(Rc::new(elements))
+        }
+        other => new_error(format!(
+            "argument to `push` must be ARRAY, got {}",
+            other.type_name()
+        )),
+    }
+}
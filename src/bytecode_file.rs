@@ -0,0 +1,845 @@
+use std::{fmt, rc::Rc};
+
+use crate::{
+    builtins::BUILTINS,
+    code::{read_operands, Opcode, SourceMap},
+    compiler::Bytecode,
+    object::{CompiledFunction, Object},
+    token::Span,
+};
+
+pub const MAGIC: &[u8; 4] = b"MNKB";
+/// Bumped whenever the layout or the instruction set changes.
+pub const VERSION: u16 = 4;
+
+const FLAG_DEBUG_INFO: u8 = 1;
+
+const TAG_INTEGER: u8 = 0;
+const TAG_BOOLEAN: u8 = 1;
+const TAG_STRING: u8 = 2;
+const TAG_NULL: u8 = 3;
+const TAG_FUNCTION: u8 = 4;
+const TAG_FLOAT: u8 = 5;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WriteError {
+    /// Only values the compiler puts in the constant pool can be written.
+    UnsupportedConstant {
+        type_name: &'static str,
+    },
+    TooLarge {
+        what: &'static str,
+    },
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteError::UnsupportedConstant { type_name } => {
+                write!(
+                    f,
+                    "cannot write a {} constant to a bytecode file",
+                    type_name
+                )
+            }
+            WriteError::TooLarge { what } => write!(f, "{} too large for a bytecode file", what),
+        }
+    }
+}
+
+/// Why a bytecode file was rejected. Offsets are in bytes from the start
+/// of the file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadError {
+    BadMagic,
+    UnsupportedVersion { found: u16 },
+    Truncated { offset: usize },
+    InvalidConstantTag { tag: u8, offset: usize },
+    InvalidString { offset: usize },
+    InvalidInstruction { offset: usize, message: String },
+    TrailingBytes { offset: usize },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::BadMagic => write!(f, "not a bytecode file"),
+            LoadError::UnsupportedVersion { found } => write!(
+                f,
+                "unsupported bytecode version {}, expected {}",
+                found, VERSION
+            ),
+            LoadError::Truncated { offset } => {
+                write!(f, "bytecode file is truncated at byte {}", offset)
+            }
+            LoadError::InvalidConstantTag { tag, offset } => {
+                write!(f, "unknown constant tag {} at byte {}", tag, offset)
+            }
+            LoadError::InvalidString { offset } => {
+                write!(f, "string constant at byte {} is not valid UTF-8", offset)
+            }
+            LoadError::InvalidInstruction { offset, message } => {
+                write!(f, "invalid instruction at byte {}: {}", offset, message)
+            }
+            LoadError::TrailingBytes { offset } => {
+                write!(
+                    f,
+                    "unexpected data after the end of the bytecode at byte {}",
+                    offset
+                )
+            }
+        }
+    }
+}
+
+/// Encodes bytecode as a file. All integers are big-endian:
+///
+/// ```text
+/// magic "MNKB", version u16, flags u8 (bit 0: debug info)
+/// constants: count u32, then per constant a tag u8 and its value
+/// instructions: length u32, bytes
+/// source map, if debug info is on
+/// ```
+///
+/// Compiled functions store their locals and parameters as u16, an optional
+/// name, their instructions and, with debug info, their own source map. A
+/// source map is a u32 count of `(offset, start, end, line, column)` u32s.
+pub fn write(bytecode: &Bytecode, debug_info: bool) -> Result<Vec<u8>, WriteError> {
+    let mut writer = Writer {
+        out: Vec::new(),
+        debug_info,
+    };
+
+    writer.out.extend_from_slice(MAGIC);
+    writer.out.extend_from_slice(&VERSION.to_be_bytes());
+    writer
+        .out
+        .push(if debug_info { FLAG_DEBUG_INFO } else { 0 });
+
+    writer.len(bytecode.constants.len(), "constant pool")?;
+    for constant in &bytecode.constants {
+        writer.constant(constant)?;
+    }
+
+    writer.bytes(&bytecode.instructions, "instructions")?;
+    writer.source_map(&bytecode.spans)?;
+
+    Ok(writer.out)
+}
+
+struct Writer {
+    out: Vec<u8>,
+    debug_info: bool,
+}
+
+impl Writer {
+    fn constant(&mut self, constant: &Object) -> Result<(), WriteError> {
+        match constant {
+            Object::Integer(value) => {
+                self.out.push(TAG_INTEGER);
+                self.out.extend_from_slice(&value.to_be_bytes());
+            }
+            Object::Float(value) => {
+                self.out.push(TAG_FLOAT);
+                self.out.extend_from_slice(&value.to_bits().to_be_bytes());
+            }
+            Object::Boolean(value) => {
+                self.out.push(TAG_BOOLEAN);
+                self.out.push(*value as u8);
+            }
+            Object::String(value) => {
+                self.out.push(TAG_STRING);
+                self.bytes(value.as_bytes(), "string")?;
+            }
+            Object::Null => self.out.push(TAG_NULL),
+            Object::CompiledFunction(function) => {
+                self.out.push(TAG_FUNCTION);
+                self.u16(function.num_locals, "locals")?;
+                self.u16(function.num_parameters, "parameters")?;
+                match &function.name {
+                    Some(name) => {
+                        self.out.push(1);
+                        self.bytes(name.as_bytes(), "function name")?;
+                    }
+                    None => self.out.push(0),
+                }
+                self.bytes(&function.instructions, "instructions")?;
+                self.source_map(&function.spans)?;
+            }
+            other => {
+                return Err(WriteError::UnsupportedConstant {
+                    type_name: other.type_name(),
+                })
+            }
+        }
+        Ok(())
+    }
+
+    fn source_map(&mut self, map: &SourceMap) -> Result<(), WriteError> {
+        if !self.debug_info {
+            return Ok(());
+        }
+
+        self.len(map.len(), "source map")?;
+        for (offset, span) in map {
+            for value in [*offset, span.start, span.end, span.line, span.column] {
+                self.len(value, "source position")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn bytes(&mut self, bytes: &[u8], what: &'static str) -> Result<(), WriteError> {
+        self.len(bytes.len(), what)?;
+        self.out.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn len(&mut self, value: usize, what: &'static str) -> Result<(), WriteError> {
+        let value = u32::try_from(value).map_err(|_| WriteError::TooLarge { what })?;
+        self.out.extend_from_slice(&value.to_be_bytes());
+        Ok(())
+    }
+
+    fn u16(&mut self, value: usize, what: &'static str) -> Result<(), WriteError> {
+        let value = u16::try_from(value).map_err(|_| WriteError::TooLarge { what })?;
+        self.out.extend_from_slice(&value.to_be_bytes());
+        Ok(())
+    }
+}
+
+/// Decodes a file produced by `write`. Besides the layout, the loader checks
+/// every instruction so that running the result can fail with a runtime
+/// error but never crash the VM: see `validate`.
+pub fn load(bytes: &[u8]) -> Result<Bytecode, LoadError> {
+    let mut reader = Reader { bytes, offset: 0 };
+
+    if reader.take(MAGIC.len()).map_err(|_| LoadError::BadMagic)? != MAGIC {
+        return Err(LoadError::BadMagic);
+    }
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Err(LoadError::UnsupportedVersion { found: version });
+    }
+    let debug_info = reader.u8()? & FLAG_DEBUG_INFO != 0;
+
+    let count = reader.u32()?;
+    let mut constants = Vec::new();
+    for _ in 0..count {
+        constants.push(reader.constant(debug_info)?);
+    }
+
+    let len = reader.u32()?;
+    let instructions_offset = reader.offset;
+    let instructions = reader.take(len)?.to_vec();
+    let spans = reader.source_map(debug_info)?;
+
+    if reader.offset != bytes.len() {
+        return Err(LoadError::TrailingBytes {
+            offset: reader.offset,
+        });
+    }
+
+    // Function bodies are checked once the whole constant pool is known.
+    validate(&instructions, instructions_offset, &constants)?;
+
+    Ok(Bytecode {
+        instructions,
+        constants,
+        spans,
+    })
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn constant(&mut self, debug_info: bool) -> Result<Object, LoadError> {
+        let tag_offset = self.offset;
+
+        let constant = match self.u8()? {
+            TAG_INTEGER => Object::Integer(i64::from_be_bytes(self.array()?)),
+            TAG_FLOAT => Object::Float(f64::from_bits(u64::from_be_bytes(self.array()?))),
+            TAG_BOOLEAN => Object::Boolean(self.u8()? != 0),
+            TAG_STRING => Object::String(self.string()?),
+            TAG_NULL => Object::Null,
+            TAG_FUNCTION => {
+                let num_locals = self.u16()? as usize;
+                let num_parameters = self.u16()? as usize;
+                let name = match self.u8()? {
+                    0 => None,
+                    _ => Some(self.string()?),
+                };
+                let instructions = self.bytes()?.to_vec();
+                let spans = self.source_map(debug_info)?;
+
+                Object::CompiledFunction(Rc::new(CompiledFunction {
+                    instructions,
+                    num_locals,
+                    num_parameters,
+                    name,
+                    spans,
+                }))
+            }
+            tag => {
+                return Err(LoadError::InvalidConstantTag {
+                    tag,
+                    offset: tag_offset,
+                })
+            }
+        };
+
+        Ok(constant)
+    }
+
+    fn source_map(&mut self, debug_info: bool) -> Result<SourceMap, LoadError> {
+        if !debug_info {
+            return Ok(SourceMap::new());
+        }
+
+        let count = self.u32()?;
+        let mut map = SourceMap::new();
+        for _ in 0..count {
+            let offset = self.u32()?;
+            let span = Span::new(self.u32()?, self.u32()?, self.u32()?, self.u32()?);
+            map.push((offset, span));
+        }
+        Ok(map)
+    }
+
+    fn string(&mut self) -> Result<String, LoadError> {
+        let offset = self.offset;
+        let bytes = self.bytes()?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| LoadError::InvalidString { offset })
+    }
+
+    /// A u32 length followed by that many bytes.
+    fn bytes(&mut self) -> Result<&'a [u8], LoadError> {
+        let len = self.u32()?;
+        self.take(len)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], LoadError> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(LoadError::Truncated {
+                offset: self.bytes.len(),
+            })?;
+
+        let taken = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], LoadError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, LoadError> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<usize, LoadError> {
+        Ok(u32::from_be_bytes(self.array()?) as usize)
+    }
+}
+
+/// An instruction of a body being validated. `offset` is where it starts
+/// in the body.
+struct Decoded {
+    offset: usize,
+    op: Opcode,
+    operands: Vec<usize>,
+}
+
+/// What the instructions of one body may refer to besides the constants.
+struct Limits {
+    num_locals: usize,
+    /// Fewest free variables any closure made from the body captures.
+    num_free: usize,
+    /// Function bodies must return; the top level may run off its end.
+    is_function: bool,
+}
+
+/// Checks every instruction of the top level and of each function so the VM
+/// can run them without further checks: operands must index existing
+/// constants, builtins, locals and free variables, jumps must land on an
+/// instruction, and no path may pop more values than were pushed.
+fn validate(
+    instructions: &[u8],
+    instructions_offset: usize,
+    constants: &[Object],
+) -> Result<(), LoadError> {
+    let main = decode(instructions, instructions_offset, constants)?;
+    let mut functions = vec![];
+    for (idx, constant) in constants.iter().enumerate() {
+        if let Object::CompiledFunction(function) = constant {
+            let body = decode(&function.instructions, 0, constants)?;
+            functions.push((idx, function, body));
+        }
+    }
+
+    // A function that is never turned into a closure never runs, so it has no limit.
+    let mut num_free = vec![usize::MAX; constants.len()];
+    let bodies = std::iter::once(&main).chain(functions.iter().map(|(_, _, body)| body));
+    for instruction in bodies.flatten() {
+        if let (Opcode::Closure, [idx, free]) = (instruction.op, &instruction.operands[..]) {
+            num_free[*idx] = num_free[*idx].min(*free);
+        }
+    }
+
+    let limits = Limits {
+        num_locals: 0,
+        num_free: 0,
+        is_function: false,
+    };
+    check_flow(&main, instructions.len(), instructions_offset, &limits)?;
+
+    for (idx, function, body) in &functions {
+        if function.num_parameters > function.num_locals {
+            return Err(LoadError::InvalidInstruction {
+                offset: 0,
+                message: format!("function constant {} has more parameters than locals", idx),
+            });
+        }
+        let limits = Limits {
+            num_locals: function.num_locals,
+            num_free: num_free[*idx],
+            is_function: true,
+        };
+        check_flow(body, function.instructions.len(), 0, &limits)?;
+    }
+
+    Ok(())
+}
+
+/// Splits `instructions` into instructions, checking that opcodes exist,
+/// operands are complete and constant and builtin indexes are in range.
+/// `base` is where they start in the file, used to report top-level
+/// offsets; function bodies report offsets within the body.
+fn decode(
+    instructions: &[u8],
+    base: usize,
+    constants: &[Object],
+) -> Result<Vec<Decoded>, LoadError> {
+    let mut decoded = vec![];
+    let mut offset = 0;
+
+    while offset < instructions.len() {
+        let invalid = |message: String| LoadError::InvalidInstruction {
+            offset: base + offset,
+            message,
+        };
+
+        let byte = instructions[offset];
+        let op = Opcode::from_byte(byte)
+            .ok_or_else(|| invalid(format!("unknown opcode {:#04x}", byte)))?;
+        let definition = op.definition();
+
+        let width: usize = definition.operand_widths.iter().sum();
+        if offset + 1 + width > instructions.len() {
+            return Err(invalid(format!("{} is missing operands", definition.name)));
+        }
+        let (operands, read) = read_operands(&definition, &instructions[offset + 1..]);
+
+        let operand = operands.first().copied().unwrap_or_default();
+        let problem = match op {
+            Opcode::Constant if operand >= constants.len() => {
+                Some(format!("constant {} does not exist", operand))
+            }
+            Opcode::Closure
+                if !matches!(constants.get(operand), Some(Object::CompiledFunction(_))) =>
+            {
+                Some(format!("constant {} is not a function", operand))
+            }
+            Opcode::GetBuiltin if operand >= BUILTINS.len() => {
+                Some(format!("builtin {} does not exist", operand))
+            }
+            Opcode::Hash if operand % 2 != 0 => Some(format!(
+                "OpHash needs keys and values, got {} values",
+                operand
+            )),
+            _ => None,
+        };
+        if let Some(message) = problem {
+            return Err(invalid(message));
+        }
+
+        decoded.push(Decoded {
+            offset,
+            op,
+            operands,
+        });
+        offset += 1 + read;
+    }
+
+    Ok(decoded)
+}
+
+/// Follows every path through a body, tracking how many values it leaves
+/// on the stack. Where paths meet, they must agree.
+fn check_flow(body: &[Decoded], len: usize, base: usize, limits: &Limits) -> Result<(), LoadError> {
+    let mut depths: Vec<Option<usize>> = vec![None; body.len()];
+    let mut pending = vec![(0, 0)];
+
+    while let Some((idx, depth)) = pending.pop() {
+        let Some(instruction) = body.get(idx) else {
+            if limits.is_function {
+                return Err(LoadError::InvalidInstruction {
+                    offset: base + len,
+                    message: String::from("function can end without returning"),
+                });
+            }
+            continue;
+        };
+        let invalid = |message: String| LoadError::InvalidInstruction {
+            offset: base + instruction.offset,
+            message,
+        };
+
+        match depths[idx] {
+            Some(known) if known == depth => continue,
+            Some(known) => {
+                return Err(invalid(format!(
+                    "paths reach this instruction with {} and {} values on the stack",
+                    known, depth
+                )))
+            }
+            None => depths[idx] = Some(depth),
+        }
+
+        let operand = instruction.operands.first().copied().unwrap_or_default();
+        let name = instruction.op.definition().name;
+        let (pops, pushes) = match instruction.op {
+            Opcode::GetLocal | Opcode::SetLocal | Opcode::CaptureLocal
+                if operand >= limits.num_locals =>
+            {
+                return Err(invalid(format!("local {} does not exist", operand)))
+            }
+            Opcode::GetFree | Opcode::SetFree | Opcode::CaptureFree
+                if operand >= limits.num_free =>
+            {
+                return Err(invalid(format!("free variable {} does not exist", operand)))
+            }
+            Opcode::Constant
+            | Opcode::True
+            | Opcode::False
+            | Opcode::Null
+            | Opcode::GetGlobal
+            | Opcode::GetLocal
+            | Opcode::GetBuiltin
+            | Opcode::GetFree
+            | Opcode::CurrentClosure
+            | Opcode::CaptureLocal
+            | Opcode::CaptureFree => (0, 1),
+            Opcode::Pop
+            | Opcode::SetGlobal
+            | Opcode::SetLocal
+            | Opcode::SetFree
+            | Opcode::JumpNotTruthy
+            | Opcode::ReturnValue => (1, 0),
+            Opcode::Add
+            | Opcode::Sub
+            | Opcode::Mul
+            | Opcode::Div
+            | Opcode::Mod
+            | Opcode::Pow
+            | Opcode::Equal
+            | Opcode::NotEqual
+            | Opcode::GreaterThan
+            | Opcode::LessThan
+            | Opcode::GreaterEqual
+            | Opcode::LessEqual
+            | Opcode::Index => (2, 1),
+            Opcode::Minus | Opcode::Bang => (1, 1),
+            Opcode::Jump | Opcode::Return => (0, 0),
+            Opcode::Array | Opcode::Hash => (operand, 1),
+            // The callee sits below its arguments.
+            Opcode::Call => (operand + 1, 1),
+            Opcode::Closure => (instruction.operands[1], 1),
+        };
+        if depth < pops {
+            return Err(invalid(format!(
+                "{} needs {} values on the stack, found {}",
+                name, pops, depth
+            )));
+        }
+        let depth = depth - pops + pushes;
+
+        let target = || {
+            if operand == len {
+                return Ok(body.len());
+            }
+            body.binary_search_by_key(&operand, |instruction| instruction.offset)
+                .map_err(|_| invalid(format!("jump target {} is not an instruction", operand)))
+        };
+        match instruction.op {
+            Opcode::Jump => pending.push((target()?, depth)),
+            Opcode::JumpNotTruthy => {
+                pending.push((target()?, depth));
+                pending.push((idx + 1, depth));
+            }
+            Opcode::ReturnValue | Opcode::Return => {}
+            _ => pending.push((idx + 1, depth)),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use crate::{
+        code::{make, Opcode},
+        compiler::Compiler,
+        lexer::Lexer,
+        object::{CompiledFunction, Object},
+        parser::Parser,
+        vm::VM,
+    };
+
+    use super::{load, write, LoadError, VERSION};
+
+    fn compile(input: &str) -> crate::compiler::Bytecode {
+        let (program, errors) = Parser::new(Lexer::new(input)).parse_program();
+        assert!(errors.is_empty(), "parser errors: {:?}", errors);
+
+        let mut compiler = Compiler::new();
+        compiler.compile_program(&program).unwrap();
+        compiler.bytecode()
+    }
+
+    const PROGRAM: &str = r#"
+        let greeting = fn(name) { "hello " + name };
+        let adder = fn(a) { fn(b) { a + b } };
+        if (adder(1)(2.5) > 2) { greeting("world") } else { false }
+    "#;
+
+    #[test]
+    fn test_round_trip() {
+        let bytecode = compile(PROGRAM);
+
+        let loaded = load(&write(&bytecode, true).unwrap()).unwrap();
+        assert_eq!(loaded, bytecode);
+
+        let mut vm = VM::new(loaded);
+        vm.run().unwrap();
+        assert_eq!(
+            vm.last_popped_stack_elem(),
+            Object::String("hello world".to_string())
+        );
+    }
+
+    #[test]
+    fn test_strip_debug_info() {
+        let bytecode = compile(PROGRAM);
+        let with_debug_info = write(&bytecode, true).unwrap();
+        let stripped = write(&bytecode, false).unwrap();
+        assert!(stripped.len() < with_debug_info.len());
+
+        let loaded = load(&stripped).unwrap();
+        assert_eq!(loaded.instructions, bytecode.instructions);
+        assert!(loaded.spans.is_empty());
+        for constant in &loaded.constants {
+            if let Object::CompiledFunction(function) = constant {
+                assert!(function.spans.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let bytes = write(&compile(PROGRAM), true).unwrap();
+
+        assert_eq!(load(b"MNK"), Err(LoadError::BadMagic));
+        assert_eq!(load(b"#!/bin/monkey\n"), Err(LoadError::BadMagic));
+
+        let mut wrong_version = bytes.clone();
+        wrong_version[4..6].copy_from_slice(&(VERSION + 1).to_be_bytes());
+        assert_eq!(
+            load(&wrong_version),
+            Err(LoadError::UnsupportedVersion { found: VERSION + 1 })
+        );
+
+        // Cutting the file anywhere must be caught rather than panic.
+        for len in 4..bytes.len() {
+            assert!(
+                matches!(load(&bytes[..len]), Err(LoadError::Truncated { .. })),
+                "truncated to {} bytes",
+                len
+            );
+        }
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            load(&trailing),
+            Err(LoadError::TrailingBytes {
+                offset: bytes.len()
+            })
+        );
+    }
+
+    #[test]
+    fn test_load_validates_instructions() {
+        let mut bytecode = compile("1");
+        bytecode.instructions = vec![0xff];
+        let error = load(&write(&bytecode, false).unwrap()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid instruction at byte 24: unknown opcode 0xff"
+        );
+
+        let mut bytecode = compile("1");
+        bytecode.constants.clear();
+        let error = load(&write(&bytecode, false).unwrap()).unwrap_err();
+        assert!(
+            error.to_string().ends_with("constant 0 does not exist"),
+            "{}",
+            error
+        );
+
+        let mut bytecode = compile("1");
+        bytecode.instructions.truncate(2);
+        let error = load(&write(&bytecode, false).unwrap()).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .ends_with("OpConstant is missing operands"),
+            "{}",
+            error
+        );
+    }
+
+    fn load_error(bytecode: &crate::compiler::Bytecode) -> String {
+        match load(&write(bytecode, false).unwrap()) {
+            Ok(_) => panic!("expected {:?} to be rejected", bytecode.instructions),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_load_rejects_programs_that_would_crash_the_vm() {
+        let tests: Vec<(Vec<Vec<u8>>, &str)> = vec![
+            (
+                vec![make(Opcode::Pop, &[])],
+                "byte 24: OpPop needs 1 values on the stack, found 0",
+            ),
+            (
+                vec![make(Opcode::True, &[]), make(Opcode::Add, &[])],
+                "byte 25: OpAdd needs 2 values on the stack, found 1",
+            ),
+            (
+                vec![make(Opcode::Array, &[2])],
+                "OpArray needs 2 values on the stack, found 0",
+            ),
+            (
+                vec![make(Opcode::Hash, &[1])],
+                "OpHash needs keys and values, got 1 values",
+            ),
+            (vec![make(Opcode::GetLocal, &[0])], "local 0 does not exist"),
+            (
+                vec![make(Opcode::GetFree, &[0])],
+                "free variable 0 does not exist",
+            ),
+            (
+                vec![make(Opcode::Jump, &[2])],
+                "jump target 2 is not an instruction",
+            ),
+            (
+                vec![
+                    make(Opcode::True, &[]),
+                    make(Opcode::JumpNotTruthy, &[6]),
+                    make(Opcode::True, &[]),
+                    make(Opcode::True, &[]),
+                    make(Opcode::Pop, &[]),
+                ],
+                "paths reach this instruction with 2 and 0 values on the stack",
+            ),
+        ];
+
+        for (instructions, expected) in tests {
+            let mut bytecode = compile("1");
+            bytecode.instructions = instructions.concat();
+            let error = load_error(&bytecode);
+            assert!(error.ends_with(expected), "{}", error);
+        }
+
+        let function = |instructions: Vec<Vec<u8>>, num_locals| {
+            Object::CompiledFunction(Rc::new(CompiledFunction {
+                instructions: instructions.concat(),
+                num_locals,
+                ..Default::default()
+            }))
+        };
+        let tests = vec![
+            (
+                function(vec![make(Opcode::Null, &[])], 0),
+                0,
+                "function can end without returning",
+            ),
+            (
+                function(
+                    vec![make(Opcode::GetLocal, &[1]), make(Opcode::ReturnValue, &[])],
+                    1,
+                ),
+                0,
+                "local 1 does not exist",
+            ),
+            (
+                function(
+                    vec![make(Opcode::GetFree, &[1]), make(Opcode::ReturnValue, &[])],
+                    0,
+                ),
+                1,
+                "free variable 1 does not exist",
+            ),
+        ];
+        for (function, num_free, expected) in tests {
+            let mut bytecode = compile("1");
+            bytecode.constants = vec![function];
+            bytecode.instructions = [
+                make(Opcode::GetBuiltin, &[0]),
+                make(Opcode::Closure, &[0, 0]),
+                make(Opcode::Closure, &[0, num_free]),
+            ]
+            .concat()[..]
+                .to_vec();
+            let error = load_error(&bytecode);
+            assert!(error.ends_with(expected), "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_load_accepts_compiled_programs() {
+        let programs = [
+            "let x = 1; let x = x + 1; if (x > 1) { x } else { return 0; }",
+            "let f = fn(a, b) { if (a) { return b; } let c = a || b && !a; c }; f(1, 2)",
+            "let adder = fn(a) { fn(b) { a + b } }; [adder(1)(2), {1: 2}[1], len(\"ab\")]",
+            "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(10)",
+            "let x = 1; x += 2; let f = fn(y) { y *= x; y }; f(x)",
+            "let f = fn(a) { let g = fn() { fn() { a -= 1 } }; g()(); a }; f(1)",
+        ];
+        for program in programs {
+            let bytecode = compile(program);
+            assert_eq!(
+                load(&write(&bytecode, true).unwrap()).as_ref(),
+                Ok(&bytecode),
+                "program {}",
+                program
+            );
+        }
+    }
+}
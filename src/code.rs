@@ -0,0 +1,289 @@
+use crate::token::Span;
+
+pub type Instructions = Vec<u8>;
+
+/// Debug info for a block of instructions: `(offset, span)` pairs sorted by
+/// offset, each covering the instructions up to the next entry.
+pub type SourceMap = Vec<(usize, Span)>;
+
+/// The span of the source that produced the instruction at `offset`.
+pub fn span_at(map: &SourceMap, offset: usize) -> Option<Span> {
+    let idx = map.partition_point(|(start, _)| *start <= offset);
+    idx.checked_sub(1).map(|idx| map[idx].1)
+}
+
+/// A single VM instruction. Operands follow the opcode byte in big-endian
+/// order, with the widths given by `Opcode::definition`.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Constant,
+    Pop,
+
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+
+    True,
+    False,
+    Null,
+
+    Equal,
+    NotEqual,
+    GreaterThan,
+    LessThan,
+    GreaterEqual,
+    LessEqual,
+
+    Minus,
+    Bang,
+
+    JumpNotTruthy,
+    Jump,
+
+    GetGlobal,
+    SetGlobal,
+    GetLocal,
+    SetLocal,
+    GetBuiltin,
+    GetFree,
+    SetFree,
+    CurrentClosure,
+    CaptureLocal,
+    CaptureFree,
+
+    Array,
+    Hash,
+    Index,
+
+    Call,
+    ReturnValue,
+    Return,
+    Closure,
+}
+
+pub struct Definition {
+    pub name: &'static str,
+    pub operand_widths: &'static [usize],
+}
+
+const OPCODES: &[Opcode] = &[
+    Opcode::Constant,
+    Opcode::Pop,
+    Opcode::Add,
+    Opcode::Sub,
+    Opcode::Mul,
+    Opcode::Div,
+    Opcode::Mod,
+    Opcode::Pow,
+    Opcode::True,
+    Opcode::False,
+    Opcode::Null,
+    Opcode::Equal,
+    Opcode::NotEqual,
+    Opcode::GreaterThan,
+    Opcode::LessThan,
+    Opcode::GreaterEqual,
+    Opcode::LessEqual,
+    Opcode::Minus,
+    Opcode::Bang,
+    Opcode::JumpNotTruthy,
+    Opcode::Jump,
+    Opcode::GetGlobal,
+    Opcode::SetGlobal,
+    Opcode::GetLocal,
+    Opcode::SetLocal,
+    Opcode::GetBuiltin,
+    Opcode::GetFree,
+    Opcode::SetFree,
+    Opcode::CurrentClosure,
+    Opcode::CaptureLocal,
+    Opcode::CaptureFree,
+    Opcode::Array,
+    Opcode::Hash,
+    Opcode::Index,
+    Opcode::Call,
+    Opcode::ReturnValue,
+    Opcode::Return,
+    Opcode::Closure,
+];
+
+impl Opcode {
+    pub fn from_byte(byte: u8) -> Option<Opcode> {
+        OPCODES.get(byte as usize).copied()
+    }
+
+    pub fn definition(&self) -> Definition {
+        let (name, operand_widths): (&'static str, &'static [usize]) = match self {
+            Opcode::Constant => ("OpConstant", &[2]),
+            Opcode::Pop => ("OpPop", &[]),
+            Opcode::Add => ("OpAdd", &[]),
+            Opcode::Sub => ("OpSub", &[]),
+            Opcode::Mul => ("OpMul", &[]),
+            Opcode::Div => ("OpDiv", &[]),
+            Opcode::Mod => ("OpMod", &[]),
+            Opcode::Pow => ("OpPow", &[]),
+            Opcode::True => ("OpTrue", &[]),
+            Opcode::False => ("OpFalse", &[]),
+            Opcode::Null => ("OpNull", &[]),
+            Opcode::Equal => ("OpEqual", &[]),
+            Opcode::NotEqual => ("OpNotEqual", &[]),
+            Opcode::GreaterThan => ("OpGreaterThan", &[]),
+            Opcode::LessThan => ("OpLessThan", &[]),
+            Opcode::GreaterEqual => ("OpGreaterEqual", &[]),
+            Opcode::LessEqual => ("OpLessEqual", &[]),
+            Opcode::Minus => ("OpMinus", &[]),
+            Opcode::Bang => ("OpBang", &[]),
+            // Jump targets are absolute offsets into the current instructions.
+            Opcode::JumpNotTruthy => ("OpJumpNotTruthy", &[2]),
+            Opcode::Jump => ("OpJump", &[2]),
+            Opcode::GetGlobal => ("OpGetGlobal", &[2]),
+            Opcode::SetGlobal => ("OpSetGlobal", &[2]),
+            Opcode::GetLocal => ("OpGetLocal", &[1]),
+            Opcode::SetLocal => ("OpSetLocal", &[1]),
+            Opcode::GetBuiltin => ("OpGetBuiltin", &[1]),
+            Opcode::GetFree => ("OpGetFree", &[1]),
+            Opcode::SetFree => ("OpSetFree", &[1]),
+            Opcode::CurrentClosure => ("OpCurrentClosure", &[]),
+            // Push the cell holding a local or free variable, for `OpClosure`
+            // to capture, instead of its value.
+            Opcode::CaptureLocal => ("OpCaptureLocal", &[1]),
+            Opcode::CaptureFree => ("OpCaptureFree", &[1]),
+            // Number of elements, and of keys plus values, taken off the stack.
+            Opcode::Array => ("OpArray", &[2]),
+            Opcode::Hash => ("OpHash", &[2]),
+            Opcode::Index => ("OpIndex", &[]),
+            // Number of arguments.
+            Opcode::Call => ("OpCall", &[1]),
+            Opcode::ReturnValue => ("OpReturnValue", &[]),
+            Opcode::Return => ("OpReturn", &[]),
+            // Constant index of the function and number of free variables.
+            Opcode::Closure => ("OpClosure", &[2, 1]),
+        };
+
+        Definition {
+            name,
+            operand_widths,
+        }
+    }
+}
+
+/// Encodes an instruction. Operands wider than their slot are truncated, so
+/// callers check limits such as the constant pool size beforehand.
+pub fn make(op: Opcode, operands: &[usize]) -> Instructions {
+    let definition = op.definition();
+    let len = 1 + definition.operand_widths.iter().sum::<usize>();
+
+    let mut instruction = Vec::with_capacity(len);
+    instruction.push(op as u8);
+
+    for (operand, width) in operands.iter().zip(definition.operand_widths) {
+        match width {
+            2 => instruction.extend_from_slice(&(*operand as u16).to_be_bytes()),
+            1 => instruction.push(*operand as u8),
+            _ => unreachable!("unsupported operand width {}", width),
+        }
+    }
+
+    instruction
+}
+
+/// Decodes the operands of an instruction whose opcode has already been
+/// read. Returns them with the number of bytes they took up.
+pub fn read_operands(definition: &Definition, ins: &[u8]) -> (Vec<usize>, usize) {
+    let mut operands = Vec::with_capacity(definition.operand_widths.len());
+    let mut offset = 0;
+
+    for width in definition.operand_widths {
+        match width {
+            2 => operands.push(read_u16(ins, offset) as usize),
+            1 => operands.push(read_u8(ins, offset) as usize),
+            _ => unreachable!("unsupported operand width {}", width),
+        }
+        offset += width;
+    }
+
+    (operands, offset)
+}
+
+pub fn read_u16(ins: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([ins[offset], ins[offset + 1]])
+}
+
+pub fn read_u8(ins: &[u8], offset: usize) -> u8 {
+    ins[offset]
+}
+
+#[cfg(test)]
+mod test {
+    use crate::token::Span;
+
+    use super::{make, read_operands, span_at, Opcode, OPCODES};
+
+    #[test]
+    fn test_make() {
+        let tests = vec![
+            (
+                Opcode::Constant,
+                vec![65534],
+                vec![Opcode::Constant as u8, 255, 254],
+            ),
+            (Opcode::Add, vec![], vec![Opcode::Add as u8]),
+            (
+                Opcode::GetLocal,
+                vec![255],
+                vec![Opcode::GetLocal as u8, 255],
+            ),
+            (
+                Opcode::Closure,
+                vec![65534, 255],
+                vec![Opcode::Closure as u8, 255, 254, 255],
+            ),
+        ];
+
+        for (op, operands, expected) in tests {
+            assert_eq!(make(op, &operands), expected, "{:?}", op);
+        }
+    }
+
+    #[test]
+    fn test_read_operands() {
+        let tests = vec![
+            (Opcode::Constant, vec![65535], 2),
+            (Opcode::GetLocal, vec![255], 1),
+            (Opcode::Closure, vec![65535, 255], 3),
+        ];
+
+        for (op, operands, bytes_read) in tests {
+            let instruction = make(op, &operands);
+            let (read, n) = read_operands(&op.definition(), &instruction[1..]);
+            assert_eq!(n, bytes_read);
+            assert_eq!(read, operands);
+        }
+    }
+
+    #[test]
+    fn test_opcode_round_trip() {
+        for (byte, op) in OPCODES.iter().enumerate() {
+            assert_eq!(*op as u8, byte as u8, "{:?} out of order", op);
+            assert_eq!(Opcode::from_byte(byte as u8), Some(*op));
+        }
+        assert_eq!(Opcode::from_byte(OPCODES.len() as u8), None);
+    }
+
+    #[test]
+    fn test_span_at() {
+        let first = Span::new(0, 5, 1, 1);
+        let second = Span::new(6, 9, 1, 7);
+        let map = vec![(2, first), (6, second)];
+
+        assert_eq!(span_at(&map, 0), None);
+        assert_eq!(span_at(&map, 2), Some(first));
+        assert_eq!(span_at(&map, 5), Some(first));
+        assert_eq!(span_at(&map, 6), Some(second));
+        assert_eq!(span_at(&map, 100), Some(second));
+    }
+}
@@ -0,0 +1,912 @@
+use std::{fmt, rc::Rc};
+
+use crate::{
+    ast::{
+        AssignExpression, BlockStatement, ExpressionNode, FunctionLiteral, IfExpression,
+        InfixExpression, Node, Program, StatementNode,
+    },
+    builtins::BUILTINS,
+    code::{make, Instructions, Opcode, SourceMap},
+    object::{CompiledFunction, Object},
+    symbol_table::{Symbol, SymbolScope, SymbolTable},
+    token::Span,
+};
+
+/// Placeholder for jump targets that are patched once the target is known.
+const PLACEHOLDER: usize = 9999;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Instructions for the top level of a program and the constants they use.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bytecode {
+    pub instructions: Instructions,
+    pub constants: Vec<Object>,
+    pub spans: SourceMap,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct EmittedInstruction {
+    opcode: Opcode,
+    position: usize,
+}
+
+/// Instructions of one function body, or of the top level, being compiled.
+#[derive(Default)]
+struct CompilationScope {
+    instructions: Instructions,
+    spans: SourceMap,
+    last_instruction: Option<EmittedInstruction>,
+    previous_instruction: Option<EmittedInstruction>,
+}
+
+pub struct Compiler {
+    constants: Vec<Object>,
+    symbol_table: SymbolTable,
+    scopes: Vec<CompilationScope>,
+    /// Span of the innermost node being compiled, recorded for each instruction.
+    span: Span,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        let mut symbol_table = SymbolTable::new();
+        for (idx, (name, _)) in BUILTINS.iter().enumerate() {
+            symbol_table.define_builtin(idx, name);
+        }
+
+        Self {
+            constants: vec![],
+            symbol_table,
+            scopes: vec![CompilationScope::default()],
+            span: Span::default(),
+        }
+    }
+
+    /// Continues from the symbols and constants of an earlier compilation,
+    /// as returned by `into_state`, so new code can use its globals.
+    pub fn new_with_state(symbol_table: SymbolTable, constants: Vec<Object>) -> Self {
+        Self {
+            constants,
+            symbol_table,
+            ..Self::new()
+        }
+    }
+
+    pub fn into_state(self) -> (SymbolTable, Vec<Object>) {
+        (self.symbol_table, self.constants)
+    }
+
+    pub fn compile_program(&mut self, program: &Program) -> Result<(), CompileError> {
+        for stmt in &program.statements {
+            self.compile_statement(stmt)?;
+        }
+        Ok(())
+    }
+
+    pub fn bytecode(&self) -> Bytecode {
+        Bytecode {
+            instructions: self.current_scope().instructions.clone(),
+            constants: self.constants.clone(),
+            spans: self.current_scope().spans.clone(),
+        }
+    }
+
+    fn compile_statement(&mut self, stmt: &StatementNode) -> Result<(), CompileError> {
+        let outer = std::mem::replace(&mut self.span, stmt.span());
+        let result = self.compile_statement_kind(stmt);
+        self.span = outer;
+        result
+    }
+
+    fn compile_statement_kind(&mut self, stmt: &StatementNode) -> Result<(), CompileError> {
+        match stmt {
+            StatementNode::Expression(exp_stmt) => {
+                self.compile_expression(&exp_stmt.expression)?;
+                self.emit(Opcode::Pop, &[]);
+            }
+            StatementNode::Let(let_stmt) => {
+                // Defined after the value, so `let x = x + 1` reads the earlier `x`.
+                // Functions refer to themselves through `define_function_name`.
+                self.compile_expression(&let_stmt.value)?;
+                let symbol = self.symbol_table.define(&let_stmt.name.value);
+
+                match symbol.scope {
+                    SymbolScope::Global => {
+                        self.check_limit(symbol.index, u16::MAX, "global bindings", stmt)?;
+                        self.emit(Opcode::SetGlobal, &[symbol.index]);
+                    }
+                    _ => {
+                        self.check_limit(symbol.index, u8::MAX as u16, "local bindings", stmt)?;
+                        self.emit(Opcode::SetLocal, &[symbol.index]);
+                    }
+                };
+            }
+            StatementNode::Return(ret) => {
+                self.compile_expression(&ret.return_value)?;
+                self.emit(Opcode::ReturnValue, &[]);
+            }
+        }
+        Ok(())
+    }
+
+    fn compile_block(&mut self, block: &BlockStatement) -> Result<(), CompileError> {
+        for stmt in &block.statements {
+            self.compile_statement(stmt)?;
+        }
+        Ok(())
+    }
+
+    fn compile_expression(&mut self, expression: &ExpressionNode) -> Result<(), CompileError> {
+        let outer = std::mem::replace(&mut self.span, expression.span());
+        let result = self.compile_expression_kind(expression);
+        self.span = outer;
+        result
+    }
+
+    fn compile_expression_kind(&mut self, expression: &ExpressionNode) -> Result<(), CompileError> {
+        match expression {
+            ExpressionNode::Integer(integer) => {
+                let idx = self.add_constant(Object::Integer(integer.value), expression)?;
+                self.emit(Opcode::Constant, &[idx]);
+            }
+            ExpressionNode::Float(float) => {
+                let idx = self.add_constant(Object::Float(float.value), expression)?;
+                self.emit(Opcode::Constant, &[idx]);
+            }
+            ExpressionNode::StringNode(string) => {
+                let idx = self.add_constant(Object::String(string.value.clone()), expression)?;
+                self.emit(Opcode::Constant, &[idx]);
+            }
+            ExpressionNode::BooleanNode(boolean) => {
+                let op = if boolean.value {
+                    Opcode::True
+                } else {
+                    Opcode::False
+                };
+                self.emit(op, &[]);
+            }
+            ExpressionNode::Prefix(prefix) => {
+                self.compile_expression(&prefix.right)?;
+                match prefix.operator.as_str() {
+                    "!" => self.emit(Opcode::Bang, &[]),
+                    "-" => self.emit(Opcode::Minus, &[]),
+                    operator => {
+                        return Err(error(format!("unknown operator: {}", operator), expression))
+                    }
+                };
+            }
+            ExpressionNode::Infix(infix) if matches!(infix.operator.as_str(), "&&" | "||") => {
+                self.compile_logical_expression(infix)?
+            }
+            ExpressionNode::Infix(infix) => {
+                self.compile_expression(&infix.left)?;
+                self.compile_expression(&infix.right)?;
+                let op = match infix.operator.as_str() {
+                    "+" => Opcode::Add,
+                    "-" => Opcode::Sub,
+                    "*" => Opcode::Mul,
+                    "/" => Opcode::Div,
+                    "%" => Opcode::Mod,
+                    "**" => Opcode::Pow,
+                    ">" => Opcode::GreaterThan,
+                    "<" => Opcode::LessThan,
+                    ">=" => Opcode::GreaterEqual,
+                    "<=" => Opcode::LessEqual,
+                    "==" => Opcode::Equal,
+                    "!=" => Opcode::NotEqual,
+                    operator => {
+                        return Err(error(format!("unknown operator: {}", operator), expression))
+                    }
+                };
+                self.emit(op, &[]);
+            }
+            ExpressionNode::Assign(assign) => self.compile_assign_expression(assign)?,
+            ExpressionNode::IfExpressionNode(if_exp) => self.compile_if_expression(if_exp)?,
+            ExpressionNode::IdentifierNode(identifier) => {
+                match self.symbol_table.resolve(&identifier.value) {
+                    Some(symbol) => self.load_symbol(&symbol),
+                    None => {
+                        return Err(error(
+                            format!("identifier not found: {}", identifier.value),
+                            expression,
+                        ))
+                    }
+                }
+            }
+            ExpressionNode::Array(array) => {
+                for element in &array.elements {
+                    self.compile_expression(element)?;
+                }
+                self.check_limit(array.elements.len(), u16::MAX, "array elements", expression)?;
+                self.emit(Opcode::Array, &[array.elements.len()]);
+            }
+            ExpressionNode::Hash(hash) => {
+                for (key, value) in &hash.pairs {
+                    self.compile_expression(key)?;
+                    self.compile_expression(value)?;
+                }
+                let len = hash.pairs.len() * 2;
+                self.check_limit(len, u16::MAX, "hash entries", expression)?;
+                self.emit(Opcode::Hash, &[len]);
+            }
+            ExpressionNode::Index(index) => {
+                self.compile_expression(&index.left)?;
+                self.compile_expression(&index.index)?;
+                self.emit(Opcode::Index, &[]);
+            }
+            ExpressionNode::Function(function) => self.compile_function(function, expression)?,
+            ExpressionNode::Call(call) => {
+                self.compile_expression(&call.function)?;
+                for arg in &call.arguments {
+                    self.compile_expression(arg)?;
+                }
+                self.check_limit(
+                    call.arguments.len(),
+                    u8::MAX as u16,
+                    "arguments",
+                    expression,
+                )?;
+                self.emit(Opcode::Call, &[call.arguments.len()]);
+            }
+        }
+        Ok(())
+    }
+
+    /// `a && b` and `a || b` skip `b` once `a` decides the result. Either way
+    /// the result is a boolean, with `!!` turning `b` into one.
+    fn compile_logical_expression(&mut self, infix: &InfixExpression) -> Result<(), CompileError> {
+        self.compile_expression(&infix.left)?;
+        let jump_not_truthy = self.emit(Opcode::JumpNotTruthy, &[PLACEHOLDER]);
+
+        let is_and = infix.operator == "&&";
+        if is_and {
+            self.compile_truthiness(&infix.right)?;
+        } else {
+            self.emit(Opcode::True, &[]);
+        }
+        let jump = self.emit(Opcode::Jump, &[PLACEHOLDER]);
+
+        let falsy_left = self.current_instructions().len();
+        self.change_operand(jump_not_truthy, falsy_left);
+        if is_and {
+            self.emit(Opcode::False, &[]);
+        } else {
+            self.compile_truthiness(&infix.right)?;
+        }
+
+        let after = self.current_instructions().len();
+        self.change_operand(jump, after);
+        Ok(())
+    }
+
+    fn compile_truthiness(&mut self, expression: &ExpressionNode) -> Result<(), CompileError> {
+        self.compile_expression(expression)?;
+        self.emit(Opcode::Bang, &[]);
+        self.emit(Opcode::Bang, &[]);
+        Ok(())
+    }
+
+    /// Computes the new value, stores it and leaves a copy on the stack as
+    /// the value of the expression. Captured variables live in cells shared
+    /// with the enclosing function, so assigning to them is seen by both.
+    fn compile_assign_expression(&mut self, assign: &AssignExpression) -> Result<(), CompileError> {
+        let name = &assign.name.value;
+        let symbol = match self.symbol_table.resolve(name) {
+            Some(symbol) => symbol,
+            None => return Err(error(format!("identifier not found: {}", name), assign)),
+        };
+        let store = match symbol.scope {
+            SymbolScope::Global => Some((Opcode::SetGlobal, Opcode::GetGlobal)),
+            SymbolScope::Local => Some((Opcode::SetLocal, Opcode::GetLocal)),
+            SymbolScope::Free => Some((Opcode::SetFree, Opcode::GetFree)),
+            SymbolScope::Builtin => {
+                return Err(error(format!("cannot assign to builtin: {}", name), assign))
+            }
+            // No operator applies to a function, so assigning to one by its
+            // own name always fails before anything would be stored.
+            SymbolScope::Function => None,
+        };
+
+        self.load_symbol(&symbol);
+        self.compile_expression(&assign.value)?;
+        let op = match assign.binary_operator() {
+            "+" => Opcode::Add,
+            "-" => Opcode::Sub,
+            "*" => Opcode::Mul,
+            "/" => Opcode::Div,
+            operator => return Err(error(format!("unknown operator: {}", operator), assign)),
+        };
+        self.emit(op, &[]);
+        if let Some((set, get)) = store {
+            self.emit(set, &[symbol.index]);
+            self.emit(get, &[symbol.index]);
+        }
+        Ok(())
+    }
+
+    fn compile_if_expression(&mut self, if_exp: &IfExpression) -> Result<(), CompileError> {
+        self.compile_expression(&if_exp.condition)?;
+        let jump_not_truthy = self.emit(Opcode::JumpNotTruthy, &[PLACEHOLDER]);
+
+        self.compile_branch(&if_exp.consequence)?;
+        let jump = self.emit(Opcode::Jump, &[PLACEHOLDER]);
+
+        let after_consequence = self.current_instructions().len();
+        self.change_operand(jump_not_truthy, after_consequence);
+
+        match &if_exp.alternative {
+            Some(alternative) => self.compile_branch(alternative)?,
+            None => {
+                self.emit(Opcode::Null, &[]);
+            }
+        }
+
+        let after_alternative = self.current_instructions().len();
+        self.change_operand(jump, after_alternative);
+        Ok(())
+    }
+
+    /// Compiles a branch of an `if` so it leaves exactly one value on the
+    /// stack: its last expression, or null when it ends in anything else.
+    fn compile_branch(&mut self, block: &BlockStatement) -> Result<(), CompileError> {
+        self.compile_block(block)?;
+
+        if self.last_instruction_is(Opcode::Pop) {
+            self.remove_last_pop();
+        } else {
+            self.emit(Opcode::Null, &[]);
+        }
+        Ok(())
+    }
+
+    fn compile_function(
+        &mut self,
+        function: &FunctionLiteral,
+        expression: &ExpressionNode,
+    ) -> Result<(), CompileError> {
+        self.enter_scope();
+
+        if let Some(name) = &function.name {
+            self.symbol_table.define_function_name(name);
+        }
+        for param in &function.parameters {
+            self.symbol_table.define(&param.value);
+        }
+
+        self.compile_block(&function.body)?;
+
+        if self.last_instruction_is(Opcode::Pop) {
+            self.replace_last_pop_with_return();
+        }
+        if !self.last_instruction_is(Opcode::ReturnValue) {
+            self.emit(Opcode::Return, &[]);
+        }
+
+        let free_symbols = self.symbol_table.free_symbols.clone();
+        let num_locals = self.symbol_table.num_definitions;
+        let (instructions, spans) = self.leave_scope();
+
+        for symbol in &free_symbols {
+            self.capture_symbol(symbol);
+        }
+
+        let compiled = CompiledFunction {
+            instructions,
+            num_locals,
+            num_parameters: function.parameters.len(),
+            name: function.name.clone(),
+            spans,
+        };
+        let idx = self.add_constant(Object::CompiledFunction(Rc::new(compiled)), expression)?;
+        self.check_limit(
+            free_symbols.len(),
+            u8::MAX as u16,
+            "captured variables",
+            expression,
+        )?;
+        self.emit(Opcode::Closure, &[idx, free_symbols.len()]);
+        Ok(())
+    }
+
+    fn load_symbol(&mut self, symbol: &Symbol) {
+        let (op, operands) = match symbol.scope {
+            SymbolScope::Global => (Opcode::GetGlobal, vec![symbol.index]),
+            SymbolScope::Local => (Opcode::GetLocal, vec![symbol.index]),
+            SymbolScope::Builtin => (Opcode::GetBuiltin, vec![symbol.index]),
+            SymbolScope::Free => (Opcode::GetFree, vec![symbol.index]),
+            SymbolScope::Function => (Opcode::CurrentClosure, vec![]),
+        };
+        self.emit(op, &operands);
+    }
+
+    /// Pushes what a closure keeps of `symbol`: the cell of a variable, so
+    /// the closure and the enclosing function share it, or the function
+    /// itself.
+    fn capture_symbol(&mut self, symbol: &Symbol) {
+        match symbol.scope {
+            SymbolScope::Local => {
+                self.emit(Opcode::CaptureLocal, &[symbol.index]);
+            }
+            SymbolScope::Free => {
+                self.emit(Opcode::CaptureFree, &[symbol.index]);
+            }
+            _ => self.load_symbol(symbol),
+        }
+    }
+
+    fn add_constant(&mut self, obj: Object, node: &impl Node) -> Result<usize, CompileError> {
+        self.check_limit(self.constants.len(), u16::MAX, "constants", node)?;
+        self.constants.push(obj);
+        Ok(self.constants.len() - 1)
+    }
+
+    /// Operands have fixed widths, so counts past `max` can't be encoded.
+    fn check_limit(
+        &self,
+        count: usize,
+        max: u16,
+        what: &str,
+        node: &impl Node,
+    ) -> Result<(), CompileError> {
+        if count > max as usize {
+            return Err(error(
+                format!("too many {}: the limit is {}", what, max),
+                node,
+            ));
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, op: Opcode, operands: &[usize]) -> usize {
+        let instruction = make(op, operands);
+        let position = self.add_instruction(instruction);
+
+        let scope = self.current_scope_mut();
+        scope.previous_instruction = scope.last_instruction;
+        scope.last_instruction = Some(EmittedInstruction {
+            opcode: op,
+            position,
+        });
+
+        position
+    }
+
+    fn add_instruction(&mut self, instruction: Vec<u8>) -> usize {
+        let span = self.span;
+        let scope = self.current_scope_mut();
+        let position = scope.instructions.len();
+        scope.instructions.extend(instruction);
+
+        if scope.spans.last().map(|(_, last)| *last) != Some(span) {
+            scope.spans.push((position, span));
+        }
+        position
+    }
+
+    fn last_instruction_is(&self, op: Opcode) -> bool {
+        matches!(self.current_scope().last_instruction, Some(last) if last.opcode == op)
+    }
+
+    fn remove_last_pop(&mut self) {
+        let scope = self.current_scope_mut();
+        if let Some(last) = scope.last_instruction {
+            scope.instructions.truncate(last.position);
+            scope.spans.retain(|(offset, _)| *offset < last.position);
+            scope.last_instruction = scope.previous_instruction;
+        }
+    }
+
+    fn replace_last_pop_with_return(&mut self) {
+        let scope = self.current_scope_mut();
+        if let Some(last) = scope.last_instruction.as_mut() {
+            scope.instructions[last.position] = Opcode::ReturnValue as u8;
+            last.opcode = Opcode::ReturnValue;
+        }
+    }
+
+    fn change_operand(&mut self, position: usize, operand: usize) {
+        let instructions = &mut self.current_scope_mut().instructions;
+        let op = Opcode::from_byte(instructions[position]).expect("patching a valid opcode");
+        let new_instruction = make(op, &[operand]);
+        instructions[position..position + new_instruction.len()].copy_from_slice(&new_instruction);
+    }
+
+    fn current_scope(&self) -> &CompilationScope {
+        self.scopes.last().expect("compiler always has a scope")
+    }
+
+    fn current_scope_mut(&mut self) -> &mut CompilationScope {
+        self.scopes.last_mut().expect("compiler always has a scope")
+    }
+
+    fn current_instructions(&self) -> &Instructions {
+        &self.current_scope().instructions
+    }
+
+    fn enter_scope(&mut self) {
+        self.scopes.push(CompilationScope::default());
+        let outer = std::mem::take(&mut self.symbol_table);
+        self.symbol_table = SymbolTable::new_enclosed(outer);
+    }
+
+    fn leave_scope(&mut self) -> (Instructions, SourceMap) {
+        let scope = self.scopes.pop().expect("leaving a scope that was entered");
+        let table = std::mem::take(&mut self.symbol_table);
+        self.symbol_table = *table
+            .outer
+            .expect("enclosed symbol table has an outer table");
+        (scope.instructions, scope.spans)
+    }
+}
+
+fn error(message: String, node: &impl Node) -> CompileError {
+    CompileError {
+        message,
+        span: node.span(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use crate::{
+        code::{make, span_at, Instructions, Opcode},
+        lexer::Lexer,
+        object::{CompiledFunction, Object},
+        parser::Parser,
+    };
+
+    use super::{Bytecode, Compiler};
+
+    fn compile(input: &str) -> Bytecode {
+        let (program, errors) = Parser::new(Lexer::new(input)).parse_program();
+        assert!(errors.is_empty(), "parser errors: {:?}", errors);
+
+        let mut compiler = Compiler::new();
+        compiler
+            .compile_program(&program)
+            .unwrap_or_else(|e| panic!("compiler error: {}", e));
+
+        // Debug info is covered by its own test, the rest compare bytecode only.
+        let mut bytecode = compiler.bytecode();
+        for constant in bytecode.constants.iter_mut() {
+            if let Object::CompiledFunction(function) = constant {
+                let mut function = (**function).clone();
+                function.spans.clear();
+                *constant = Object::CompiledFunction(Rc::new(function));
+            }
+        }
+        bytecode
+    }
+
+    fn concat(instructions: Vec<Instructions>) -> Instructions {
+        instructions.into_iter().flatten().collect()
+    }
+
+    fn function(
+        instructions: Vec<Instructions>,
+        num_locals: usize,
+        num_parameters: usize,
+    ) -> Object {
+        Object::CompiledFunction(Rc::new(CompiledFunction {
+            instructions: concat(instructions),
+            num_locals,
+            num_parameters,
+            ..Default::default()
+        }))
+    }
+
+    #[test]
+    fn test_integer_arithmetic() {
+        let bytecode = compile("1 + 2; -1");
+
+        assert_eq!(
+            bytecode.instructions,
+            concat(vec![
+                make(Opcode::Constant, &[0]),
+                make(Opcode::Constant, &[1]),
+                make(Opcode::Add, &[]),
+                make(Opcode::Pop, &[]),
+                make(Opcode::Constant, &[2]),
+                make(Opcode::Minus, &[]),
+                make(Opcode::Pop, &[]),
+            ])
+        );
+        assert_eq!(
+            bytecode.constants,
+            vec![Object::Integer(1), Object::Integer(2), Object::Integer(1)]
+        );
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let bytecode = compile("if (true) { 10 }; 3333;");
+
+        assert_eq!(
+            bytecode.instructions,
+            concat(vec![
+                // 0000
+                make(Opcode::True, &[]),
+                // 0001
+                make(Opcode::JumpNotTruthy, &[10]),
+                // 0004
+                make(Opcode::Constant, &[0]),
+                // 0007
+                make(Opcode::Jump, &[11]),
+                // 0010
+                make(Opcode::Null, &[]),
+                // 0011
+                make(Opcode::Pop, &[]),
+                // 0012
+                make(Opcode::Constant, &[1]),
+                // 0015
+                make(Opcode::Pop, &[]),
+            ])
+        );
+
+        let bytecode = compile("if (true) { let a = 1; } else { 20 }");
+        assert_eq!(
+            bytecode.instructions,
+            concat(vec![
+                // 0000
+                make(Opcode::True, &[]),
+                // 0001
+                make(Opcode::JumpNotTruthy, &[14]),
+                // 0004
+                make(Opcode::Constant, &[0]),
+                // 0007
+                make(Opcode::SetGlobal, &[0]),
+                // 0010
+                make(Opcode::Null, &[]),
+                // 0011
+                make(Opcode::Jump, &[17]),
+                // 0014
+                make(Opcode::Constant, &[1]),
+                // 0017
+                make(Opcode::Pop, &[]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        let bytecode = compile("true && 1; false || 2");
+
+        assert_eq!(
+            bytecode.instructions,
+            concat(vec![
+                // 0000
+                make(Opcode::True, &[]),
+                // 0001
+                make(Opcode::JumpNotTruthy, &[12]),
+                // 0004
+                make(Opcode::Constant, &[0]),
+                // 0007
+                make(Opcode::Bang, &[]),
+                // 0008
+                make(Opcode::Bang, &[]),
+                // 0009
+                make(Opcode::Jump, &[13]),
+                // 0012
+                make(Opcode::False, &[]),
+                // 0013
+                make(Opcode::Pop, &[]),
+                // 0014
+                make(Opcode::False, &[]),
+                // 0015
+                make(Opcode::JumpNotTruthy, &[22]),
+                // 0018
+                make(Opcode::True, &[]),
+                // 0019
+                make(Opcode::Jump, &[27]),
+                // 0022
+                make(Opcode::Constant, &[1]),
+                // 0025
+                make(Opcode::Bang, &[]),
+                // 0026
+                make(Opcode::Bang, &[]),
+                // 0027
+                make(Opcode::Pop, &[]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_compound_assignment() {
+        let bytecode = compile("let x = 1; x += 2;");
+
+        assert_eq!(
+            bytecode.instructions,
+            concat(vec![
+                make(Opcode::Constant, &[0]),
+                make(Opcode::SetGlobal, &[0]),
+                make(Opcode::GetGlobal, &[0]),
+                make(Opcode::Constant, &[1]),
+                make(Opcode::Add, &[]),
+                make(Opcode::SetGlobal, &[0]),
+                make(Opcode::GetGlobal, &[0]),
+                make(Opcode::Pop, &[]),
+            ])
+        );
+
+        let bytecode = compile("fn(a) { fn() { a -= 1 } }");
+        match &bytecode.constants[1] {
+            Object::CompiledFunction(function) => assert_eq!(
+                function.instructions,
+                concat(vec![
+                    make(Opcode::GetFree, &[0]),
+                    make(Opcode::Constant, &[0]),
+                    make(Opcode::Sub, &[]),
+                    make(Opcode::SetFree, &[0]),
+                    make(Opcode::GetFree, &[0]),
+                    make(Opcode::ReturnValue, &[]),
+                ])
+            ),
+            other => panic!("expected compiled function, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_global_let_statements_and_builtins() {
+        let bytecode = compile("let one = 1; let two = one; len(two);");
+
+        assert_eq!(
+            bytecode.instructions,
+            concat(vec![
+                make(Opcode::Constant, &[0]),
+                make(Opcode::SetGlobal, &[0]),
+                make(Opcode::GetGlobal, &[0]),
+                make(Opcode::SetGlobal, &[1]),
+                make(Opcode::GetBuiltin, &[0]),
+                make(Opcode::GetGlobal, &[1]),
+                make(Opcode::Call, &[1]),
+                make(Opcode::Pop, &[]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_functions() {
+        let bytecode = compile("fn(a) { let b = a; return b; }; fn() { }");
+
+        assert_eq!(
+            bytecode.constants,
+            vec![
+                function(
+                    vec![
+                        make(Opcode::GetLocal, &[0]),
+                        make(Opcode::SetLocal, &[1]),
+                        make(Opcode::GetLocal, &[1]),
+                        make(Opcode::ReturnValue, &[]),
+                    ],
+                    2,
+                    1
+                ),
+                function(vec![make(Opcode::Return, &[])], 0, 0),
+            ]
+        );
+        assert_eq!(
+            bytecode.instructions,
+            concat(vec![
+                make(Opcode::Closure, &[0, 0]),
+                make(Opcode::Pop, &[]),
+                make(Opcode::Closure, &[1, 0]),
+                make(Opcode::Pop, &[]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_closures() {
+        let bytecode = compile("fn(a) { fn(b) { a + b } }");
+
+        assert_eq!(
+            bytecode.constants,
+            vec![
+                function(
+                    vec![
+                        make(Opcode::GetFree, &[0]),
+                        make(Opcode::GetLocal, &[0]),
+                        make(Opcode::Add, &[]),
+                        make(Opcode::ReturnValue, &[]),
+                    ],
+                    1,
+                    1
+                ),
+                function(
+                    vec![
+                        make(Opcode::CaptureLocal, &[0]),
+                        make(Opcode::Closure, &[0, 1]),
+                        make(Opcode::ReturnValue, &[]),
+                    ],
+                    1,
+                    1
+                ),
+            ]
+        );
+
+        let bytecode = compile("fn(a) { fn() { fn() { a } } }");
+        match &bytecode.constants[1] {
+            Object::CompiledFunction(function) => assert_eq!(
+                function.instructions,
+                concat(vec![
+                    make(Opcode::CaptureFree, &[0]),
+                    make(Opcode::Closure, &[0, 1]),
+                    make(Opcode::ReturnValue, &[]),
+                ])
+            ),
+            other => panic!("expected compiled function, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_recursive_function_uses_current_closure() {
+        let bytecode = compile("let countDown = fn(x) { countDown(x - 1); };");
+
+        match &bytecode.constants[1] {
+            Object::CompiledFunction(function) => {
+                assert_eq!(function.name.as_deref(), Some("countDown"));
+                assert_eq!(
+                    function.instructions,
+                    concat(vec![
+                        make(Opcode::CurrentClosure, &[]),
+                        make(Opcode::GetLocal, &[0]),
+                        make(Opcode::Constant, &[0]),
+                        make(Opcode::Sub, &[]),
+                        make(Opcode::Call, &[1]),
+                        make(Opcode::ReturnValue, &[]),
+                    ])
+                );
+            }
+            other => panic!("expected compiled function, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_source_map() {
+        let input = "1 + 2;\n-x";
+        let (program, _) = Parser::new(Lexer::new(input)).parse_program();
+        let mut compiler = Compiler::new();
+        compiler.symbol_table.define("x");
+        compiler.compile_program(&program).unwrap();
+        let bytecode = compiler.bytecode();
+
+        let text = |offset| {
+            let span = span_at(&bytecode.spans, offset).unwrap();
+            &input[span.start..span.end]
+        };
+        // 1, 2, +, pop, x, -, pop
+        let offsets = [0, 3, 6, 7, 8, 11, 12];
+        let expected = ["1", "2", "1 + 2", "1 + 2", "x", "-x", "-x"];
+        for (offset, expected) in offsets.into_iter().zip(expected) {
+            assert_eq!(text(offset), expected, "offset {}", offset);
+        }
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let (program, _) = Parser::new(Lexer::new("let a = 1;\nfoo + a")).parse_program();
+        let error = Compiler::new().compile_program(&program).unwrap_err();
+
+        assert_eq!(error.message, "identifier not found: foo");
+        assert_eq!((error.span.line, error.span.column), (2, 1));
+    }
+}
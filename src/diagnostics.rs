@@ -0,0 +1,352 @@
+use std::fmt::Write;
+
+use crate::{
+    compiler::CompileError, lexer::LexError, object::RuntimeError, parser::ParseError, token::Span,
+};
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// A message about a location in the source, rendered in the style of rustc.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Option<Span>,
+    /// Short text printed next to the carets.
+    pub label: Option<String>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: String, span: Option<Span>) -> Self {
+        Self {
+            message,
+            span,
+            label: None,
+            help: None,
+        }
+    }
+
+    pub fn with_label(mut self, label: &str) -> Self {
+        self.label = Some(label.to_string());
+        self
+    }
+
+    pub fn with_help(mut self, help: &str) -> Self {
+        self.help = Some(help.to_string());
+        self
+    }
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(error: &ParseError) -> Self {
+        let diagnostic = Diagnostic::error(error.to_string(), Some(error.span()));
+
+        match error {
+            ParseError::UnexpectedToken { expected, .. } => {
+                diagnostic.with_label(&format!("expected {}", expected))
+            }
+            ParseError::UnexpectedEof {
+                expected: Some(expected),
+                ..
+            } => diagnostic.with_label(&format!("expected {}", expected)),
+            ParseError::UnexpectedEof { expected: None, .. } => {
+                diagnostic.with_label("expected an expression")
+            }
+            ParseError::MissingSemicolon { .. } => diagnostic
+                .with_label("unexpected token")
+                .with_help("add `;` to end the previous statement"),
+            ParseError::ExpectedExpression { .. } => {
+                diagnostic.with_label("expected an expression")
+            }
+            ParseError::Lex(LexError::IllegalCharacter { .. }) => {
+                diagnostic.with_label("not valid here")
+            }
+            ParseError::Lex(LexError::UnterminatedString { .. }) => {
+                diagnostic.with_label("string starts here and is never closed")
+            }
+            ParseError::Lex(LexError::UnterminatedComment { .. }) => {
+                diagnostic.with_label("comment starts here and is never closed")
+            }
+            ParseError::Lex(LexError::InvalidNumber { .. }) => diagnostic
+                .with_label("not a valid number")
+                .with_help("numbers look like `42`, `1_000`, `0xff`, `0o17`, `0b1010` or `2.5e-3`"),
+            ParseError::Lex(LexError::InvalidEscape { .. }) => diagnostic
+                .with_label("unknown escape")
+                .with_help("valid escapes are `\\n`, `\\t`, `\\\"`, `\\\\` and `\\u{...}`"),
+            ParseError::InvalidInteger { .. } => diagnostic
+                .with_label("does not fit in 64 bits")
+                .with_help(&format!("integers range from {} to {}", i64::MIN, i64::MAX)),
+            ParseError::InvalidFloat { .. } => diagnostic
+                .with_label("does not fit in 64 bits")
+                .with_help(&format!(
+                    "floats range up to {:e} either side of zero",
+                    f64::MAX
+                )),
+            ParseError::InvalidAssignmentTarget { .. } => {
+                diagnostic.with_label("cannot be assigned to")
+            }
+        }
+    }
+}
+
+impl From<&RuntimeError> for Diagnostic {
+    fn from(error: &RuntimeError) -> Self {
+        Diagnostic::error(error.message.clone(), error.span)
+    }
+}
+
+impl From<&CompileError> for Diagnostic {
+    fn from(error: &CompileError) -> Self {
+        Diagnostic::error(error.message.clone(), Some(error.span))
+    }
+}
+
+pub struct Renderer {
+    color: bool,
+}
+
+impl Renderer {
+    pub fn plain() -> Self {
+        Self { color: false }
+    }
+
+    pub fn colored() -> Self {
+        Self { color: true }
+    }
+
+    /// Colors output when `stream` is a terminal and `NO_COLOR` isn't set.
+    pub fn for_stream(stream: &impl std::io::IsTerminal) -> Self {
+        Self {
+            color: stream.is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+        }
+    }
+
+    pub fn render(&self, file_name: &str, source: &str, diagnostic: &Diagnostic) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{}error{}{}: {}{}",
+            self.paint(RED),
+            self.paint(RESET),
+            self.paint(BOLD),
+            diagnostic.message,
+            self.paint(RESET)
+        );
+
+        let span = match diagnostic.span {
+            Some(span) => span,
+            None => {
+                self.render_help(&mut out, "", diagnostic);
+                return out;
+            }
+        };
+
+        let location = SourceLocation::find(source, span);
+        let line_number = location.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+
+        let _ = writeln!(
+            out,
+            "{}{}-->{} {}:{}:{}",
+            gutter,
+            self.paint(BLUE),
+            self.paint(RESET),
+            file_name,
+            location.line,
+            location.column
+        );
+        let _ = writeln!(out, "{} {}|{}", gutter, self.paint(BLUE), self.paint(RESET));
+        let _ = writeln!(
+            out,
+            "{}{} |{} {}",
+            self.paint(BLUE),
+            line_number,
+            self.paint(RESET),
+            location.text
+        );
+
+        // Tabs are copied into the padding so the carets line up with the source line.
+        let padding: String = location
+            .text
+            .chars()
+            .take(location.column - 1)
+            .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+            .collect();
+        let mut marker = format!(
+            "{} {}|{} {}{}{}",
+            gutter,
+            self.paint(BLUE),
+            self.paint(RESET),
+            padding,
+            self.paint(RED),
+            "^".repeat(location.width)
+        );
+        if let Some(label) = &diagnostic.label {
+            marker.push(' ');
+            marker.push_str(label);
+        }
+        marker.push_str(self.paint(RESET));
+        let _ = writeln!(out, "{}", marker);
+
+        self.render_help(&mut out, &gutter, diagnostic);
+
+        out
+    }
+
+    fn render_help(&self, out: &mut String, gutter: &str, diagnostic: &Diagnostic) {
+        if let Some(help) = &diagnostic.help {
+            let _ = writeln!(
+                out,
+                "{} {}={} {}help{}: {}",
+                gutter,
+                self.paint(BLUE),
+                self.paint(RESET),
+                self.paint(BOLD),
+                self.paint(RESET),
+                help
+            );
+        }
+    }
+
+    fn paint(&self, code: &'static str) -> &'static str {
+        if self.color {
+            code
+        } else {
+            ""
+        }
+    }
+}
+
+/// The source line a span starts on, with the 1-based column and the number
+/// of characters to underline on that line.
+struct SourceLocation<'a> {
+    text: &'a str,
+    line: usize,
+    column: usize,
+    width: usize,
+}
+
+impl<'a> SourceLocation<'a> {
+    fn find(source: &'a str, span: Span) -> Self {
+        let mut start = span.start.min(source.len());
+        let mut end = span.end.clamp(start, source.len());
+
+        // An empty span at the end of the input (usually EOF) reads better right
+        // after the last piece of code than on a trailing blank line.
+        let trimmed = source.trim_end().len();
+        if start == end && start > trimmed {
+            start = trimmed;
+            end = trimmed;
+        }
+
+        let line_start = source[..start].rfind('\n').map_or(0, |idx| idx + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |idx| start + idx);
+        let text = source[line_start..line_end].trim_end_matches('\r');
+
+        Self {
+            text,
+            line: source[..line_start].matches('\n').count() + 1,
+            column: source[line_start..start].chars().count() + 1,
+            width: source[start..end.min(line_end)].chars().count().max(1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{lexer::Lexer, object::RuntimeError, parser::Parser, token::Span};
+
+    use super::{Diagnostic, Renderer};
+
+    fn render_parse_errors(source: &str) -> Vec<String> {
+        let (_, errors) = Parser::new(Lexer::new(source)).parse_program();
+        errors
+            .iter()
+            .map(|error| Renderer::plain().render("test.mk", source, &Diagnostic::from(error)))
+            .collect()
+    }
+
+    #[test]
+    fn test_render_parse_error() {
+        let source = "let a = 1;\nlet = 2;\n";
+
+        assert_eq!(
+            render_parse_errors(source),
+            vec![concat!(
+                "error: expected identifier, found `=`\n",
+                " --> test.mk:2:5\n",
+                "  |\n",
+                "2 | let = 2;\n",
+                "  |     ^ expected identifier\n",
+            )]
+        );
+    }
+
+    #[test]
+    fn test_render_help_and_eof() {
+        let source = "let a = 1 let b = 2;";
+        assert_eq!(
+            render_parse_errors(source),
+            vec![concat!(
+                "error: expected `;` after statement, found `let`\n",
+                " --> test.mk:1:11\n",
+                "  |\n",
+                "1 | let a = 1 let b = 2;\n",
+                "  |           ^^^ unexpected token\n",
+                "  = help: add `;` to end the previous statement\n",
+            )]
+        );
+
+        let source = "fn(x) {\n\tx\n\n";
+        assert_eq!(
+            render_parse_errors(source),
+            vec![concat!(
+                "error: unexpected end of input, expected `}`\n",
+                " --> test.mk:2:3\n",
+                "  |\n",
+                "2 | \tx\n",
+                "  | \t ^ expected `}`\n",
+            )]
+        );
+    }
+
+    #[test]
+    fn test_render_runtime_error() {
+        let source = "let x = 5;\nx + true;";
+        let error = RuntimeError {
+            message: "type mismatch: INTEGER + BOOLEAN".to_string(),
+            span: Some(Span::new(11, 19, 2, 1)),
+        };
+
+        assert_eq!(
+            Renderer::plain().render("main.mk", source, &Diagnostic::from(&error)),
+            concat!(
+                "error: type mismatch: INTEGER + BOOLEAN\n",
+                " --> main.mk:2:1\n",
+                "  |\n",
+                "2 | x + true;\n",
+                "  | ^^^^^^^^\n",
+            )
+        );
+
+        let error = RuntimeError::new("stack overflow".to_string());
+        assert_eq!(
+            Renderer::plain().render("main.mk", source, &Diagnostic::from(&error)),
+            "error: stack overflow\n"
+        );
+    }
+
+    #[test]
+    fn test_render_colored() {
+        let diagnostic = Diagnostic::error("boom".to_string(), Some(Span::new(0, 1, 1, 1)));
+        let rendered = Renderer::colored().render("a.mk", "x", &diagnostic);
+
+        assert!(rendered.starts_with("\x1b[1;31merror\x1b[0m\x1b[1m: boom\x1b[0m\n"));
+        assert!(rendered.contains("\x1b[1;31m^\x1b[0m"));
+    }
+}
@@ -0,0 +1,141 @@
+use std::fmt::Write;
+
+use crate::{
+    code::{read_operands, Opcode},
+    compiler::Bytecode,
+    object::Object,
+};
+
+/// Renders compiled bytecode as a listing, one instruction per line:
+///
+/// ```text
+/// 0000 OpConstant 0 (5)
+/// 0003 OpSetGlobal 0
+/// ```
+///
+/// The body of every compiled function in the constant pool follows the
+/// top-level listing, indented under a header naming its constant.
+pub fn disassemble(bytecode: &Bytecode) -> String {
+    let mut out = String::new();
+    write_listing(&mut out, &bytecode.instructions, &bytecode.constants, "");
+
+    for (idx, constant) in bytecode.constants.iter().enumerate() {
+        if let Object::CompiledFunction(function) = constant {
+            let _ = writeln!(
+                out,
+                "\nconstant {}: {} (locals: {})",
+                idx,
+                function.inspect(),
+                function.num_locals
+            );
+            write_listing(&mut out, &function.instructions, &bytecode.constants, "  ");
+        }
+    }
+
+    out
+}
+
+fn write_listing(out: &mut String, instructions: &[u8], constants: &[Object], indent: &str) {
+    let mut offset = 0;
+
+    while offset < instructions.len() {
+        let (text, len) = format_instruction(instructions, offset, constants);
+        let _ = writeln!(out, "{}{:04} {}", indent, offset, text);
+        offset += len;
+    }
+}
+
+/// Formats the instruction starting at `offset` as its name and operands,
+/// followed by the constant it refers to, if any. Returns the text and the
+/// length of the instruction in bytes.
+pub fn format_instruction(
+    instructions: &[u8],
+    offset: usize,
+    constants: &[Object],
+) -> (String, usize) {
+    let byte = instructions[offset];
+    let op = match Opcode::from_byte(byte) {
+        Some(op) => op,
+        None => return (format!("ERROR: unknown opcode {:#04x}", byte), 1),
+    };
+
+    let definition = op.definition();
+    let (operands, read) = read_operands(&definition, &instructions[offset + 1..]);
+
+    let mut text = definition.name.to_string();
+    for operand in &operands {
+        let _ = write!(text, " {}", operand);
+    }
+
+    if matches!(op, Opcode::Constant | Opcode::Closure) {
+        if let Some(constant) = constants.get(operands[0]) {
+            let _ = write!(text, " ({})", describe_constant(constant));
+        }
+    }
+
+    (text, 1 + read)
+}
+
+/// Strings are quoted so they can't be mistaken for other values.
+fn describe_constant(constant: &Object) -> String {
+    match constant {
+        Object::String(value) => format!("{:?}", value),
+        other => other.inspect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{compiler::Compiler, lexer::Lexer, parser::Parser};
+
+    use super::disassemble;
+
+    fn disassemble_source(input: &str) -> String {
+        let (program, errors) = Parser::new(Lexer::new(input)).parse_program();
+        assert!(errors.is_empty(), "parser errors: {:?}", errors);
+
+        let mut compiler = Compiler::new();
+        compiler.compile_program(&program).unwrap();
+        disassemble(&compiler.bytecode())
+    }
+
+    #[test]
+    fn test_disassemble() {
+        assert_eq!(
+            disassemble_source("1 + 2; \"two\"; -1"),
+            concat!(
+                "0000 OpConstant 0 (1)\n",
+                "0003 OpConstant 1 (2)\n",
+                "0006 OpAdd\n",
+                "0007 OpPop\n",
+                "0008 OpConstant 2 (\"two\")\n",
+                "0011 OpPop\n",
+                "0012 OpConstant 3 (1)\n",
+                "0015 OpMinus\n",
+                "0016 OpPop\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_disassemble_nested_functions() {
+        assert_eq!(
+            disassemble_source("let adder = fn(a) { fn(b) { a + b } };"),
+            concat!(
+                "0000 OpClosure 1 0 (fn adder/1)\n",
+                "0004 OpSetGlobal 0\n",
+                "\n",
+                "constant 0: fn <anonymous>/1 (locals: 1)\n",
+                "  0000 OpGetFree 0\n",
+                "  0002 OpGetLocal 0\n",
+                "  0004 OpAdd\n",
+                "  0005 OpReturnValue\n",
+                "\n",
+                "constant 1: fn adder/1 (locals: 1)\n",
+                "  0000 OpCaptureLocal 0\n",
+                "  0002 OpClosure 0 1 (fn <anonymous>/1)\n",
+                "  0006 OpReturnValue\n",
+            )
+        );
+    }
+}
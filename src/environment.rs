@@ -0,0 +1,101 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::object::Object;
+
+pub type Env = Rc<RefCell<Environment>>;
+
+#[derive(Debug, Default)]
+pub struct Environment {
+    store: HashMap<String, Object>,
+    outer: Option<Env>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Creates a scope whose lookups fall back to `outer`, as used for function calls.
+    pub fn new_enclosed(outer: Env) -> Self {
+        Self {
+            store: HashMap::new(),
+            outer: Some(outer),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Object> {
+        match self.store.get(name) {
+            Some(obj) => Some(obj.clone()),
+            None => self
+                .outer
+                .as_ref()
+                .and_then(|outer| outer.borrow().get(name)),
+        }
+    }
+
+    /// Bindings made in this scope, not including those of outer scopes.
+    pub fn bindings(&self) -> impl Iterator<Item = (&str, &Object)> {
+        self.store.iter().map(|(name, obj)| (name.as_str(), obj))
+    }
+
+    pub fn set(&mut self, name: &str, val: Object) -> Object {
+        self.store.insert(name.to_string(), val.clone());
+        val
+    }
+
+    /// Replaces the value of an existing binding in the innermost scope that
+    /// has one. Returns false, binding nothing, when `name` isn't bound.
+    pub fn assign(&mut self, name: &str, val: Object) -> bool {
+        if let Some(slot) = self.store.get_mut(name) {
+            *slot = val;
+            return true;
+        }
+        match &self.outer {
+            Some(outer) => outer.borrow_mut().assign(name, val),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::object::Object;
+
+    use super::Environment;
+
+    #[test]
+    fn test_enclosed_environment_lookup() {
+        let outer = Rc::new(RefCell::new(Environment::new()));
+        outer.borrow_mut().set("a", Object::Integer(1));
+        outer.borrow_mut().set("b", Object::Integer(2));
+
+        let mut inner = Environment::new_enclosed(Rc::clone(&outer));
+        inner.set("b", Object::Integer(3));
+
+        assert_eq!(inner.get("a"), Some(Object::Integer(1)));
+        assert_eq!(inner.get("b"), Some(Object::Integer(3)));
+        assert_eq!(outer.borrow().get("b"), Some(Object::Integer(2)));
+        assert_eq!(inner.get("c"), None);
+    }
+
+    #[test]
+    fn test_assign_updates_innermost_binding() {
+        let outer = Rc::new(RefCell::new(Environment::new()));
+        outer.borrow_mut().set("a", Object::Integer(1));
+        outer.borrow_mut().set("b", Object::Integer(2));
+
+        let mut inner = Environment::new_enclosed(Rc::clone(&outer));
+        inner.set("b", Object::Integer(3));
+
+        assert!(inner.assign("a", Object::Integer(10)));
+        assert!(inner.assign("b", Object::Integer(30)));
+        assert!(!inner.assign("c", Object::Integer(0)));
+
+        assert_eq!(outer.borrow().get("a"), Some(Object::Integer(10)));
+        assert_eq!(outer.borrow().get("b"), Some(Object::Integer(2)));
+        assert_eq!(inner.get("b"), Some(Object::Integer(30)));
+        assert_eq!(inner.get("c"), None);
+    }
+}
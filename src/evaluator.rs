@@ -0,0 +1,979 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    rc::Rc,
+};
+
+use crate::{
+    ast::{
+        BlockStatement, ExpressionNode, HashLiteral, IfExpression, Node, Program, StatementNode,
+    },
+    builtins,
+    environment::{Env, Environment},
+    object::{Function, Object, RuntimeError},
+    vm::MAX_FRAMES,
+};
+
+thread_local! {
+    /// Function calls in progress on this thread.
+    static CALL_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+pub fn eval_program(program: &Program, env: &Env) -> Object {
+    let mut result = Object::Null;
+
+    for stmt in &program.statements {
+        result = eval_statement(stmt, env);
+
+        match result {
+            Object::ReturnValue(value) => return *value,
+            Object::Error(_) => return result,
+            _ => {}
+        }
+    }
+
+    result
+}
+
+fn eval_block_statement(block: &BlockStatement, env: &Env) -> Object {
+    let mut result = Object::Null;
+
+    for stmt in &block.statements {
+        result = eval_statement(stmt, env);
+
+        // Return values stay wrapped so the enclosing function or program can stop on them.
+        if matches!(result, Object::ReturnValue(_) | Object::Error(_)) {
+            return result;
+        }
+    }
+
+    result
+}
+
+fn eval_statement(stmt: &StatementNode, env: &Env) -> Object {
+    match stmt {
+        StatementNode::Let(let_stmt) => {
+            let value = eval_expression(&let_stmt.value, env);
+            if value.is_error() {
+                return value;
+            }
+            env.borrow_mut().set(&let_stmt.name.value, value);
+            Object::Null
+        }
+        StatementNode::Return(ret) => {
+            let value = eval_expression(&ret.return_value, env);
+            if value.is_error() {
+                return value;
+            }
+            Object::ReturnValue(Box::new(value))
+        }
+        StatementNode::Expression(exp_stmt) => eval_expression(&exp_stmt.expression, env),
+    }
+}
+
+fn eval_expression(expression: &ExpressionNode, env: &Env) -> Object {
+    let mut result = eval_expression_kind(expression, env);
+
+    // The innermost failing expression claims the error so it can be reported in place.
+    if let Object::Error(error) = &mut result {
+        if error.span.is_none() {
+            error.span = Some(expression.span());
+        }
+    }
+
+    result
+}
+
+fn eval_expression_kind(expression: &ExpressionNode, env: &Env) -> Object {
+    match expression {
+        ExpressionNode::Integer(integer) => Object::Integer(integer.value),
+        ExpressionNode::Float(float) => Object::Float(float.value),
+        ExpressionNode::StringNode(string) => Object::String(string.value.clone()),
+        ExpressionNode::BooleanNode(boolean) => Object::Boolean(boolean.value),
+        ExpressionNode::IdentifierNode(identifier) => {
+            let value = env.borrow().get(&identifier.value);
+            match value.or_else(|| builtins::lookup(&identifier.value)) {
+                Some(value) => value,
+                None => new_error(format!("identifier not found: {}", identifier.value)),
+            }
+        }
+        ExpressionNode::Prefix(prefix) => {
+            let right = eval_expression(&prefix.right, env);
+            if right.is_error() {
+                return right;
+            }
+            eval_prefix_expression(&prefix.operator, right)
+        }
+        ExpressionNode::Infix(infix) => {
+            let left = eval_expression(&infix.left, env);
+            if left.is_error() {
+                return left;
+            }
+            // The right side of `&&` and `||` only runs when it decides the result.
+            match (infix.operator.as_str(), left.is_truthy()) {
+                ("&&", false) => return Object::Boolean(false),
+                ("||", true) => return Object::Boolean(true),
+                ("&&" | "||", _) => {
+                    let right = eval_expression(&infix.right, env);
+                    if right.is_error() {
+                        return right;
+                    }
+                    return Object::Boolean(right.is_truthy());
+                }
+                _ => {}
+            }
+            let right = eval_expression(&infix.right, env);
+            if right.is_error() {
+                return right;
+            }
+            eval_infix_expression(&infix.operator, left, right)
+        }
+        ExpressionNode::Assign(assign) => {
+            let name = &assign.name.value;
+            let current = match env.borrow().get(name) {
+                Some(current) => current,
+                None if builtins::lookup(name).is_some() => {
+                    return new_error(format!("cannot assign to builtin: {}", name))
+                }
+                None => return new_error(format!("identifier not found: {}", name)),
+            };
+            let value = eval_expression(&assign.value, env);
+            if value.is_error() {
+                return value;
+            }
+            let result = eval_infix_expression(assign.binary_operator(), current, value);
+            if !result.is_error() {
+                env.borrow_mut().assign(name, result.clone());
+            }
+            result
+        }
+        ExpressionNode::IfExpressionNode(if_exp) => eval_if_expression(if_exp, env),
+        ExpressionNode::Function(function) => Object::Function(Rc::new(Function {
+            parameters: function.parameters.clone(),
+            body: function.body.clone(),
+            env: Rc::clone(env),
+        })),
+        ExpressionNode::Call(call) => {
+            let function = eval_expression(&call.function, env);
+            if function.is_error() {
+                return function;
+            }
+
+            match eval_expressions(&call.arguments, env) {
+                Ok(args) => apply_function(function, args),
+                Err(error) => error,
+            }
+        }
+        ExpressionNode::Array(array) => match eval_expressions(&array.elements, env) {
+            Ok(elements) => Object::Array(Rc::new(elements)),
+            Err(error) => error,
+        },
+        ExpressionNode::Index(index) => {
+            let left = eval_expression(&index.left, env);
+            if left.is_error() {
+                return left;
+            }
+            let idx = eval_expression(&index.index, env);
+            if idx.is_error() {
+                return idx;
+            }
+            eval_index_expression(left, idx)
+        }
+        ExpressionNode::Hash(hash) => eval_hash_literal(hash, env),
+    }
+}
+
+fn eval_hash_literal(hash: &HashLiteral, env: &Env) -> Object {
+    let mut pairs = BTreeMap::new();
+
+    for (key_node, value_node) in &hash.pairs {
+        let key = eval_expression(key_node, env);
+        if key.is_error() {
+            return key;
+        }
+
+        let hash_key = match key.hash_key() {
+            Some(hash_key) => hash_key,
+            None => return unusable_hash_key(&key),
+        };
+
+        let value = eval_expression(value_node, env);
+        if value.is_error() {
+            return value;
+        }
+
+        pairs.insert(hash_key, value);
+    }
+
+    Object::Hash(Rc::new(pairs))
+}
+
+fn unusable_hash_key(key: &Object) -> Object {
+    new_error(format!("unusable as hash key: {}", key.type_name()))
+}
+
+/// Evaluates expressions left to right, stopping at the first error.
+fn eval_expressions(expressions: &[ExpressionNode], env: &Env) -> Result<Vec<Object>, Object> {
+    let mut result = Vec::with_capacity(expressions.len());
+
+    for expression in expressions {
+        let evaluated = eval_expression(expression, env);
+        if evaluated.is_error() {
+            return Err(evaluated);
+        }
+        result.push(evaluated);
+    }
+
+    Ok(result)
+}
+
+/// Indexing outside `0..len`, including negative indexes, evaluates to null,
+/// as does looking up a key missing from a hash.
+fn eval_index_expression(left: Object, index: Object) -> Object {
+    match (&left, &index) {
+        (Object::Array(elements), Object::Integer(idx)) => usize::try_from(*idx)
+            .ok()
+            .and_then(|idx| elements.get(idx))
+            .cloned()
+            .unwrap_or(Object::Null),
+        (Object::Hash(pairs), _) => match index.hash_key() {
+            Some(key) => pairs.get(&key).cloned().unwrap_or(Object::Null),
+            None => unusable_hash_key(&index),
+        },
+        _ => new_error(format!(
+            "index operator not supported: {}[{}]",
+            left.type_name(),
+            index.type_name()
+        )),
+    }
+}
+
+fn eval_prefix_expression(operator: &str, right: Object) -> Object {
+    match (operator, &right) {
+        ("!", _) => Object::Boolean(!right.is_truthy()),
+        ("-", Object::Integer(value)) => match value.checked_neg() {
+            Some(negated) => Object::Integer(negated),
+            None => new_error(format!("integer overflow: -{}", value)),
+        },
+        ("-", Object::Float(value)) => Object::Float(-value),
+        _ => new_error(format!(
+            "unknown operator: {}{}",
+            operator,
+            right.type_name()
+        )),
+    }
+}
+
+fn eval_infix_expression(operator: &str, left: Object, right: Object) -> Object {
+    match (&left, &right) {
+        (Object::Integer(l), Object::Integer(r)) => eval_integer_infix_expression(operator, *l, *r),
+        // Mixing an integer with a float gives a float.
+        (Object::Float(l), Object::Float(r)) => eval_float_infix_expression(operator, *l, *r),
+        (Object::Integer(l), Object::Float(r)) => {
+            eval_float_infix_expression(operator, *l as f64, *r)
+        }
+        (Object::Float(l), Object::Integer(r)) => {
+            eval_float_infix_expression(operator, *l, *r as f64)
+        }
+        (Object::String(l), Object::String(r)) => match operator {
+            "+" => Object::String(format!("{}{}", l, r)),
+            "==" => Object::Boolean(l == r),
+            "!=" => Object::Boolean(l != r),
+            _ => new_error(format!("unknown operator: STRING {} STRING", operator)),
+        },
+        (Object::Boolean(l), Object::Boolean(r)) => match operator {
+            "==" => Object::Boolean(l == r),
+            "!=" => Object::Boolean(l != r),
+            _ => new_error(format!(
+                "unknown operator: {} {} {}",
+                left.type_name(),
+                operator,
+                right.type_name()
+            )),
+        },
+        _ if left.type_name() != right.type_name() => new_error(format!(
+            "type mismatch: {} {} {}",
+            left.type_name(),
+            operator,
+            right.type_name()
+        )),
+        _ => new_error(format!(
+            "unknown operator: {} {} {}",
+            left.type_name(),
+            operator,
+            right.type_name()
+        )),
+    }
+}
+
+fn eval_integer_infix_expression(operator: &str, left: i64, right: i64) -> Object {
+    let result = match operator {
+        "+" => left.checked_add(right),
+        "-" => left.checked_sub(right),
+        "*" => left.checked_mul(right),
+        "/" => {
+            if right == 0 {
+                return new_error(String::from("division by zero"));
+            }
+            left.checked_div(right)
+        }
+        "%" => {
+            if right == 0 {
+                return new_error(String::from("division by zero"));
+            }
+            left.checked_rem(right)
+        }
+        "**" => {
+            if right < 0 {
+                return new_error(format!("negative exponent: {} ** {}", left, right));
+            }
+            u32::try_from(right)
+                .ok()
+                .and_then(|exponent| left.checked_pow(exponent))
+        }
+        "<" => return Object::Boolean(left < right),
+        ">" => return Object::Boolean(left > right),
+        "<=" => return Object::Boolean(left <= right),
+        ">=" => return Object::Boolean(left >= right),
+        "==" => return Object::Boolean(left == right),
+        "!=" => return Object::Boolean(left != right),
+        _ => {
+            return new_error(format!("unknown operator: INTEGER {} INTEGER", operator));
+        }
+    };
+
+    match result {
+        Some(value) => Object::Integer(value),
+        None => new_error(format!("integer overflow: {} {} {}", left, operator, right)),
+    }
+}
+
+/// Floats follow IEEE 754, so overflow gives infinity, except that dividing
+/// by zero is an error as it is for integers.
+fn eval_float_infix_expression(operator: &str, left: f64, right: f64) -> Object {
+    let result = match operator {
+        "+" => left + right,
+        "-" => left - right,
+        "*" => left * right,
+        "/" | "%" if right == 0.0 => return new_error(String::from("division by zero")),
+        "/" => left / right,
+        "%" => left % right,
+        "**" => left.powf(right),
+        "<" => return Object::Boolean(left < right),
+        ">" => return Object::Boolean(left > right),
+        "<=" => return Object::Boolean(left <= right),
+        ">=" => return Object::Boolean(left >= right),
+        "==" => return Object::Boolean(left == right),
+        "!=" => return Object::Boolean(left != right),
+        _ => return new_error(format!("unknown operator: FLOAT {} FLOAT", operator)),
+    };
+    Object::Float(result)
+}
+
+fn eval_if_expression(if_exp: &IfExpression, env: &Env) -> Object {
+    let condition = eval_expression(&if_exp.condition, env);
+    if condition.is_error() {
+        return condition;
+    }
+
+    if condition.is_truthy() {
+        eval_block_statement(&if_exp.consequence, env)
+    } else if let Some(alternative) = &if_exp.alternative {
+        eval_block_statement(alternative, env)
+    } else {
+        Object::Null
+    }
+}
+
+/// Calls a user defined or builtin function with evaluated arguments.
+pub fn apply_function(function: Object, args: Vec<Object>) -> Object {
+    match function {
+        Object::Function(function) => {
+            if function.parameters.len() != args.len() {
+                return new_error(format!(
+                    "wrong number of arguments: want={}, got={}",
+                    function.parameters.len(),
+                    args.len()
+                ));
+            }
+
+            // The top level counts as a frame, as it does in the VM.
+            let depth = CALL_DEPTH.get();
+            if depth + 1 >= MAX_FRAMES {
+                return new_error(String::from("stack overflow"));
+            }
+
+            let mut extended = Environment::new_enclosed(Rc::clone(&function.env));
+            for (param, arg) in function.parameters.iter().zip(args) {
+                extended.set(&param.value, arg);
+            }
+
+            CALL_DEPTH.set(depth + 1);
+            let evaluated = eval_block_statement(&function.body, &Rc::new(RefCell::new(extended)));
+            CALL_DEPTH.set(depth);
+            match evaluated {
+                Object::ReturnValue(value) => *value,
+                _ => evaluated,
+            }
+        }
+        Object::Builtin(builtin) => (builtin.func)(args),
+        other => new_error(format!("not a function: {}", other.type_name())),
+    }
+}
+
+fn new_error(message: String) -> Object {
+    Object::Error(RuntimeError::new(message))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::{
+        environment::Environment,
+        lexer::Lexer,
+        object::{HashKey, Object},
+        parser::Parser,
+    };
+
+    use super::eval_program;
+
+    fn test_eval(input: &str) -> Object {
+        let (program, errors) = Parser::new(Lexer::new(input)).parse_program();
+        assert!(errors.is_empty(), "parser errors: {:?}", errors);
+        let env = Rc::new(RefCell::new(Environment::new()));
+        eval_program(&program, &env)
+    }
+
+    #[test]
+    fn test_eval_integer_expression() {
+        let tests = vec![
+            ("5", 5),
+            ("10", 10),
+            ("-5", -5),
+            ("-10", -10),
+            ("5 + 5 + 5 + 5 - 10", 10),
+            ("2 * 2 * 2 * 2 * 2", 32),
+            ("-50 + 100 + -50", 0),
+            ("5 * 2 + 10", 20),
+            ("5 + 2 * 10", 25),
+            ("20 + 2 * -10", 0),
+            ("50 / 2 * 2 + 10", 60),
+            ("2 * (5 + 10)", 30),
+            ("3 * 3 * 3 + 10", 37),
+            ("3 * (3 * 3) + 10", 37),
+            ("(5 + 10 * 2 + 15 / 3) * 2 + -10", 50),
+            ("17 % 5", 2),
+            ("-17 % 5", -2),
+            ("2 ** 10", 1024),
+            ("2 ** 3 ** 2", 512),
+            ("-2 ** 2", -4),
+            ("7 ** 0", 1),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(
+                test_eval(input),
+                Object::Integer(expected),
+                "input {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_eval_float_expression() {
+        let tests = vec![
+            ("2.5", 2.5),
+            ("-2.5", -2.5),
+            ("1.5 + 1.5", 3.0),
+            ("1 + 0.5", 1.5),
+            ("0.5 * 4", 2.0),
+            ("7 / 2.0", 3.5),
+            ("7.5 % 2", 1.5),
+            ("2 ** 0.5 ** 2", 2f64.powf(0.25)),
+            ("4.0 ** -1", 0.25),
+            ("1e308 * 10", f64::INFINITY),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(
+                test_eval(input),
+                Object::Float(expected),
+                "input {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_eval_boolean_expression() {
+        let tests = vec![
+            ("true", true),
+            ("false", false),
+            ("1 < 2", true),
+            ("1 > 2", false),
+            ("1 < 1", false),
+            ("1 == 1", true),
+            ("1 != 1", false),
+            ("1 == 2", false),
+            ("1 != 2", true),
+            ("true == true", true),
+            ("false == false", true),
+            ("true == false", false),
+            ("true != false", true),
+            ("(1 < 2) == true", true),
+            ("(1 > 2) == true", false),
+            ("1 <= 1", true),
+            ("2 <= 1", false),
+            ("1 >= 2", false),
+            ("2 >= 2", true),
+            ("true && 1", true),
+            ("true && false", false),
+            ("false || 0", true),
+            ("false || false", false),
+            ("1 < 2 && 2 < 3", true),
+            ("1 == 1.0", true),
+            ("0.1 + 0.2 == 0.3", false),
+            ("2.5 >= 2", true),
+            ("-1 < -0.5", true),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(
+                test_eval(input),
+                Object::Boolean(expected),
+                "input {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_bang_operator() {
+        let tests = vec![
+            ("!true", false),
+            ("!false", true),
+            ("!5", false),
+            ("!!true", true),
+            ("!!false", false),
+            ("!!5", true),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(
+                test_eval(input),
+                Object::Boolean(expected),
+                "input {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_if_else_expressions() {
+        let tests = vec![
+            ("if (true) { 10 }", Object::Integer(10)),
+            ("if (false) { 10 }", Object::Null),
+            ("if (1) { 10 }", Object::Integer(10)),
+            ("if (1 < 2) { 10 }", Object::Integer(10)),
+            ("if (1 > 2) { 10 }", Object::Null),
+            ("if (1 > 2) { 10 } else { 20 }", Object::Integer(20)),
+            ("if (1 < 2) { 10 } else { 20 }", Object::Integer(10)),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(test_eval(input), expected, "input {}", input);
+        }
+    }
+
+    #[test]
+    fn test_return_statements() {
+        let tests = vec![
+            ("return 10;", 10),
+            ("return 10; 9;", 10),
+            ("return 2 * 5; 9;", 10),
+            ("9; return 2 * 5; 9;", 10),
+            ("if (10 > 1) { if (10 > 1) { return 10; } return 1; }", 10),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(
+                test_eval(input),
+                Object::Integer(expected),
+                "input {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_error_handling() {
+        let tests = vec![
+            ("5 + true;", "type mismatch: INTEGER + BOOLEAN"),
+            ("5 + true; 5;", "type mismatch: INTEGER + BOOLEAN"),
+            ("-true", "unknown operator: -BOOLEAN"),
+            ("true + false;", "unknown operator: BOOLEAN + BOOLEAN"),
+            ("5; true + false; 5", "unknown operator: BOOLEAN + BOOLEAN"),
+            (
+                "if (10 > 1) { true + false; }",
+                "unknown operator: BOOLEAN + BOOLEAN",
+            ),
+            (
+                "if (10 > 1) { if (10 > 1) { return true + false; } return 1; }",
+                "unknown operator: BOOLEAN + BOOLEAN",
+            ),
+            ("foobar", "identifier not found: foobar"),
+            ("10 / 0", "division by zero"),
+            (
+                "9223372036854775807 + 1",
+                "integer overflow: 9223372036854775807 + 1",
+            ),
+            (
+                "let f = fn(x) { x }; f(1, 2)",
+                "wrong number of arguments: want=1, got=2",
+            ),
+            ("5(1)", "not a function: INTEGER"),
+            (r#""Hello" - "World""#, "unknown operator: STRING - STRING"),
+            (r#""a" + 1"#, "type mismatch: STRING + INTEGER"),
+            ("10 % 0", "division by zero"),
+            ("2 ** -1", "negative exponent: 2 ** -1"),
+            ("2 ** 63", "integer overflow: 2 ** 63"),
+            ("true <= false", "unknown operator: BOOLEAN <= BOOLEAN"),
+            ("true && 1 + true", "type mismatch: INTEGER + BOOLEAN"),
+            ("x += 1", "identifier not found: x"),
+            ("len += 1", "cannot assign to builtin: len"),
+            ("let x = 1; x += true", "type mismatch: INTEGER + BOOLEAN"),
+            ("1.5 / 0", "division by zero"),
+            ("1 % 0.0", "division by zero"),
+            ("1.5 + true", "type mismatch: FLOAT + BOOLEAN"),
+            ("{1.5: 1}", "unusable as hash key: FLOAT"),
+        ];
+
+        for (input, expected) in tests {
+            match test_eval(input) {
+                Object::Error(error) => assert_eq!(error.message, expected, "input {}", input),
+                other => panic!("expected error for {}, got {:?}", input, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_error_spans() {
+        let tests = vec![
+            ("1 + foo", "foo"),
+            ("let x = 1;\nx + true", "x + true"),
+            ("let f = fn(a) { -a };\nf(true)", "-a"),
+            ("let f = fn(a) { a };\nf(1, 2)", "f(1, 2)"),
+        ];
+
+        for (input, expected) in tests {
+            match test_eval(input) {
+                Object::Error(error) => {
+                    let span = error.span.expect("error should carry a span");
+                    assert_eq!(&input[span.start..span.end], expected, "input {}", input);
+                }
+                other => panic!("expected error for {}, got {:?}", input, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_string_expressions() {
+        let tests = vec![
+            (
+                r#""Hello World!""#,
+                Object::String("Hello World!".to_string()),
+            ),
+            (
+                r#""Hello" + " " + "World!""#,
+                Object::String("Hello World!".to_string()),
+            ),
+            (r#""a\tb" + "\n""#, Object::String("a\tb\n".to_string())),
+            (r#""abc" == "abc""#, Object::Boolean(true)),
+            (r#""abc" != "abd""#, Object::Boolean(true)),
+            (
+                r#"let greet = fn(name) { "hi " + name }; greet("bob")"#,
+                Object::String("hi bob".to_string()),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(test_eval(input), expected, "input {}", input);
+        }
+    }
+
+    #[test]
+    fn test_array_literals_and_indexing() {
+        let tests = vec![
+            ("[1, 2 * 2, 3 + 3]", "[1, 4, 6]"),
+            ("[]", "[]"),
+            ("[1, 2, 3][0]", "1"),
+            ("[1, 2, 3][1]", "2"),
+            ("let i = 0; [1][i];", "1"),
+            ("[1, 2, 3][1 + 1];", "3"),
+            ("let myArray = [1, 2, 3]; myArray[2];", "3"),
+            (
+                "let myArray = [1, 2, 3]; myArray[0] + myArray[1] + myArray[2];",
+                "6",
+            ),
+            ("[[1, 2], [3]][0][1]", "2"),
+            ("[1, 2, 3][3]", "null"),
+            ("[1, 2, 3][-1]", "null"),
+            ("[][0]", "null"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(test_eval(input).inspect(), expected, "input {}", input);
+        }
+    }
+
+    #[test]
+    fn test_builtin_functions() {
+        let tests = vec![
+            (r#"len("")"#, "0"),
+            (r#"len("four")"#, "4"),
+            (r#"len("héllo")"#, "5"),
+            ("len([1, 2, 3])", "3"),
+            ("len([])", "0"),
+            ("first([1, 2, 3])", "1"),
+            ("first([])", "null"),
+            ("last([1, 2, 3])", "3"),
+            ("last([])", "null"),
+            ("rest([1, 2, 3])", "[2, 3]"),
+            ("rest([1])", "[]"),
+            ("rest([])", "null"),
+            ("push([], 1)", "[1]"),
+            ("let a = [1]; let b = push(a, 2); [a, b]", "[[1], [1, 2]]"),
+            ("let len = fn(x) { 42 }; len([1])", "42"),
+            (
+                r#"
+                let map = fn(arr, f) {
+                    let iter = fn(arr, acc) {
+                        if (len(arr) == 0) { acc } else { iter(rest(arr), push(acc, f(first(arr)))) }
+                    };
+                    iter(arr, []);
+                };
+                map([1, 2, 3], fn(x) { x * 2 });
+                "#,
+                "[2, 4, 6]",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(test_eval(input).inspect(), expected, "input {}", input);
+        }
+
+        let errors = vec![
+            ("len(1)", "argument to `len` not supported, got INTEGER"),
+            (
+                r#"len("one", "two")"#,
+                "wrong number of arguments to `len`: want=1, got=2",
+            ),
+            ("first(1)", "argument to `first` must be ARRAY, got INTEGER"),
+            (
+                "push(1, 1)",
+                "argument to `push` must be ARRAY, got INTEGER",
+            ),
+            ("1[0]", "index operator not supported: INTEGER[INTEGER]"),
+            (r#"[1]["a"]"#, "index operator not supported: ARRAY[STRING]"),
+        ];
+
+        for (input, expected) in errors {
+            match test_eval(input) {
+                Object::Error(error) => assert_eq!(error.message, expected, "input {}", input),
+                other => panic!("expected error for {}, got {:?}", input, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_hash_literals() {
+        let input = r#"
+        let two = "two";
+        {
+            "one": 10 - 9,
+            two: 1 + 1,
+            "thr" + "ee": 6 / 2,
+            4: 4,
+            true: 5,
+            false: 6
+        }
+        "#;
+
+        match test_eval(input) {
+            Object::Hash(pairs) => {
+                let expected = vec![
+                    (HashKey::String("one".to_string()), 1),
+                    (HashKey::String("two".to_string()), 2),
+                    (HashKey::String("three".to_string()), 3),
+                    (HashKey::Integer(4), 4),
+                    (HashKey::Boolean(true), 5),
+                    (HashKey::Boolean(false), 6),
+                ];
+                assert_eq!(pairs.len(), expected.len());
+                for (key, value) in expected {
+                    assert_eq!(
+                        pairs.get(&key),
+                        Some(&Object::Integer(value)),
+                        "key {}",
+                        key
+                    );
+                }
+            }
+            other => panic!("expected hash, got {:?}", other),
+        }
+
+        assert_eq!(
+            test_eval(r#"{"b": 2, 1: [true], "a": 1}"#).inspect(),
+            "{1: [true], a: 1, b: 2}"
+        );
+    }
+
+    #[test]
+    fn test_hash_index_expressions() {
+        let tests = vec![
+            (r#"{"foo": 5}["foo"]"#, "5"),
+            (r#"{"foo": 5}["bar"]"#, "null"),
+            (r#"let key = "foo"; {"foo": 5}[key]"#, "5"),
+            (r#"{}["foo"]"#, "null"),
+            ("{5: 5}[5]", "5"),
+            ("{true: 5}[true]", "5"),
+            ("{false: 5}[false]", "5"),
+            ("{1: 1, 1: 2}[1]", "2"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(test_eval(input).inspect(), expected, "input {}", input);
+        }
+
+        let errors = vec![
+            (
+                r#"{"name": "Monkey"}[fn(x) { x }];"#,
+                "unusable as hash key: FUNCTION",
+            ),
+            ("{[1]: 2}", "unusable as hash key: ARRAY"),
+            ("{{}: 2}", "unusable as hash key: HASH"),
+        ];
+
+        for (input, expected) in errors {
+            match test_eval(input) {
+                Object::Error(error) => assert_eq!(error.message, expected, "input {}", input),
+                other => panic!("expected error for {}, got {:?}", input, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_let_statements() {
+        let tests = vec![
+            ("let a = 5; a;", 5),
+            ("let a = 5 * 5; a;", 25),
+            ("let a = 5; let b = a; b;", 5),
+            ("let a = 5; let b = a; let c = a + b + 5; c;", 15),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(
+                test_eval(input),
+                Object::Integer(expected),
+                "input {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_function_object() {
+        match test_eval("fn(x) { x + 2; };") {
+            Object::Function(function) => {
+                assert_eq!(function.parameters.len(), 1);
+                assert_eq!(function.parameters[0].value, "x");
+                assert_eq!(function.inspect(), "fn(x) { (x + 2); }");
+            }
+            other => panic!("object is not a function: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_function_application() {
+        let tests = vec![
+            ("let identity = fn(x) { x; }; identity(5);", 5),
+            ("let identity = fn(x) { return x; }; identity(5);", 5),
+            ("let double = fn(x) { x * 2; }; double(5);", 10),
+            ("let add = fn(x, y) { x + y; }; add(5, 5);", 10),
+            ("let add = fn(x, y) { x + y; }; add(5 + 5, add(5, 5));", 20),
+            ("fn(x) { x; }(5)", 5),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(
+                test_eval(input),
+                Object::Integer(expected),
+                "input {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_closures() {
+        let input = r#"
+        let newAdder = fn(x) {
+            fn(y) { x + y };
+        };
+        let addTwo = newAdder(2);
+        addTwo(2);
+        "#;
+
+        assert_eq!(test_eval(input), Object::Integer(4));
+    }
+
+    #[test]
+    fn test_short_circuit() {
+        let tests = vec![
+            ("false && missing", Object::Boolean(false)),
+            ("true || missing", Object::Boolean(true)),
+            ("let x = 1; false && (x += 1); x", Object::Integer(1)),
+            ("let x = 1; true && (x += 1); x", Object::Integer(2)),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(test_eval(input), expected, "input {}", input);
+        }
+    }
+
+    #[test]
+    fn test_compound_assignment() {
+        let tests = vec![
+            ("let x = 5; x += 2; x", 7),
+            ("let x = 5; x -= 2", 3),
+            ("let x = 5; x *= 2; x /= 5; x", 2),
+            ("let a = 1; let b = 2; a += b += 3; a * 10 + b", 65),
+            (
+                "let sum = fn(n) { let total = 0; let i = 0; \
+              let go = fn() { if (i < n) { i += 1; total += i; go() } }; go(); total }; sum(4)",
+                10,
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(
+                test_eval(input),
+                Object::Integer(expected),
+                "input {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_recursive_function() {
+        let input = r#"
+        let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } };
+        fib(15);
+        "#;
+
+        assert_eq!(test_eval(input), Object::Integer(610));
+    }
+}
@@ -0,0 +1,268 @@
+use std::{
+    cell::RefCell,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::{
+    ast::Program,
+    diagnostics::Diagnostic,
+    environment::{Env, Environment},
+    evaluator,
+    lexer::Lexer,
+    native::{self, NativeFunction},
+    object::{Object, RuntimeError},
+    parser::{ParseError, Parser},
+};
+
+/// Everything that can go wrong running code through an [`Interpreter`].
+#[derive(Debug)]
+pub enum Error {
+    /// The file passed to [`Interpreter::eval_file`] couldn't be read.
+    Io { path: PathBuf, error: io::Error },
+    /// The source has syntax errors. Nothing was evaluated. Every error the
+    /// parser found is included, in source order.
+    Parse(Vec<ParseError>),
+    /// Evaluation stopped with an error. Bindings made before the failing
+    /// statement are kept.
+    Runtime(RuntimeError),
+}
+
+impl Error {
+    /// One diagnostic per underlying error, ready for `diagnostics::Renderer`.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        match self {
+            Error::Io { .. } => vec![Diagnostic::error(self.to_string(), None)],
+            Error::Parse(errors) => errors.iter().map(Diagnostic::from).collect(),
+            Error::Runtime(error) => vec![Diagnostic::from(error)],
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, error } => write!(f, "could not read {}: {}", path.display(), error),
+            Error::Parse(errors) => {
+                let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}", messages.join("; "))
+            }
+            Error::Runtime(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Runs Monkey code against a global environment that persists between
+/// calls, so later code sees the bindings made by earlier code.
+///
+/// ```
+/// use interpreter::{object::Object, Interpreter};
+///
+/// let mut interpreter = Interpreter::new();
+/// interpreter.set_global("base", Object::Integer(40));
+/// interpreter.eval_str("let add = fn(a, b) { base + a + b };").unwrap();
+///
+/// let result = interpreter
+///     .call_function("add", vec![Object::Integer(1), Object::Integer(1)])
+///     .unwrap();
+/// assert_eq!(result, Object::Integer(42));
+/// ```
+pub struct Interpreter {
+    env: Env,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self {
+            env: Rc::new(RefCell::new(Environment::new())),
+        }
+    }
+
+    /// Evaluates a program and returns the value of its last statement, or
+    /// `Object::Null` when that statement is a `let`.
+    pub fn eval_str(&mut self, source: &str) -> Result<Object, Error> {
+        let (program, errors) = Parser::new(Lexer::new(source)).parse_program();
+        if !errors.is_empty() {
+            return Err(Error::Parse(errors));
+        }
+        self.eval_program(&program)
+    }
+
+    /// Evaluates an already parsed program, for callers that inspect the
+    /// syntax tree first.
+    pub fn eval_program(&mut self, program: &Program) -> Result<Object, Error> {
+        match evaluator::eval_program(program, &self.env) {
+            Object::Error(error) => Err(Error::Runtime(error)),
+            value => Ok(value),
+        }
+    }
+
+    pub fn eval_file(&mut self, path: impl AsRef<Path>) -> Result<Object, Error> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(|error| Error::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        self.eval_str(&source)
+    }
+
+    /// Binds `name` in the global environment, replacing any earlier binding.
+    pub fn set_global(&mut self, name: &str, value: Object) {
+        self.env.borrow_mut().set(name, value);
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Object> {
+        self.env.borrow().get(name)
+    }
+
+    /// Every global binding, sorted by name. Builtins are only included when
+    /// shadowed or registered with `register_function`.
+    pub fn globals(&self) -> Vec<(String, Object)> {
+        let mut globals: Vec<(String, Object)> = self
+            .env
+            .borrow()
+            .bindings()
+            .map(|(name, obj)| (name.to_string(), obj.clone()))
+            .collect();
+        globals.sort_by(|a, b| a.0.cmp(&b.0));
+        globals
+    }
+
+    /// Makes a Rust closure callable from Monkey code as a global function.
+    /// Arguments are converted with `native::FromObject` after checking
+    /// their count, and the result with `native::IntoObject`, so returning
+    /// `Err` raises a Monkey runtime error.
+    ///
+    /// ```
+    /// use interpreter::{object::Object, Interpreter};
+    ///
+    /// let mut interpreter = Interpreter::new();
+    /// interpreter.register_function("repeat", |s: String, n: i64| s.repeat(n as usize));
+    ///
+    /// let result = interpreter.eval_str(r#"repeat("ab", 3)"#).unwrap();
+    /// assert_eq!(result, Object::String("ababab".to_string()));
+    /// ```
+    pub fn register_function<Args>(
+        &mut self,
+        name: &str,
+        func: impl NativeFunction<Args> + 'static,
+    ) {
+        self.set_global(name, Object::Builtin(native::builtin(name, func)));
+    }
+
+    /// Calls the function bound to `name`, which may be a builtin, with
+    /// already evaluated arguments.
+    pub fn call_function(&mut self, name: &str, args: Vec<Object>) -> Result<Object, Error> {
+        let function = self
+            .get_global(name)
+            .or_else(|| crate::builtins::lookup(name));
+        let function = function.ok_or_else(|| {
+            Error::Runtime(RuntimeError::new(format!("identifier not found: {}", name)))
+        })?;
+
+        match evaluator::apply_function(function, args) {
+            Object::Error(error) => Err(Error::Runtime(error)),
+            value => Ok(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::object::Object;
+
+    use super::{Error, Interpreter};
+
+    #[test]
+    fn test_state_persists_between_calls() {
+        let mut interpreter = Interpreter::new();
+        interpreter.eval_str("let a = 5;").unwrap();
+        interpreter
+            .eval_str("let double = fn(x) { x * 2 };")
+            .unwrap();
+
+        assert_eq!(
+            interpreter.eval_str("double(a)").unwrap(),
+            Object::Integer(10)
+        );
+        assert_eq!(interpreter.get_global("a"), Some(Object::Integer(5)));
+        assert_eq!(interpreter.get_global("b"), None);
+
+        let names: Vec<String> = interpreter.globals().into_iter().map(|(n, _)| n).collect();
+        assert_eq!(names, vec!["a", "double"]);
+    }
+
+    #[test]
+    fn test_call_function() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .eval_str("let greet = fn(name) { \"hi \" + name };")
+            .unwrap();
+
+        let greeting = interpreter
+            .call_function("greet", vec![Object::String("bob".to_string())])
+            .unwrap();
+        assert_eq!(greeting, Object::String("hi bob".to_string()));
+
+        let len = interpreter
+            .call_function("len", vec![Object::String("four".to_string())])
+            .unwrap();
+        assert_eq!(len, Object::Integer(4));
+
+        let errors = [
+            interpreter.call_function("missing", vec![]),
+            interpreter.call_function("greet", vec![]),
+        ];
+        let messages: Vec<String> = errors
+            .iter()
+            .map(|e| e.as_ref().unwrap_err().to_string())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "identifier not found: missing",
+                "wrong number of arguments: want=1, got=0"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut interpreter = Interpreter::new();
+
+        match interpreter.eval_str("let = 1; let b 2;") {
+            Err(Error::Parse(errors)) => assert_eq!(errors.len(), 2),
+            other => panic!("expected parse errors, got {:?}", other),
+        }
+        match interpreter.eval_str("let a = 1; a + true; let b = 2;") {
+            Err(error @ Error::Runtime(_)) => {
+                assert_eq!(error.to_string(), "type mismatch: INTEGER + BOOLEAN");
+                assert!(error.diagnostics()[0].span.is_some());
+            }
+            other => panic!("expected runtime error, got {:?}", other),
+        }
+        assert_eq!(interpreter.get_global("a"), Some(Object::Integer(1)));
+        assert_eq!(interpreter.get_global("b"), None);
+
+        match interpreter.eval_file("does/not/exist.mk") {
+            Err(Error::Io { path, .. }) => assert_eq!(path.to_str(), Some("does/not/exist.mk")),
+            other => panic!("expected io error, got {:?}", other),
+        }
+    }
+}
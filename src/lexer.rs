@@ -1,119 +1,965 @@
+use std::{borrow::Cow, collections::VecDeque, fmt};
 
+use crate::token::{Span, Token, TokenKind};
 
-use crate::token::{Token, TokenKind};
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexError {
+    IllegalCharacter {
+        ch: char,
+        span: Span,
+    },
+    /// The input ended before the closing `"`. The span runs from the opening quote.
+    UnterminatedString {
+        span: Span,
+    },
+    /// A `/*` without its matching `*/`. The span covers the opening `/*`.
+    UnterminatedComment {
+        span: Span,
+    },
+    /// A `\` escape the lexer doesn't know, including malformed `\u{...}` escapes.
+    InvalidEscape {
+        sequence: String,
+        span: Span,
+    },
+    /// A number with no digits after its prefix or exponent, or with digits
+    /// or letters its base doesn't allow, such as `0x`, `1e+` or `0b12`.
+    InvalidNumber {
+        literal: String,
+        span: Span,
+    },
+}
 
-pub struct Lexer {
-    input: Vec<char>,
-    position: usize,
-    read_position: usize,
-    ch: char,
+impl LexError {
+    pub fn span(&self) -> Span {
+        match self {
+            LexError::IllegalCharacter { span, .. } => *span,
+            LexError::UnterminatedString { span } => *span,
+            LexError::UnterminatedComment { span } => *span,
+            LexError::InvalidEscape { span, .. } => *span,
+            LexError::InvalidNumber { span, .. } => *span,
+        }
+    }
 }
 
-impl Lexer {
-    pub fn new(input: &str) -> Self {
-        let mut lex = Lexer {
-            input: input.chars().collect(),
-            position: 0,
-            read_position: 0,
-            ch: Default::default(),
-        };
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexError::IllegalCharacter { ch, .. } => write!(f, "unexpected character `{}`", ch),
+            LexError::UnterminatedString { .. } => write!(f, "unterminated string literal"),
+            LexError::UnterminatedComment { .. } => write!(f, "unterminated block comment"),
+            LexError::InvalidEscape { sequence, .. } => {
+                write!(f, "invalid escape sequence `{}`", sequence)
+            }
+            LexError::InvalidNumber { literal, .. } => {
+                write!(f, "invalid number literal `{}`", literal)
+            }
+        }
+    }
+}
 
-        lex.read_char();
+/// A token whose literal borrows from the source where it can. Only string
+/// literals with escapes need an owned literal, holding the resolved text.
+#[derive(PartialEq, Debug, Default, Clone)]
+pub struct BorrowedToken<'a> {
+    pub kind: TokenKind,
+    pub literal: Cow<'a, str>,
+    pub span: Span,
+}
 
-        lex
+impl BorrowedToken<'_> {
+    pub fn into_owned(self) -> Token {
+        Token {
+            kind: self.kind,
+            literal: self.literal.into_owned(),
+            span: self.span,
+        }
     }
 
-    fn peek_char(&self) -> char {
-        if self.read_position >= self.input.len() {
-            '\0'
-        } else {
-            self.input[self.read_position]
+    /// Copies the token out of the source, for keeping it beyond the source's
+    /// lifetime.
+    pub fn to_token(&self) -> Token {
+        Token {
+            kind: self.kind.clone(),
+            literal: self.literal.to_string(),
+            span: self.span,
         }
     }
+}
 
-    fn read_char(&mut self) {
-        if self.read_position >= self.input.len() {
-            self.ch = '\0';
+/// Splits source text into tokens. The input is scanned as bytes in place,
+/// decoding UTF-8 only where non-ASCII characters can appear.
+pub struct Lexer<'a> {
+    input: &'a str,
+    // Byte offset, line and column of the next character to read.
+    offset: usize,
+    line: usize,
+    column: usize,
+    errors: Vec<LexError>,
+    /// Text of the `///` comments right before the token last returned.
+    doc: Option<Cow<'a, str>>,
+    /// Set once the iterator has yielded `TokenKind::Eof`.
+    finished: bool,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Lexer {
+            input,
+            offset: 0,
+            line: 1,
+            column: 1,
+            errors: vec![],
+            doc: None,
+            finished: false,
+        }
+    }
+
+    fn peek_byte(&self) -> Option<u8> {
+        self.input.as_bytes().get(self.offset).copied()
+    }
+
+    fn peek_char(&self) -> Option<char> {
+        match self.peek_byte()? {
+            byte if byte.is_ascii() => Some(byte as char),
+            _ => self.input[self.offset..].chars().next(),
+        }
+    }
+
+    /// Moves past the next character and returns it.
+    fn read_char(&mut self) -> Option<char> {
+        let ch = self.peek_char()?;
+        self.offset += ch.len_utf8();
+        if ch == '\n' {
+            self.line += 1;
+            self.column = 1;
         } else {
-            self.ch = self.input[self.read_position]
+            self.column += 1;
         }
-        self.position = self.read_position;
-        self.read_position += 1;
+        Some(ch)
     }
 
-    fn should_roll_fwd(tk: &TokenKind) -> bool {
-        tk == &TokenKind::Eq || tk == &TokenKind::NotEq
+    /// Moves past bytes matching `pred`, which never matches a line break or
+    /// part of a multi-byte character.
+    fn read_bytes_while(&mut self, pred: impl Fn(u8) -> bool) {
+        while self.peek_byte().is_some_and(&pred) {
+            self.offset += 1;
+            self.column += 1;
+        }
+    }
+
+    /// Moves past `count` bytes known to be ASCII and not newlines.
+    fn skip_bytes(&mut self, count: usize) {
+        self.offset += count;
+        self.column += count;
+    }
+
+    /// Hands over the errors found since the last call. Lexing goes on after
+    /// each one: a bad character, string or number still produces a token,
+    /// usually `TokenKind::Illegal`, while an unterminated block comment
+    /// swallows the rest of the input and produces none.
+    pub fn take_errors(&mut self) -> Vec<LexError> {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Hands over the text of the `///` comments right before the token
+    /// last returned, a line per comment.
+    pub fn take_doc(&mut self) -> Option<Cow<'a, str>> {
+        self.doc.take()
     }
 
     pub fn next_token(&mut self) -> Token {
-        self.skip_whitespace();
-        let next_char = &self.peek_char().to_string();
-        let kind = TokenKind::to_tok(&self.ch.to_string(), next_char);
-        let literal = kind.to_literal(self.ch);
-        let tok = Token { kind, literal };
+        self.next_borrowed().into_owned()
+    }
 
-        if Self::should_roll_fwd(&tok.kind) {
-            self.read_char();
+    /// Like `next_token`, without copying the literal out of the source.
+    pub fn next_borrowed(&mut self) -> BorrowedToken<'a> {
+        self.doc = self.skip_trivia();
+        self.scan_token()
+    }
+
+    fn scan_token(&mut self) -> BorrowedToken<'a> {
+        let (start, line, column) = (self.offset, self.line, self.column);
+
+        let Some(byte) = self.peek_byte() else {
+            return self.token(TokenKind::Eof, Cow::Borrowed("\0"), start, line, column);
+        };
+
+        if byte == b'"' {
+            return self.read_string(start, line, column);
+        }
+        if Self::is_letter(byte) {
+            self.read_bytes_while(Self::is_letter);
+            let literal = &self.input[start..self.offset];
+            let kind = TokenKind::lookup_ident(literal);
+            return self.token(kind, Cow::Borrowed(literal), start, line, column);
         }
-        // Clean this later
-        if tok.kind == TokenKind::Illegal && Self::is_letter(self.ch) {
-            let literal = self.read_identifier();
-            let kind = TokenKind::lookup_ident(&literal);
-            return Token { kind, literal };
+        if byte.is_ascii_digit() {
+            return self.read_number(start, line, column);
         }
 
-        if tok.kind == TokenKind::Illegal && Self::is_num(self.ch) {
-            let literal = self.read_num();
-            let kind = TokenKind::Int;
-            return Token { kind, literal };
+        let Some((kind, len)) = TokenKind::lookup_operator(&self.input[start..]) else {
+            let ch = self.read_char().unwrap_or_default();
+            let literal = Cow::Borrowed(&self.input[start..self.offset]);
+            let token = self.token(TokenKind::Illegal, literal, start, line, column);
+            self.errors.push(LexError::IllegalCharacter {
+                ch,
+                span: token.span,
+            });
+            return token;
+        };
+
+        self.skip_bytes(len);
+        let literal = Cow::Borrowed(&self.input[start..self.offset]);
+        self.token(kind, literal, start, line, column)
+    }
+
+    /// Reads a `"` delimited string starting at the opening quote. The
+    /// literal borrows the text between the quotes unless escapes had to be
+    /// resolved.
+    fn read_string(&mut self, start: usize, line: usize, column: usize) -> BorrowedToken<'a> {
+        self.read_char();
+        // Only allocated once the first escape is found.
+        let mut resolved: Option<String> = None;
+        let mut plain_from = self.offset;
+
+        loop {
+            let Some(byte) = self.peek_byte() else {
+                let span = Span::new(start, self.offset, line, column);
+                self.errors.push(LexError::UnterminatedString { span });
+                let literal = Cow::Borrowed(&self.input[start..]);
+                return self.token(TokenKind::Illegal, literal, start, line, column);
+            };
+
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let value = resolved.get_or_insert_with(String::new);
+                    value.push_str(&self.input[plain_from..self.offset]);
+                    if let Some(ch) = self.read_escape() {
+                        value.push(ch);
+                    }
+                    plain_from = self.offset;
+                }
+                _ => {
+                    self.read_char();
+                }
+            }
         }
+
+        let rest = &self.input[plain_from..self.offset];
+        let literal = match resolved {
+            Some(mut value) => {
+                value.push_str(rest);
+                Cow::Owned(value)
+            }
+            None => Cow::Borrowed(rest),
+        };
         self.read_char();
 
-        tok
+        self.token(TokenKind::String, literal, start, line, column)
     }
 
-    fn skip_whitespace(&mut self) {
-        while self.ch.is_ascii_whitespace() {
+    /// Reads the escape sequence starting at the next `\\`. Unknown escapes
+    /// are reported and dropped.
+    fn read_escape(&mut self) -> Option<char> {
+        let (start, line, column) = (self.offset, self.line, self.column);
+        self.read_char();
+
+        if self.peek_byte() != Some(b'u') {
+            let escaped = match self.peek_char() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('"') => '"',
+                Some('\\') => '\\',
+                // Leave the end of input for `read_string` to report.
+                None => return None,
+                Some(ch) => {
+                    self.read_char();
+                    let span = Span::new(start, self.offset, line, column);
+                    self.errors.push(LexError::InvalidEscape {
+                        sequence: format!("\\{}", ch),
+                        span,
+                    });
+                    return None;
+                }
+            };
+            self.read_char();
+            return Some(escaped);
+        }
+
+        self.read_char();
+        let mut closed = false;
+        let mut digits = "";
+
+        if self.peek_byte() == Some(b'{') {
             self.read_char();
+            let digits_start = self.offset;
+            self.read_bytes_while(|byte| byte.is_ascii_hexdigit());
+            digits = &self.input[digits_start..self.offset];
+            if self.peek_byte() == Some(b'}') {
+                self.read_char();
+                closed = true;
+            }
         }
+
+        let escaped = if closed && digits.len() <= 6 {
+            u32::from_str_radix(digits, 16)
+                .ok()
+                .and_then(char::from_u32)
+        } else {
+            None
+        };
+
+        if escaped.is_none() {
+            let span = Span::new(start, self.offset, line, column);
+            self.errors.push(LexError::InvalidEscape {
+                sequence: self.input[start..self.offset].to_string(),
+                span,
+            });
+        }
+        escaped
     }
 
-    fn is_letter(ch: char) -> bool {
-        ch.is_ascii_alphabetic() || ch == '_'
+    /// Builds a token that started at the given position and ends at the current one.
+    fn token(
+        &self,
+        kind: TokenKind,
+        literal: Cow<'a, str>,
+        start: usize,
+        line: usize,
+        column: usize,
+    ) -> BorrowedToken<'a> {
+        BorrowedToken {
+            kind,
+            literal,
+            span: Span::new(start, self.offset, line, column),
+        }
     }
 
-    fn is_num(ch: char) -> bool {
-        ch.is_numeric()
+    /// Skips whitespace and comments. Returns the text of the `///` doc
+    /// comments among them, a line per comment, which belongs to the next token.
+    fn skip_trivia(&mut self) -> Option<Cow<'a, str>> {
+        let mut doc: Option<Cow<'a, str>> = None;
+
+        loop {
+            self.skip_whitespace();
+            let rest = &self.input.as_bytes()[self.offset..];
+
+            if rest.starts_with(b"//") {
+                let start = self.offset;
+                self.skip_line();
+                let Some(text) = doc_comment_text(&self.input[start..self.offset]) else {
+                    continue;
+                };
+                match &mut doc {
+                    Some(doc) => {
+                        let doc = doc.to_mut();
+                        doc.push('\n');
+                        doc.push_str(text);
+                    }
+                    None => doc = Some(Cow::Borrowed(text)),
+                }
+            } else if rest.starts_with(b"/*") {
+                self.skip_block_comment();
+            } else {
+                return doc;
+            }
+        }
     }
 
-    fn read_num(&mut self) -> String {
-        let mut num = String::new();
+    /// Moves to the end of the line, leaving the line break.
+    fn skip_line(&mut self) {
+        while let Some(byte) = self.peek_byte() {
+            if byte == b'\n' {
+                break;
+            }
+            self.offset += 1;
+            // Continuation bytes of a multi-byte character don't take a column.
+            if byte & 0xc0 != 0x80 {
+                self.column += 1;
+            }
+        }
+    }
 
-        while Self::is_num(self.ch) {
-            num.push(self.ch);
-            self.read_char();
+    /// Skips a `/* */` comment starting at the cursor. Comments nest, so
+    /// code containing comments can be commented out.
+    fn skip_block_comment(&mut self) {
+        let (start, line, column) = (self.offset, self.line, self.column);
+        let mut depth = 0;
+
+        loop {
+            let rest = &self.input.as_bytes()[self.offset..];
+            if rest.starts_with(b"/*") || rest.starts_with(b"*/") {
+                depth = if rest[0] == b'/' {
+                    depth + 1
+                } else {
+                    depth - 1
+                };
+                self.offset += 2;
+                self.column += 2;
+                if depth == 0 {
+                    return;
+                }
+            } else if self.read_char().is_none() {
+                let span = Span::new(start, start + 2, line, column);
+                self.errors.push(LexError::UnterminatedComment { span });
+                return;
+            }
         }
+    }
 
-        num
+    fn skip_whitespace(&mut self) {
+        while let Some(byte) = self.peek_byte() {
+            match byte {
+                b'\n' => {
+                    self.offset += 1;
+                    self.line += 1;
+                    self.column = 1;
+                }
+                byte if byte.is_ascii_whitespace() => {
+                    self.offset += 1;
+                    self.column += 1;
+                }
+                _ => break,
+            }
+        }
     }
 
-    fn read_identifier(&mut self) -> String {
-        let mut ident = String::new();
+    fn is_letter(byte: u8) -> bool {
+        byte.is_ascii_alphabetic() || byte == b'_'
+    }
 
-        while Self::is_letter(self.ch) {
-            ident.push(self.ch);
-            self.read_char();
+    /// Reads a number starting at its first digit: an integer with an
+    /// optional `0x`, `0b` or `0o` prefix, or a decimal float with a fraction,
+    /// an exponent or both. Digits may be separated by `_`. Letters and
+    /// digits running on from the number are kept in the same token, so
+    /// `0b12` is reported as one bad literal rather than lexed as `0b1` `2`.
+    fn read_number(&mut self, start: usize, line: usize, column: usize) -> BorrowedToken<'a> {
+        let radix = match self.input.as_bytes().get(start..start + 2) {
+            Some(b"0x") => 16,
+            Some(b"0b") => 2,
+            Some(b"0o") => 8,
+            _ => 10,
+        };
+
+        let mut kind = TokenKind::Int;
+        let mut valid = true;
+        if radix != 10 {
+            self.skip_bytes(2);
+            valid = self.read_digits(radix);
+        } else {
+            self.read_digits(10);
+            let bytes = self.input.as_bytes();
+            if self.peek_byte() == Some(b'.')
+                && bytes.get(self.offset + 1).is_some_and(u8::is_ascii_digit)
+            {
+                kind = TokenKind::Float;
+                self.skip_bytes(1);
+                self.read_digits(10);
+            }
+            if matches!(self.peek_byte(), Some(b'e' | b'E')) {
+                kind = TokenKind::Float;
+                self.skip_bytes(1);
+                if matches!(self.peek_byte(), Some(b'+' | b'-')) {
+                    self.skip_bytes(1);
+                }
+                valid = self.read_digits(10);
+            }
+        }
+
+        if self
+            .peek_byte()
+            .is_some_and(|byte| byte.is_ascii_alphanumeric() || byte == b'_')
+        {
+            self.read_bytes_while(|byte| byte.is_ascii_alphanumeric() || byte == b'_');
+            valid = false;
+        }
+
+        let literal = &self.input[start..self.offset];
+        let token = self.token(kind, Cow::Borrowed(literal), start, line, column);
+        if valid {
+            return token;
+        }
+        self.errors.push(LexError::InvalidNumber {
+            literal: literal.to_string(),
+            span: token.span,
+        });
+        BorrowedToken {
+            kind: TokenKind::Illegal,
+            ..token
+        }
+    }
+
+    /// Reads digits of the given base and `_` separators, returning whether
+    /// there was at least one digit.
+    fn read_digits(&mut self, radix: u32) -> bool {
+        let digits_from = self.offset;
+        self.read_bytes_while(|byte| byte == b'_' || (byte as char).is_digit(radix));
+        self.input[digits_from..self.offset]
+            .bytes()
+            .any(|byte| byte != b'_')
+    }
+}
+
+/// The text of a `///` comment without the slashes and the space after
+/// them. `////` starts an ordinary comment, as in Rust.
+fn doc_comment_text(comment: &str) -> Option<&str> {
+    if comment.starts_with("////") {
+        return None;
+    }
+    let text = comment.strip_prefix("///")?.trim_end_matches('\r');
+    Some(text.strip_prefix(' ').unwrap_or(text))
+}
+
+/// Yields every token up to and including `TokenKind::Eof`, then stops,
+/// unlike `next_token` which keeps returning `Eof`.
+impl Iterator for Lexer<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        if self.finished {
+            return None;
+        }
+        let token = self.next_token();
+        self.finished = token.kind == TokenKind::Eof;
+        Some(token)
+    }
+}
+
+/// Tokens with lookahead as far as needed. Errors are kept with the token
+/// that caused them and only handed out by `take_errors` once that token is
+/// the next one, so looking ahead doesn't report errors early. Tokens borrow
+/// from the source, so only those kept in the syntax tree are copied.
+pub struct TokenStream<'a> {
+    lexer: Lexer<'a>,
+    /// Tokens lexed ahead with their doc comments, never empty. The first is
+    /// the next token.
+    buffer: VecDeque<(BorrowedToken<'a>, Option<Cow<'a, str>>, Vec<LexError>)>,
+    errors: Vec<LexError>,
+    /// Doc comments of the token last returned.
+    doc: Option<Cow<'a, str>>,
+}
+
+impl<'a> TokenStream<'a> {
+    pub fn new(lexer: Lexer<'a>) -> Self {
+        let mut stream = Self {
+            lexer,
+            buffer: VecDeque::new(),
+            errors: vec![],
+            doc: None,
+        };
+        stream.advance_to_next();
+        stream
+    }
+
+    /// The next token, without consuming it.
+    pub fn peek(&self) -> &BorrowedToken<'a> {
+        &self.buffer[0].0
+    }
+
+    /// The token `n` positions after the next one, so `peek_nth(0)` is
+    /// `peek()`. Past the end of input this is `TokenKind::Eof`.
+    pub fn peek_nth(&mut self, n: usize) -> &BorrowedToken<'a> {
+        self.fill(n);
+        &self.buffer[n].0
+    }
+
+    pub fn next_token(&mut self) -> BorrowedToken<'a> {
+        let (token, doc, _) = self.buffer.pop_front().expect("buffer is never empty");
+        self.doc = doc;
+        self.advance_to_next();
+        token
+    }
+
+    /// Hands over the errors of the tokens up to and including the next one.
+    pub fn take_errors(&mut self) -> Vec<LexError> {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Hands over the text of the `///` comments right before the token
+    /// last returned by `next_token`.
+    pub fn take_doc(&mut self) -> Option<String> {
+        self.doc.take().map(Cow::into_owned)
+    }
+
+    /// Lexes until the buffer holds the token at index `n`.
+    fn fill(&mut self, n: usize) {
+        while self.buffer.len() <= n {
+            let token = self.lexer.next_borrowed();
+            let doc = self.lexer.take_doc();
+            let errors = self.lexer.take_errors();
+            self.buffer.push_back((token, doc, errors));
         }
+    }
 
-        return ident;
+    fn advance_to_next(&mut self) {
+        self.fill(0);
+        let (_, _, errors) = &mut self.buffer[0];
+        self.errors.append(errors);
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::token::{Token, TokenKind};
+    use std::borrow::Cow;
+
+    use crate::token::{Span, Token, TokenKind};
+
+    use super::{LexError, Lexer, TokenStream};
+
+    #[test]
+    fn test_brackets() {
+        let kinds: Vec<TokenKind> = Lexer::new("[1, 2][0]").map(|token| token.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::Lbracket,
+                TokenKind::Int,
+                TokenKind::Comma,
+                TokenKind::Int,
+                TokenKind::Rbracket,
+                TokenKind::Lbracket,
+                TokenKind::Int,
+                TokenKind::Rbracket,
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hash_tokens() {
+        let kinds: Vec<TokenKind> = Lexer::new(r#"{"foo": 1}"#)
+            .map(|token| token.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::Lbrace,
+                TokenKind::String,
+                TokenKind::Colon,
+                TokenKind::Int,
+                TokenKind::Rbrace,
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_operators() {
+        let input = "a<=b>=c&&d||e%f**g+=1-=2*=3/=4->=>< = ! ***";
+        let tokens: Vec<(TokenKind, String)> = Lexer::new(input)
+            .filter(|token| !matches!(token.kind, TokenKind::Ident | TokenKind::Int))
+            .map(|token| (token.kind, token.literal))
+            .collect();
+        let expected = [
+            (TokenKind::LtEq, "<="),
+            (TokenKind::GtEq, ">="),
+            (TokenKind::And, "&&"),
+            (TokenKind::Or, "||"),
+            (TokenKind::Percent, "%"),
+            (TokenKind::Power, "**"),
+            (TokenKind::PlusAssign, "+="),
+            (TokenKind::MinusAssign, "-="),
+            (TokenKind::AsteriskAssign, "*="),
+            (TokenKind::SlashAssign, "/="),
+            (TokenKind::Arrow, "->"),
+            (TokenKind::FatArrow, "=>"),
+            (TokenKind::Lt, "<"),
+            (TokenKind::Assign, "="),
+            (TokenKind::Bang, "!"),
+            (TokenKind::Power, "**"),
+            (TokenKind::Asterisk, "*"),
+            (TokenKind::Eof, "\0"),
+        ]
+        .map(|(kind, literal)| (kind, literal.to_string()));
+        assert_eq!(tokens, expected);
+
+        let mut lexer = Lexer::new("a & b | c");
+        let kinds: Vec<TokenKind> = lexer.by_ref().map(|token| token.kind).collect();
+        assert_eq!(kinds[1], TokenKind::Illegal);
+        assert_eq!(kinds[3], TokenKind::Illegal);
+        assert_eq!(lexer.take_errors().len(), 2);
+    }
+
+    #[test]
+    fn test_numbers() {
+        let input = "0 42 1_000 0xff_FF 0b1010 0o17 3.14 1e-9 2.5E+3 1_0.0_1 7.abs";
+        let tokens: Vec<(TokenKind, String)> = Lexer::new(input)
+            .map(|token| (token.kind, token.literal))
+            .collect();
+        let expected = [
+            (TokenKind::Int, "0"),
+            (TokenKind::Int, "42"),
+            (TokenKind::Int, "1_000"),
+            (TokenKind::Int, "0xff_FF"),
+            (TokenKind::Int, "0b1010"),
+            (TokenKind::Int, "0o17"),
+            (TokenKind::Float, "3.14"),
+            (TokenKind::Float, "1e-9"),
+            (TokenKind::Float, "2.5E+3"),
+            (TokenKind::Float, "1_0.0_1"),
+            // A `.` only starts a fraction when a digit follows it.
+            (TokenKind::Int, "7"),
+            (TokenKind::Illegal, "."),
+            (TokenKind::Ident, "abs"),
+            (TokenKind::Eof, "\0"),
+        ]
+        .map(|(kind, literal)| (kind, literal.to_string()));
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn test_invalid_numbers() {
+        for (input, literal) in [
+            ("0x", "0x"),
+            ("0b_", "0b_"),
+            ("0b102", "0b102"),
+            ("0o8", "0o8"),
+            ("12abc", "12abc"),
+            ("1e", "1e"),
+            ("1.5e+;", "1.5e+"),
+            ("0xfg", "0xfg"),
+        ] {
+            let mut lexer = Lexer::new(input);
+            let token = lexer.next_token();
+            assert_eq!(token.kind, TokenKind::Illegal, "input {}", input);
+            assert_eq!(token.literal, literal, "input {}", input);
+            assert_eq!(
+                lexer.take_errors(),
+                vec![LexError::InvalidNumber {
+                    literal: literal.to_string(),
+                    span: Span::new(0, literal.len(), 1, 1),
+                }],
+                "input {}",
+                input
+            );
+        }
+
+        // Numerals outside ASCII aren't digits.
+        let mut lexer = Lexer::new("١٢");
+        assert_eq!(lexer.next_token().kind, TokenKind::Illegal);
+        assert!(matches!(
+            lexer.take_errors()[..],
+            [LexError::IllegalCharacter { ch: '١', .. }]
+        ));
+    }
+
+    #[test]
+    fn test_string_literals() {
+        let input = r#""foobar" "foo bar" "a\n\t\"b\"\\" "\u{48}\u{1F600}" """#;
+        let expected = vec![
+            (TokenKind::String, "foobar"),
+            (TokenKind::String, "foo bar"),
+            (TokenKind::String, "a\n\t\"b\"\\"),
+            (TokenKind::String, "H\u{1F600}"),
+            (TokenKind::String, ""),
+            (TokenKind::Eof, "\0"),
+        ];
+
+        let mut lexer = Lexer::new(input);
+        for (kind, literal) in expected {
+            let token = lexer.next_token();
+            assert_eq!(token.kind, kind);
+            assert_eq!(token.literal, literal);
+        }
+        assert!(lexer.take_errors().is_empty());
+
+        let mut lexer = Lexer::new(r#"x = "hi";"#);
+        lexer.next_token();
+        lexer.next_token();
+        assert_eq!(lexer.next_token().span, Span::new(4, 8, 1, 5));
+    }
+
+    #[test]
+    fn test_string_errors() {
+        let mut lexer = Lexer::new("let s = \"abc\n  def");
+        let kinds: Vec<TokenKind> = (0..5).map(|_| lexer.next_token().kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::Let,
+                TokenKind::Ident,
+                TokenKind::Assign,
+                TokenKind::Illegal,
+                TokenKind::Eof
+            ]
+        );
+        assert_eq!(
+            lexer.take_errors(),
+            vec![LexError::UnterminatedString {
+                span: Span::new(8, 18, 1, 9)
+            }]
+        );
 
-    use super::Lexer;
+        let mut lexer = Lexer::new(r#""a\qb\u{110000}\u{41""#);
+        let token = lexer.next_token();
+        assert_eq!(token.kind, TokenKind::String);
+        assert_eq!(token.literal, "ab");
+        let errors: Vec<String> = lexer.take_errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "invalid escape sequence `\\q`",
+                "invalid escape sequence `\\u{110000}`",
+                "invalid escape sequence `\\u{41`",
+            ]
+        );
+
+        let mut lexer = Lexer::new("a @ b");
+        lexer.next_token();
+        assert_eq!(lexer.next_token().kind, TokenKind::Illegal);
+        assert_eq!(
+            lexer.take_errors(),
+            vec![LexError::IllegalCharacter {
+                ch: '@',
+                span: Span::new(2, 3, 1, 3)
+            }]
+        );
+    }
+
+    #[test]
+    fn test_comments() {
+        let input = "a // to the end\n/* b /* nested */ still comment */ c /**/ d";
+        let tokens: Vec<Token> = Lexer::new(input).collect();
+        let literals: Vec<&str> = tokens.iter().map(|t| t.literal.as_str()).collect();
+        assert_eq!(literals, vec!["a", "c", "d", "\0"]);
+        assert_eq!(tokens[1].span, Span::new(51, 52, 2, 36));
+
+        let mut lexer = Lexer::new("x /* é /* */\n");
+        let kinds: Vec<TokenKind> = lexer.by_ref().map(|t| t.kind).collect();
+        assert_eq!(kinds, vec![TokenKind::Ident, TokenKind::Eof]);
+        assert_eq!(
+            lexer.take_errors(),
+            vec![LexError::UnterminatedComment {
+                span: Span::new(2, 4, 1, 3)
+            }]
+        );
+    }
+
+    #[test]
+    fn test_doc_comments() {
+        let input = "///  One.\r\n//// Not doc.\n///\n/// Two. é\nlet\n/// Three.\nx";
+        let mut lexer = Lexer::new(input);
+        let mut docs = vec![];
+        while lexer.next_token().kind != TokenKind::Eof {
+            docs.push(lexer.take_doc());
+        }
+        assert_eq!(
+            docs,
+            vec![Some(" One.\n\nTwo. é".into()), Some("Three.".into())]
+        );
+        assert_eq!(lexer.take_doc(), None);
+
+        let mut tokens = TokenStream::new(Lexer::new(input));
+        tokens.peek_nth(2);
+        assert_eq!(tokens.take_doc(), None);
+        tokens.next_token();
+        assert_eq!(tokens.take_doc().as_deref(), Some(" One.\n\nTwo. é"));
+        assert_eq!(tokens.take_doc(), None);
+        tokens.next_token();
+        assert_eq!(tokens.take_doc().as_deref(), Some("Three."));
+    }
+
+    #[test]
+    fn test_iterator_stops_after_eof() {
+        let mut lexer = Lexer::new("x");
+        assert_eq!(lexer.next().map(|token| token.kind), Some(TokenKind::Ident));
+        assert_eq!(lexer.next().map(|token| token.kind), Some(TokenKind::Eof));
+        assert_eq!(lexer.next(), None);
+        assert_eq!(lexer.next_token().kind, TokenKind::Eof);
+
+        assert_eq!(Lexer::new("").count(), 1);
+    }
+
+    #[test]
+    fn test_token_stream_lookahead() {
+        let mut stream = TokenStream::new(Lexer::new("a @ b # c"));
+        assert_eq!(stream.peek().literal, "a");
+        assert_eq!(stream.peek_nth(3).literal, "#");
+        assert_eq!(stream.peek_nth(10).kind, TokenKind::Eof);
+        // Looking past `@` and `#` doesn't report them yet.
+        assert!(stream.take_errors().is_empty());
+
+        assert_eq!(stream.next_token().literal, "a");
+        assert_eq!(stream.peek().literal, "@");
+        let errors: Vec<String> = stream.take_errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(errors, vec!["unexpected character `@`"]);
+
+        let literals: Vec<_> = (0..5).map(|_| stream.next_token().literal).collect();
+        assert_eq!(literals, vec!["@", "b", "#", "c", "\0"]);
+        assert_eq!(stream.take_errors().len(), 1);
+    }
+
+    #[test]
+    fn test_borrowed_literals() {
+        let input = r#"let café = "plain" + "esc\taped";"#;
+        let mut lexer = Lexer::new(input);
+
+        let mut tokens = vec![];
+        loop {
+            let token = lexer.next_borrowed();
+            if token.kind == TokenKind::Eof {
+                break;
+            }
+            tokens.push(token);
+        }
+
+        let literals: Vec<(&str, bool)> = tokens
+            .iter()
+            .map(|token| {
+                (
+                    token.literal.as_ref(),
+                    matches!(token.literal, Cow::Borrowed(_)),
+                )
+            })
+            .collect();
+        assert_eq!(
+            literals,
+            vec![
+                ("let", true),
+                ("caf", true),
+                ("é", true),
+                ("=", true),
+                ("plain", true),
+                ("+", true),
+                ("esc\taped", false),
+                (";", true),
+            ]
+        );
+        assert_eq!(
+            tokens[6].clone().into_owned().span,
+            Span::new(22, 33, 1, 22)
+        );
+    }
+
+    #[test]
+    fn test_token_spans() {
+        let input = "let x = 10;\n  x != é;";
+        let mut lexer = Lexer::new(input);
+
+        let expected = vec![
+            (TokenKind::Let, Span::new(0, 3, 1, 1)),
+            (TokenKind::Ident, Span::new(4, 5, 1, 5)),
+            (TokenKind::Assign, Span::new(6, 7, 1, 7)),
+            (TokenKind::Int, Span::new(8, 10, 1, 9)),
+            (TokenKind::Semicolon, Span::new(10, 11, 1, 11)),
+            (TokenKind::Ident, Span::new(14, 15, 2, 3)),
+            (TokenKind::NotEq, Span::new(16, 18, 2, 5)),
+            (TokenKind::Illegal, Span::new(19, 21, 2, 8)),
+            (TokenKind::Semicolon, Span::new(21, 22, 2, 9)),
+            (TokenKind::Eof, Span::new(22, 22, 2, 10)),
+        ];
+
+        for (idx, (kind, span)) in expected.into_iter().enumerate() {
+            let token = lexer.next_token();
+            assert_eq!(token.kind, kind, "wrong kind at idx {}", idx);
+            assert_eq!(token.span, span, "wrong span at idx {}", idx);
+            assert_eq!(
+                &input[span.start..span.end],
+                token.literal.trim_end_matches('\0')
+            );
+        }
+    }
 
     #[test]
     fn test_next_token() {
@@ -123,38 +969,47 @@ mod test {
             Token {
                 kind: TokenKind::Assign,
                 literal: "=".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Plus,
                 literal: "+".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Lparen,
                 literal: "(".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Rparen,
                 literal: ")".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Lbrace,
                 literal: "{".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Rbrace,
                 literal: "}".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Comma,
                 literal: ",".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Semicolon,
                 literal: ";".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Eof,
                 literal: "\0".to_string(),
+                ..Default::default()
             },
         ];
         compare(expected, input);
@@ -171,7 +1026,7 @@ mod test {
       }
 
       let result = add(five, ten);
-      !-/*5;
+      !-/ *5;
       5 < 10 > 5;
 
       if (5 < 10) {
@@ -190,250 +1045,312 @@ mod test {
             Token {
                 kind: TokenKind::Let,
                 literal: "let".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Ident,
                 literal: "add".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Assign,
                 literal: "=".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Function,
                 literal: "fn".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Lparen,
                 literal: "(".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Ident,
                 literal: "x".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Comma,
                 literal: ",".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Ident,
                 literal: "y".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Rparen,
                 literal: ")".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Lbrace,
                 literal: "{".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Ident,
                 literal: "x".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Plus,
                 literal: "+".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Ident,
                 literal: "y".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Semicolon,
                 literal: ";".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Rbrace,
                 literal: "}".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Let,
                 literal: "let".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Ident,
                 literal: "result".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Assign,
                 literal: "=".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Ident,
                 literal: "add".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Lparen,
                 literal: "(".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Ident,
                 literal: "five".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Comma,
                 literal: ",".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Ident,
                 literal: "ten".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Rparen,
                 literal: ")".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Semicolon,
                 literal: ";".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Bang,
                 literal: "!".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Minus,
                 literal: "-".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Slash,
                 literal: "/".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Asterisk,
                 literal: "*".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Int,
                 literal: "5".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Semicolon,
                 literal: ";".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Int,
                 literal: "5".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Lt,
                 literal: "<".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Int,
                 literal: "10".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Gt,
                 literal: ">".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Int,
                 literal: "5".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Semicolon,
                 literal: ";".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::If,
                 literal: "if".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Lparen,
                 literal: "(".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Int,
                 literal: "5".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Lt,
                 literal: "<".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Int,
                 literal: "10".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Rparen,
                 literal: ")".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Lbrace,
                 literal: "{".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Return,
                 literal: "return".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::True,
                 literal: "true".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Semicolon,
                 literal: ";".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Rbrace,
                 literal: "}".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Else,
                 literal: "else".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Lbrace,
                 literal: "{".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Return,
                 literal: "return".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::False,
                 literal: "false".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Semicolon,
                 literal: ";".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Rbrace,
                 literal: "}".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Int,
                 literal: "10".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Eq,
                 literal: "==".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Int,
                 literal: "10".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Semicolon,
                 literal: ";".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Int,
                 literal: "10".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::NotEq,
                 literal: "!=".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Int,
                 literal: "9".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Semicolon,
                 literal: ";".to_string(),
+                ..Default::default()
             },
         ];
         five_ident.append(&mut expected);
@@ -462,27 +1379,32 @@ mod test {
     }
 
     fn get_ident(ident: &str, val: &str) -> Vec<Token> {
-        return vec![
+        vec![
             Token {
                 kind: TokenKind::Let,
                 literal: "let".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Ident,
                 literal: val.to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Assign,
                 literal: "=".to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Int,
                 literal: ident.to_string(),
+                ..Default::default()
             },
             Token {
                 kind: TokenKind::Semicolon,
                 literal: ";".to_string(),
+                ..Default::default()
             },
-        ];
+        ]
     }
 }
@@ -0,0 +1,29 @@
+//! An interpreter for the Monkey programming language.
+//!
+//! Most embedders only need [`Interpreter`], which parses and evaluates
+//! source against a persistent global environment. The pipeline stages are
+//! public too: `lexer`, `parser` and `ast` for the front end, `evaluator`
+//! for the tree-walking evaluator, and `compiler`, `vm` and `bytecode_file`
+//! for the bytecode engine.
+
+pub mod ast;
+pub mod builtins;
+pub mod bytecode_file;
+pub mod code;
+pub mod compiler;
+pub mod diagnostics;
+pub mod disassembler;
+pub mod environment;
+pub mod evaluator;
+pub mod interpreter;
+pub mod lexer;
+pub mod line_editor;
+pub mod native;
+pub mod object;
+pub mod parser;
+pub mod repl;
+pub mod symbol_table;
+pub mod token;
+pub mod vm;
+
+pub use interpreter::{Error, Interpreter};
@@ -0,0 +1,651 @@
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::{self, Read, Write},
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+/// Entries kept in memory and in the history file.
+const MAX_HISTORY: usize = 1000;
+
+/// A key press, decoded from the bytes a terminal sends in raw mode.
+#[derive(Debug, Clone, PartialEq)]
+enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Home,
+    End,
+    Up,
+    Down,
+    Tab,
+    /// Ctrl-C.
+    Interrupt,
+    /// Ctrl-D.
+    EndOfInput,
+    /// Ctrl-K.
+    KillToEnd,
+    /// Ctrl-U.
+    KillToStart,
+    /// Ctrl-W.
+    DeleteWord,
+    /// Ctrl-L.
+    ClearScreen,
+    /// Ctrl-R.
+    Search,
+    /// Ctrl-G.
+    Cancel,
+    /// Anything else, like function keys or unassigned control characters.
+    Ignored,
+}
+
+/// The line being edited. `cursor` is an index into `buffer`.
+#[derive(Default)]
+struct Line {
+    buffer: Vec<char>,
+    cursor: usize,
+}
+
+impl Line {
+    fn set(&mut self, text: &str) {
+        self.buffer = text.chars().collect();
+        self.cursor = self.buffer.len();
+    }
+
+    fn text(&self) -> String {
+        self.buffer.iter().collect()
+    }
+
+    fn insert(&mut self, text: &str) {
+        for ch in text.chars() {
+            self.buffer.insert(self.cursor, ch);
+            self.cursor += 1;
+        }
+    }
+
+    /// Start of the identifier that ends at the cursor.
+    fn word_start(&self) -> usize {
+        let mut start = self.cursor;
+        while start > 0 && is_word_char(self.buffer[start - 1]) {
+            start -= 1;
+        }
+        start
+    }
+}
+
+fn is_word_char(ch: char) -> bool {
+    ch.is_ascii_alphabetic() || ch == '_'
+}
+
+/// The current terminal settings, in the form `stty` takes back to restore them.
+fn terminal_settings() -> io::Result<String> {
+    let output = Command::new("stty")
+        .arg("-g")
+        .stdin(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(
+            "stty could not read the terminal settings",
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Puts the terminal in raw mode through `stty` for as long as it lives, so
+/// keys arrive one at a time and without echo, then restores `saved`.
+struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    fn enable(saved: String) -> io::Result<Self> {
+        stty(&["raw", "-echo"])?;
+        Ok(Self { saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = stty(&[self.saved.as_str()]);
+    }
+}
+
+fn stty(args: &[&str]) -> io::Result<()> {
+    let status = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(
+            "stty could not change the terminal settings",
+        ));
+    }
+    Ok(())
+}
+
+/// Reads lines from a terminal with cursor movement, history and completion.
+///
+/// Keys follow the usual readline bindings: arrows or Ctrl-B/F/P/N move and
+/// browse history, Ctrl-A/E jump to the ends of the line, Ctrl-K/U/W delete,
+/// Ctrl-R searches history backwards, Tab completes the word before the
+/// cursor, Ctrl-C abandons the line and Ctrl-D on an empty line ends input.
+/// Lines are assumed to fit on one row of the terminal.
+#[derive(Default)]
+pub struct LineEditor {
+    history: Vec<String>,
+    history_file: Option<PathBuf>,
+    /// Lines in the history file, which is rewritten once it has too many.
+    saved_lines: usize,
+    /// Settings to restore after each line, saved by `check_terminal`.
+    terminal_settings: Option<String>,
+}
+
+impl LineEditor {
+    /// An editor whose history only lasts as long as it does.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// An editor that loads its history from `path`, if it exists, and
+    /// appends every entered line to it.
+    pub fn with_history_file(path: PathBuf) -> Self {
+        let mut history: Vec<String> = fs::read_to_string(&path)
+            .map(|contents| contents.lines().map(String::from).collect())
+            .unwrap_or_default();
+        let saved_lines = history.len();
+        let excess = history.len().saturating_sub(MAX_HISTORY);
+        history.drain(..excess);
+
+        Self {
+            history,
+            history_file: Some(path),
+            saved_lines,
+            terminal_settings: None,
+        }
+    }
+
+    /// `.monkey_history` in the home directory.
+    pub fn default_history_file() -> Option<PathBuf> {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join(".monkey_history"))
+    }
+
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    /// Saves the terminal settings and checks that `stty` can switch the
+    /// terminal to raw mode and back. If it can't, lines should be read some
+    /// other way.
+    pub fn check_terminal(&mut self) -> io::Result<()> {
+        let saved = terminal_settings()?;
+        drop(RawMode::enable(saved.clone())?);
+        self.terminal_settings = Some(saved);
+        Ok(())
+    }
+
+    /// Shows `prompt` and edits a line on the terminal. Returns `None` at the
+    /// end of input, and an `Interrupted` error when the user presses Ctrl-C.
+    /// `complete` returns the words that could complete a prefix.
+    ///
+    /// The terminal is only in raw mode while the line is edited, so code
+    /// run between lines can still be stopped with Ctrl-C.
+    pub fn read_line(
+        &mut self,
+        prompt: &str,
+        complete: &dyn Fn(&str) -> Vec<String>,
+    ) -> io::Result<Option<String>> {
+        let saved = match &self.terminal_settings {
+            Some(saved) => saved.clone(),
+            None => terminal_settings()?,
+        };
+        let _raw_mode = RawMode::enable(saved)?;
+        self.edit(&mut io::stdin().lock(), &mut io::stdout(), prompt, complete)
+    }
+
+    fn edit(
+        &mut self,
+        input: &mut impl Read,
+        out: &mut impl Write,
+        prompt: &str,
+        complete: &dyn Fn(&str) -> Vec<String>,
+    ) -> io::Result<Option<String>> {
+        let mut line = Line::default();
+        // Index of the history entry shown, where `history.len()` is the new line.
+        let mut history_index = self.history.len();
+        let mut draft = String::new();
+        // A key that ended a search and still has to be handled.
+        let mut pending = None;
+
+        refresh(out, prompt, &line)?;
+        loop {
+            let key = match pending.take() {
+                Some(key) => key,
+                None => match read_key(input)? {
+                    Some(key) => key,
+                    None => return Ok(None),
+                },
+            };
+
+            match key {
+                Key::Enter => {
+                    write!(out, "\r\n")?;
+                    out.flush()?;
+                    let text = line.text();
+                    self.add_history(&text);
+                    return Ok(Some(text));
+                }
+                Key::Char(ch) => line.insert(&ch.to_string()),
+                Key::Backspace if line.cursor > 0 => {
+                    line.cursor -= 1;
+                    line.buffer.remove(line.cursor);
+                }
+                Key::Delete if line.cursor < line.buffer.len() => {
+                    line.buffer.remove(line.cursor);
+                }
+                Key::Left => line.cursor = line.cursor.saturating_sub(1),
+                Key::Right => line.cursor = (line.cursor + 1).min(line.buffer.len()),
+                Key::Home => line.cursor = 0,
+                Key::End => line.cursor = line.buffer.len(),
+                Key::Up if history_index > 0 => {
+                    if history_index == self.history.len() {
+                        draft = line.text();
+                    }
+                    history_index -= 1;
+                    line.set(&self.history[history_index]);
+                }
+                Key::Down if history_index < self.history.len() => {
+                    history_index += 1;
+                    match self.history.get(history_index) {
+                        Some(entry) => line.set(entry),
+                        None => line.set(&draft),
+                    }
+                }
+                Key::Tab => complete_word(out, &mut line, complete)?,
+                Key::Interrupt => {
+                    write!(out, "^C\r\n")?;
+                    out.flush()?;
+                    return Err(io::Error::from(io::ErrorKind::Interrupted));
+                }
+                Key::EndOfInput if line.buffer.is_empty() => return Ok(None),
+                Key::EndOfInput if line.cursor < line.buffer.len() => {
+                    line.buffer.remove(line.cursor);
+                }
+                Key::KillToEnd => line.buffer.truncate(line.cursor),
+                Key::KillToStart => {
+                    line.buffer.drain(..line.cursor);
+                    line.cursor = 0;
+                }
+                Key::DeleteWord => {
+                    // Like readline, skips the spaces before the word too.
+                    let mut start = line.cursor;
+                    while start > 0 && line.buffer[start - 1] == ' ' {
+                        start -= 1;
+                    }
+                    while start > 0 && line.buffer[start - 1] != ' ' {
+                        start -= 1;
+                    }
+                    line.buffer.drain(start..line.cursor);
+                    line.cursor = start;
+                }
+                Key::ClearScreen => write!(out, "\x1b[2J\x1b[H")?,
+                Key::Search => pending = self.search(input, out, &mut line)?,
+                _ => {}
+            }
+            refresh(out, prompt, &line)?;
+        }
+    }
+
+    /// Reverse incremental search. Typing narrows the query, Ctrl-R moves to
+    /// older matches, Ctrl-G gives up. Any other key puts the match on the
+    /// line and is returned so the caller handles it, so Enter submits it.
+    fn search(
+        &self,
+        input: &mut impl Read,
+        out: &mut impl Write,
+        line: &mut Line,
+    ) -> io::Result<Option<Key>> {
+        let mut query = String::new();
+        let mut found: Option<usize> = None;
+
+        loop {
+            let status = if found.is_none() && !query.is_empty() {
+                "failed reverse-i-search"
+            } else {
+                "reverse-i-search"
+            };
+            let matched = found.map_or("", |idx| self.history[idx].as_str());
+            write!(out, "\r({status})`{query}': {matched}\x1b[K")?;
+            out.flush()?;
+
+            let Some(key) = read_key(input)? else {
+                return Ok(None);
+            };
+            match key {
+                Key::Char(ch) => {
+                    query.push(ch);
+                    // The current match may still contain the longer query.
+                    let before = found.map_or(self.history.len(), |idx| idx + 1);
+                    found = self.find(&query, before);
+                }
+                Key::Backspace => {
+                    query.pop();
+                    found = self.find(&query, self.history.len());
+                }
+                Key::Search => {
+                    let before = found.unwrap_or(self.history.len());
+                    found = self.find(&query, before).or(found);
+                }
+                Key::Cancel | Key::Interrupt => return Ok(None),
+                key => {
+                    if let Some(idx) = found {
+                        line.set(&self.history[idx]);
+                    }
+                    return Ok(Some(key));
+                }
+            }
+        }
+    }
+
+    /// The newest entry before `before` containing `query`.
+    fn find(&self, query: &str, before: usize) -> Option<usize> {
+        if query.is_empty() {
+            return None;
+        }
+        (0..before)
+            .rev()
+            .find(|&idx| self.history[idx].contains(query))
+    }
+
+    fn add_history(&mut self, line: &str) {
+        if line.trim().is_empty() || self.history.last().map(String::as_str) == Some(line) {
+            return;
+        }
+        self.history.push(line.to_string());
+        if self.history.len() > MAX_HISTORY {
+            self.history.remove(0);
+        }
+
+        // Failing to save history shouldn't interrupt the session.
+        let Some(path) = &self.history_file else {
+            return;
+        };
+        if self.saved_lines >= MAX_HISTORY {
+            let mut contents = self.history.join("\n");
+            contents.push('\n');
+            if fs::write(path, contents).is_ok() {
+                self.saved_lines = self.history.len();
+            }
+        } else if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+            if writeln!(file, "{}", line).is_ok() {
+                self.saved_lines += 1;
+            }
+        }
+    }
+}
+
+/// Completes the word before the cursor as far as all candidates agree,
+/// listing them when that adds nothing.
+fn complete_word(
+    out: &mut impl Write,
+    line: &mut Line,
+    complete: &dyn Fn(&str) -> Vec<String>,
+) -> io::Result<()> {
+    let start = line.word_start();
+    let prefix: String = line.buffer[start..line.cursor].iter().collect();
+    if prefix.is_empty() {
+        return Ok(());
+    }
+
+    let mut candidates: Vec<String> = complete(&prefix)
+        .into_iter()
+        .filter(|candidate| candidate.starts_with(&prefix))
+        .collect();
+    candidates.sort();
+    candidates.dedup();
+
+    let Some(first) = candidates.first() else {
+        // Ring the bell.
+        return write!(out, "\x07");
+    };
+    let common = candidates.iter().fold(first.as_str(), |common, candidate| {
+        let len = common
+            .char_indices()
+            .zip(candidate.chars())
+            .take_while(|((_, a), b)| a == b)
+            .last()
+            .map_or(0, |((idx, ch), _)| idx + ch.len_utf8());
+        &common[..len]
+    });
+
+    if common.len() > prefix.len() {
+        line.insert(&common[prefix.len()..]);
+    } else if candidates.len() > 1 {
+        write!(out, "\r\n{}\r\n", candidates.join("  "))?;
+    }
+    Ok(())
+}
+
+/// Redraws the prompt and line, then puts the terminal cursor at `line.cursor`.
+fn refresh(out: &mut impl Write, prompt: &str, line: &Line) -> io::Result<()> {
+    write!(out, "\r{}{}\x1b[K", prompt, line.text())?;
+    let back = line.buffer.len() - line.cursor;
+    if back > 0 {
+        write!(out, "\x1b[{}D", back)?;
+    }
+    out.flush()
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    loop {
+        match input.read(&mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Reads one key, or `None` when the input is closed.
+fn read_key(input: &mut impl Read) -> io::Result<Option<Key>> {
+    let Some(byte) = read_byte(input)? else {
+        return Ok(None);
+    };
+
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        0x7f | 0x08 => Key::Backspace,
+        b'\t' => Key::Tab,
+        0x01 => Key::Home,
+        0x02 => Key::Left,
+        0x03 => Key::Interrupt,
+        0x04 => Key::EndOfInput,
+        0x05 => Key::End,
+        0x06 => Key::Right,
+        0x07 => Key::Cancel,
+        0x0b => Key::KillToEnd,
+        0x0c => Key::ClearScreen,
+        0x0e => Key::Down,
+        0x10 => Key::Up,
+        0x12 => Key::Search,
+        0x15 => Key::KillToStart,
+        0x17 => Key::DeleteWord,
+        0x1b => read_escape(input)?,
+        byte if byte < 0x20 => Key::Ignored,
+        byte => read_char(input, byte)?,
+    };
+    Ok(Some(key))
+}
+
+/// Decodes the rest of a UTF-8 character starting with `first`.
+fn read_char(input: &mut impl Read, first: u8) -> io::Result<Key> {
+    let len = match first {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => 1,
+    };
+
+    let mut bytes = vec![first];
+    for _ in 1..len {
+        match read_byte(input)? {
+            Some(byte) => bytes.push(byte),
+            None => break,
+        }
+    }
+
+    Ok(std::str::from_utf8(&bytes)
+        .ok()
+        .and_then(|s| s.chars().next())
+        .map_or(Key::Ignored, Key::Char))
+}
+
+/// Decodes the CSI (`ESC [`) and SS3 (`ESC O`) sequences sent by arrows,
+/// Home, End and Delete.
+fn read_escape(input: &mut impl Read) -> io::Result<Key> {
+    let introducer = match read_byte(input)? {
+        Some(byte @ (b'[' | b'O')) => byte,
+        _ => return Ok(Key::Ignored),
+    };
+
+    let mut params = String::new();
+    let last = loop {
+        match read_byte(input)? {
+            Some(byte @ (b'0'..=b'9' | b';')) => params.push(byte as char),
+            Some(byte) => break byte,
+            None => return Ok(Key::Ignored),
+        }
+    };
+
+    // Modifiers, as in `ESC [ 1 ; 5 C` for Ctrl-Right, are ignored.
+    let key = match (introducer, params.split(';').next().unwrap_or(""), last) {
+        (_, _, b'A') => Key::Up,
+        (_, _, b'B') => Key::Down,
+        (_, _, b'C') => Key::Right,
+        (_, _, b'D') => Key::Left,
+        (_, _, b'H') => Key::Home,
+        (_, _, b'F') => Key::End,
+        (b'[', "1" | "7", b'~') => Key::Home,
+        (b'[', "4" | "8", b'~') => Key::End,
+        (b'[', "3", b'~') => Key::Delete,
+        _ => Key::Ignored,
+    };
+    Ok(key)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, io, process};
+
+    use super::{LineEditor, MAX_HISTORY};
+
+    fn complete(prefix: &str) -> Vec<String> {
+        ["let", "len", "last", "lengthy"]
+            .iter()
+            .filter(|word| word.starts_with(prefix))
+            .map(|word| word.to_string())
+            .collect()
+    }
+
+    /// Types `keys` into the editor and returns each line read, stopping at
+    /// the end of input.
+    fn type_keys(editor: &mut LineEditor, keys: &str) -> Vec<String> {
+        let mut input = keys.as_bytes();
+        let mut lines = vec![];
+        loop {
+            match editor.edit(&mut input, &mut io::sink(), ">> ", &complete) {
+                Ok(Some(line)) => lines.push(line),
+                Ok(None) => return lines,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => lines.push("^C".into()),
+                Err(e) => panic!("unexpected error {}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn test_editing() {
+        let tests = vec![
+            ("let x = 1;\r", "let x = 1;"),
+            // Left arrow, then insert.
+            ("1 + 3\x1b[D2 \r", "1 + 2 3"),
+            // Ctrl-A, Delete, Ctrl-E, Backspace.
+            ("(1)\x01\x1b[3~\x05\x7f\r", "1"),
+            ("let x = 1; x\x17\x17y\r", "let x = y"),
+            ("abc def\x1b[D\x1b[D\x1b[D\x0b\r", "abc "),
+            ("abc def\x1b[D\x1b[D\x1b[D\x15\r", "def"),
+            ("héllo\x7f\x7f\x7f\x7fy\r", "hy"),
+            // Home and End escape sequences.
+            ("b\x1b[Ha\x1b[Fc\r", "abc"),
+        ];
+
+        for (keys, expected) in tests {
+            let mut editor = LineEditor::new();
+            assert_eq!(type_keys(&mut editor, keys), vec![expected], "{:?}", keys);
+        }
+    }
+
+    #[test]
+    fn test_history() {
+        let mut editor = LineEditor::new();
+        let lines = type_keys(
+            &mut editor,
+            "one\rtwo\rtwo\r\r\x1b[A\x1b[A!\rdraft\x1b[A\x1b[B\r\x03\x04",
+        );
+        assert_eq!(lines, vec!["one", "two", "two", "", "one!", "draft", "^C"]);
+        assert_eq!(editor.history(), &["one", "two", "one!", "draft"]);
+    }
+
+    #[test]
+    fn test_history_file_stays_bounded() {
+        let path = env::temp_dir().join(format!("monkey_history_test_{}", process::id()));
+        let old: Vec<String> = (0..MAX_HISTORY + 5).map(|n| n.to_string()).collect();
+        fs::write(&path, old.join("\n") + "\n").unwrap();
+
+        let mut editor = LineEditor::with_history_file(path.clone());
+        assert_eq!(editor.history().len(), MAX_HISTORY);
+        type_keys(&mut editor, "new\r");
+
+        let saved = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let saved: Vec<&str> = saved.lines().collect();
+        assert_eq!(saved.len(), MAX_HISTORY);
+        assert_eq!(saved[0], "6");
+        assert_eq!(saved[MAX_HISTORY - 1], "new");
+    }
+
+    #[test]
+    fn test_reverse_search() {
+        let mut editor = LineEditor::new();
+        let lines = type_keys(
+            &mut editor,
+            // Finds "let b", then the older "let a" and edits it.
+            "let a = 1;\rlet b = 2;\rb\rlet x\x12let\x12\x05 + 1\r\x12zzz\x07c\r",
+        );
+        assert_eq!(
+            lines,
+            vec!["let a = 1;", "let b = 2;", "b", "let a = 1; + 1", "c"]
+        );
+    }
+
+    #[test]
+    fn test_completion() {
+        let tests = vec![
+            // A single candidate is completed.
+            ("la\t\r", "last"),
+            // Several are completed as far as they agree.
+            ("x + le\t\r", "x + le"),
+            ("x + len\tg\t\r", "x + lengthy"),
+            ("zz\t\r", "zz"),
+        ];
+
+        for (keys, expected) in tests {
+            let mut editor = LineEditor::new();
+            assert_eq!(type_keys(&mut editor, keys), vec![expected], "{:?}", keys);
+        }
+    }
+}
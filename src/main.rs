@@ -1,15 +1,419 @@
-use std::io::{self};
+use std::{
+    env, fs,
+    io::{self, IsTerminal},
+    path::Path,
+    process::ExitCode,
+};
 
-use repl::start;
+use interpreter::{
+    ast::{Node, Program},
+    bytecode_file,
+    compiler::{Bytecode, Compiler},
+    diagnostics::{Diagnostic, Renderer},
+    disassembler,
+    lexer::Lexer,
+    object::{Object, RuntimeError},
+    parser::{ParseError, Parser},
+    repl::{self, Config},
+    token::TokenKind,
+    vm::VM,
+    Interpreter,
+};
 
-pub mod lexer;
-pub mod repl;
-pub mod token;
-pub mod ast;
-pub mod parser;
+const USAGE: &str = "\
+usage: interpreter [command] [options] <file>
 
-fn main() {
-    println!("Hello, world!");
-    println!("Please type in the code");
-    start(io::stdin(), io::stdout())
+commands:
+  repl                          start an interactive session (the default)
+  run [--vm] [--trace] <file>   run a script, or a compiled .mkb file
+  tokens <file>                 print the tokens of a script
+  ast [--debug] <file>          print the parsed program
+  check <file>                  report syntax errors without running
+  compile [--strip] <file>      write the bytecode of a script to <file>.mkb
+  disassemble <file>            print the bytecode of a script or .mkb file
+  help                          print this message
+
+options:
+  --vm       run on the bytecode VM instead of the tree-walking evaluator
+  --trace    log every VM instruction to stderr (implies --vm)
+  --debug    print the syntax tree instead of formatted source
+  --strip    leave debug info out of the bytecode file
+
+exit codes:
+  0 success, 1 runtime error, 2 usage error, 3 invalid token,
+  4 syntax error, 5 compile error, 6 unreadable input
+";
+
+/// Process exit codes, one per stage that can fail.
+const EXIT_RUNTIME: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_LEX: u8 = 3;
+const EXIT_PARSE: u8 = 4;
+const EXIT_COMPILE: u8 = 5;
+const EXIT_IO: u8 = 6;
+
+/// Flags given after the command. Each command accepts only some of them.
+#[derive(Default)]
+struct Options {
+    vm: bool,
+    trace: bool,
+    debug: bool,
+    strip: bool,
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let Some((command, rest)) = args.split_first() else {
+        return start_repl();
+    };
+
+    let allowed: &[&str] = match command.as_str() {
+        "repl" if rest.is_empty() => return start_repl(),
+        "help" | "--help" | "-h" => {
+            print!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        "run" => &["--vm", "--trace"],
+        "ast" => &["--debug"],
+        "compile" => &["--strip"],
+        "tokens" | "check" | "disassemble" => &[],
+        _ => return usage_error(&format!("unknown command `{command}`")),
+    };
+
+    let (path, options) = match parse_args(rest, allowed) {
+        Ok(parsed) => parsed,
+        Err(message) => return usage_error(&message),
+    };
+
+    let result = match command.as_str() {
+        "run" => run(&path, &options),
+        "tokens" => tokens(&path),
+        "ast" => ast(&path, &options),
+        "check" => check(&path),
+        "compile" => compile(&path, &options),
+        "disassemble" => disassemble(&path),
+        _ => unreachable!("commands are matched above"),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(code) => ExitCode::from(code),
+    }
+}
+
+fn start_repl() -> ExitCode {
+    let config = Config {
+        color: io::stdout().is_terminal(),
+        ..Config::default()
+    };
+    match repl::start_terminal(&config) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::from(EXIT_IO)
+        }
+    }
+}
+
+fn usage_error(message: &str) -> ExitCode {
+    eprintln!("error: {message}\n\n{USAGE}");
+    ExitCode::from(EXIT_USAGE)
+}
+
+/// Splits the arguments after the command into the file and the flags in
+/// `allowed`.
+fn parse_args(args: &[String], allowed: &[&str]) -> Result<(String, Options), String> {
+    let mut options = Options::default();
+    let mut path = None;
+
+    for arg in args {
+        if arg.starts_with("--") {
+            if !allowed.contains(&arg.as_str()) {
+                return Err(format!("unexpected option `{arg}`"));
+            }
+            match arg.as_str() {
+                "--vm" => options.vm = true,
+                "--trace" => options.trace = true,
+                "--debug" => options.debug = true,
+                "--strip" => options.strip = true,
+                _ => unreachable!("allowed options are all handled"),
+            }
+        } else if path.replace(arg.clone()).is_some() {
+            return Err(String::from("expected a single file"));
+        }
+    }
+
+    path.map(|path| (path, options))
+        .ok_or_else(|| String::from("missing file"))
+}
+
+/// A script's source, or the bytecode of a compiled file.
+enum Input {
+    Source(String),
+    Bytecode(Bytecode),
+}
+
+fn read_input(path: &str) -> Result<Input, u8> {
+    let bytes = fs::read(path).map_err(|e| {
+        eprintln!("error: could not read {path}: {e}");
+        EXIT_IO
+    })?;
+
+    if bytes.starts_with(bytecode_file::MAGIC) {
+        return match bytecode_file::load(&bytes) {
+            Ok(bytecode) => Ok(Input::Bytecode(bytecode)),
+            Err(e) => {
+                eprintln!("error: could not load {path}: {e}");
+                Err(EXIT_IO)
+            }
+        };
+    }
+
+    String::from_utf8(bytes).map(Input::Source).map_err(|_| {
+        eprintln!("error: {path} is neither UTF-8 source nor a bytecode file");
+        EXIT_IO
+    })
+}
+
+fn read_source(path: &str) -> Result<String, u8> {
+    match read_input(path)? {
+        Input::Source(source) => Ok(source),
+        Input::Bytecode(_) => {
+            eprintln!("error: {path} is a compiled bytecode file, not source");
+            Err(EXIT_IO)
+        }
+    }
+}
+
+fn renderer() -> Renderer {
+    Renderer::for_stream(&io::stderr())
+}
+
+/// Renders every error. An invalid token decides the exit code over other
+/// syntax errors, which are often a consequence of it.
+fn report_parse_errors(path: &str, source: &str, errors: &[ParseError]) -> u8 {
+    let renderer = renderer();
+    for error in errors {
+        eprint!(
+            "{}",
+            renderer.render(path, source, &Diagnostic::from(error))
+        );
+    }
+
+    if errors
+        .iter()
+        .any(|error| matches!(error, ParseError::Lex(_)))
+    {
+        EXIT_LEX
+    } else {
+        EXIT_PARSE
+    }
+}
+
+fn parse(path: &str, source: &str) -> Result<Program, u8> {
+    let (program, errors) = Parser::new(Lexer::new(source)).parse_program();
+    if !errors.is_empty() {
+        return Err(report_parse_errors(path, source, &errors));
+    }
+    Ok(program)
+}
+
+fn compile_source(path: &str, source: &str) -> Result<Bytecode, u8> {
+    let program = parse(path, source)?;
+
+    let mut compiler = Compiler::new();
+    if let Err(error) = compiler.compile_program(&program) {
+        eprint!(
+            "{}",
+            renderer().render(path, source, &Diagnostic::from(&error))
+        );
+        return Err(EXIT_COMPILE);
+    }
+    Ok(compiler.bytecode())
+}
+
+/// Runs a script, or a compiled bytecode file, and prints its final value.
+fn run(path: &str, options: &Options) -> Result<(), u8> {
+    let (source, result) = match read_input(path)? {
+        Input::Bytecode(bytecode) => (None, run_bytecode(bytecode, options)),
+        Input::Source(source) if options.vm || options.trace => {
+            let bytecode = compile_source(path, &source)?;
+            let result = run_bytecode(bytecode, options);
+            (Some(source), result)
+        }
+        Input::Source(source) => match Interpreter::new().eval_str(&source) {
+            Ok(value) => (Some(source), Ok(value)),
+            Err(interpreter::Error::Parse(errors)) => {
+                return Err(report_parse_errors(path, &source, &errors))
+            }
+            Err(interpreter::Error::Runtime(error)) => (Some(source), Err(error)),
+            Err(error) => unreachable!("eval_str does no IO, got {error}"),
+        },
+    };
+
+    match result {
+        Ok(Object::Null) => Ok(()),
+        Ok(value) => {
+            println!("{}", value.inspect());
+            Ok(())
+        }
+        Err(error) => {
+            report_runtime_error(path, source.as_deref(), error);
+            Err(EXIT_RUNTIME)
+        }
+    }
+}
+
+fn run_bytecode(bytecode: Bytecode, options: &Options) -> Result<Object, RuntimeError> {
+    let mut vm = VM::new(bytecode);
+    if options.trace {
+        vm.set_tracer(Box::new(io::stderr()));
+    }
+    vm.run()?;
+    Ok(vm.last_popped_stack_elem())
+}
+
+/// Without the source, as for a compiled file, the error can only name the
+/// position recorded in the debug info.
+fn report_runtime_error(path: &str, source: Option<&str>, error: RuntimeError) {
+    let diagnostic = match source {
+        Some(_) => Diagnostic::from(&error),
+        None => {
+            let diagnostic = Diagnostic::error(error.message, None);
+            match error.span {
+                Some(span) => diagnostic.with_help(&format!(
+                    "raised at line {}, column {} of the original source",
+                    span.line, span.column
+                )),
+                None => diagnostic,
+            }
+        }
+    };
+    eprint!(
+        "{}",
+        renderer().render(path, source.unwrap_or_default(), &diagnostic)
+    );
+}
+
+/// Prints one token per line with its position, then any invalid tokens.
+fn tokens(path: &str) -> Result<(), u8> {
+    let source = read_source(path)?;
+    let mut lexer = Lexer::new(&source);
+
+    for token in lexer
+        .by_ref()
+        .take_while(|token| token.kind != TokenKind::Eof)
+    {
+        println!(
+            "{}:{} {:?} {:?}",
+            token.span.line, token.span.column, token.kind, token.literal
+        );
+    }
+
+    let errors: Vec<ParseError> = lexer
+        .take_errors()
+        .into_iter()
+        .map(ParseError::Lex)
+        .collect();
+    if !errors.is_empty() {
+        return Err(report_parse_errors(path, &source, &errors));
+    }
+    Ok(())
+}
+
+fn ast(path: &str, options: &Options) -> Result<(), u8> {
+    let source = read_source(path)?;
+    let program = parse(path, &source)?;
+
+    if options.debug {
+        println!("{:#?}", program);
+    } else {
+        for statement in &program.statements {
+            println!("{}", statement.print_string());
+        }
+    }
+    Ok(())
+}
+
+fn check(path: &str) -> Result<(), u8> {
+    let source = read_source(path)?;
+    parse(path, &source).map(|_| ())
+}
+
+fn compile(path: &str, options: &Options) -> Result<(), u8> {
+    let source = read_source(path)?;
+    let bytecode = compile_source(path, &source)?;
+
+    let out_path = Path::new(path).with_extension("mkb");
+    let bytes = bytecode_file::write(&bytecode, !options.strip).map_err(|e| {
+        eprintln!("error: {e}");
+        EXIT_COMPILE
+    })?;
+
+    fs::write(&out_path, bytes).map_err(|e| {
+        eprintln!("error: could not write {}: {e}", out_path.display());
+        EXIT_IO
+    })
+}
+
+fn disassemble(path: &str) -> Result<(), u8> {
+    let bytecode = match read_input(path)? {
+        Input::Bytecode(bytecode) => bytecode,
+        Input::Source(source) => compile_source(path, &source)?,
+    };
+    print!("{}", disassembler::disassemble(&bytecode));
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use interpreter::{lexer::Lexer, parser::Parser};
+
+    use super::{parse_args, report_parse_errors, EXIT_LEX, EXIT_PARSE};
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let (path, options) =
+            parse_args(&args(&["--vm", "a.mk", "--trace"]), &["--vm", "--trace"]).unwrap();
+        assert_eq!(path, "a.mk");
+        assert!(options.vm && options.trace && !options.strip);
+
+        let errors = [
+            parse_args(&args(&["--strip", "a.mk"]), &["--vm"]),
+            parse_args(&args(&["a.mk", "b.mk"]), &[]),
+            parse_args(&args(&[]), &[]),
+        ];
+        let messages: Vec<String> = errors.into_iter().map(|e| e.err().unwrap()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "unexpected option `--strip`",
+                "expected a single file",
+                "missing file"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_error_exit_codes() {
+        let tests = vec![
+            ("let a = ;", EXIT_PARSE),
+            ("let a = 1 @ 2;", EXIT_LEX),
+            ("let a = \"open", EXIT_LEX),
+        ];
+
+        for (source, expected) in tests {
+            let (_, errors) = Parser::new(Lexer::new(source)).parse_program();
+            assert_eq!(
+                report_parse_errors("test.mk", source, &errors),
+                expected,
+                "{}",
+                source
+            );
+        }
+    }
 }
@@ -0,0 +1,408 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    rc::Rc,
+};
+
+use crate::{
+    builtins,
+    object::{Builtin, HashKey, Object, RuntimeError},
+};
+
+/// A Rust type that Monkey values can be converted to, for the arguments of
+/// native functions.
+pub trait FromObject: Sized {
+    /// The type expected, spelled like `Object::type_name`, for error messages.
+    fn expected() -> String;
+
+    fn from_object(obj: &Object) -> Option<Self>;
+}
+
+/// A Rust value that can be handed back to Monkey code.
+pub trait IntoObject {
+    fn into_object(self) -> Object;
+}
+
+impl FromObject for Object {
+    fn expected() -> String {
+        "ANY".to_string()
+    }
+
+    fn from_object(obj: &Object) -> Option<Self> {
+        Some(obj.clone())
+    }
+}
+
+impl FromObject for i64 {
+    fn expected() -> String {
+        "INTEGER".to_string()
+    }
+
+    fn from_object(obj: &Object) -> Option<Self> {
+        match obj {
+            Object::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+/// Integers are accepted too, as they are wherever a float is expected.
+impl FromObject for f64 {
+    fn expected() -> String {
+        "FLOAT".to_string()
+    }
+
+    fn from_object(obj: &Object) -> Option<Self> {
+        match obj {
+            Object::Float(value) => Some(*value),
+            Object::Integer(value) => Some(*value as f64),
+            _ => None,
+        }
+    }
+}
+
+impl FromObject for bool {
+    fn expected() -> String {
+        "BOOLEAN".to_string()
+    }
+
+    fn from_object(obj: &Object) -> Option<Self> {
+        match obj {
+            Object::Boolean(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+impl FromObject for String {
+    fn expected() -> String {
+        "STRING".to_string()
+    }
+
+    fn from_object(obj: &Object) -> Option<Self> {
+        match obj {
+            Object::String(value) => Some(value.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Every element has to convert, otherwise the whole array is rejected.
+impl<T: FromObject> FromObject for Vec<T> {
+    fn expected() -> String {
+        format!("ARRAY of {}", T::expected())
+    }
+
+    fn from_object(obj: &Object) -> Option<Self> {
+        match obj {
+            Object::Array(elements) => elements.iter().map(T::from_object).collect(),
+            _ => None,
+        }
+    }
+}
+
+impl<V: FromObject> FromObject for BTreeMap<HashKey, V> {
+    fn expected() -> String {
+        format!("HASH of {}", V::expected())
+    }
+
+    fn from_object(obj: &Object) -> Option<Self> {
+        match obj {
+            Object::Hash(pairs) => pairs
+                .iter()
+                .map(|(key, value)| Some((key.clone(), V::from_object(value)?)))
+                .collect(),
+            _ => None,
+        }
+    }
+}
+
+/// Only accepts hashes whose keys are all strings.
+impl<V: FromObject> FromObject for HashMap<String, V> {
+    fn expected() -> String {
+        format!("HASH of STRING to {}", V::expected())
+    }
+
+    fn from_object(obj: &Object) -> Option<Self> {
+        match obj {
+            Object::Hash(pairs) => pairs
+                .iter()
+                .map(|(key, value)| match key {
+                    HashKey::String(key) => Some((key.clone(), V::from_object(value)?)),
+                    _ => None,
+                })
+                .collect(),
+            _ => None,
+        }
+    }
+}
+
+impl IntoObject for Object {
+    fn into_object(self) -> Object {
+        self
+    }
+}
+
+impl IntoObject for i64 {
+    fn into_object(self) -> Object {
+        Object::Integer(self)
+    }
+}
+
+impl IntoObject for f64 {
+    fn into_object(self) -> Object {
+        Object::Float(self)
+    }
+}
+
+impl IntoObject for bool {
+    fn into_object(self) -> Object {
+        Object::Boolean(self)
+    }
+}
+
+impl IntoObject for String {
+    fn into_object(self) -> Object {
+        Object::String(self)
+    }
+}
+
+impl IntoObject for &str {
+    fn into_object(self) -> Object {
+        Object::String(self.to_string())
+    }
+}
+
+impl IntoObject for () {
+    fn into_object(self) -> Object {
+        Object::Null
+    }
+}
+
+impl<T: IntoObject> IntoObject for Option<T> {
+    fn into_object(self) -> Object {
+        self.map_or(Object::Null, T::into_object)
+    }
+}
+
+impl<T: IntoObject> IntoObject for Vec<T> {
+    fn into_object(self) -> Object {
+        Object::Array(Rc::new(self.into_iter().map(T::into_object).collect()))
+    }
+}
+
+impl<V: IntoObject> IntoObject for BTreeMap<HashKey, V> {
+    fn into_object(self) -> Object {
+        let pairs = self
+            .into_iter()
+            .map(|(key, value)| (key, value.into_object()))
+            .collect();
+        Object::Hash(Rc::new(pairs))
+    }
+}
+
+impl<V: IntoObject> IntoObject for HashMap<String, V> {
+    fn into_object(self) -> Object {
+        let pairs = self
+            .into_iter()
+            .map(|(key, value)| (HashKey::String(key), value.into_object()))
+            .collect();
+        Object::Hash(Rc::new(pairs))
+    }
+}
+
+/// An `Err` becomes a Monkey error carrying its message, which stops the
+/// program like any other runtime error.
+impl<T: IntoObject, E: Display> IntoObject for Result<T, E> {
+    fn into_object(self) -> Object {
+        match self {
+            Ok(value) => value.into_object(),
+            Err(error) => Object::Error(RuntimeError::new(error.to_string())),
+        }
+    }
+}
+
+/// A Rust closure callable from Monkey. Implemented for closures of up to
+/// four arguments that are all `FromObject` and return an `IntoObject`;
+/// `Args` is the tuple of argument types and only guides inference.
+pub trait NativeFunction<Args> {
+    fn arity(&self) -> usize;
+
+    /// Converts `args`, which already have the right count, and calls the
+    /// closure. `name` is used in error messages.
+    fn call(&self, name: &str, args: Vec<Object>) -> Object;
+}
+
+/// Wraps `func` as a builtin that checks the number and types of its
+/// arguments before calling it.
+pub fn builtin<Args>(name: &str, func: impl NativeFunction<Args> + 'static) -> Builtin {
+    let owned_name = name.to_string();
+
+    Builtin::new(name, move |args| {
+        if let Some(error) = builtins::check_arity(&owned_name, &args, func.arity()) {
+            return error;
+        }
+        func.call(&owned_name, args)
+    })
+}
+
+fn convert<T: FromObject>(name: &str, args: &[Object], idx: usize) -> Result<T, Object> {
+    T::from_object(&args[idx]).ok_or_else(|| {
+        let position = if args.len() == 1 {
+            String::new()
+        } else {
+            format!(" {}", idx + 1)
+        };
+        Object::Error(RuntimeError::new(format!(
+            "argument{} to `{}` must be {}, got {}",
+            position,
+            name,
+            T::expected(),
+            args[idx].type_name()
+        )))
+    })
+}
+
+macro_rules! impl_native_function {
+    ($arity:expr; $($arg:ident $value:ident $idx:tt),*) => {
+        impl<F, R, $($arg),*> NativeFunction<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R,
+            R: IntoObject,
+            $($arg: FromObject,)*
+        {
+            fn arity(&self) -> usize {
+                $arity
+            }
+
+            #[allow(unused_variables)]
+            fn call(&self, name: &str, args: Vec<Object>) -> Object {
+                $(
+                    let $value = match convert::<$arg>(name, &args, $idx) {
+                        Ok(value) => value,
+                        Err(error) => return error,
+                    };
+                )*
+                self($($value),*).into_object()
+            }
+        }
+    };
+}
+
+impl_native_function!(0;);
+impl_native_function!(1; A a 0);
+impl_native_function!(2; A a 0, B b 1);
+impl_native_function!(3; A a 0, B b 1, C c 2);
+impl_native_function!(4; A a 0, B b 1, C c 2, D d 3);
+
+#[cfg(test)]
+mod test {
+    use std::collections::{BTreeMap, HashMap};
+
+    use crate::{
+        object::{HashKey, Object},
+        Interpreter,
+    };
+
+    use super::builtin;
+
+    fn eval(interpreter: &mut Interpreter, input: &str) -> String {
+        match interpreter.eval_str(input) {
+            Ok(value) => value.inspect(),
+            Err(error) => format!("ERROR: {}", error),
+        }
+    }
+
+    #[test]
+    fn test_typed_arguments_and_results() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register_function("answer", || 42);
+        interpreter.register_function("add", |a: i64, b: i64| a + b);
+        interpreter.register_function("shout", |s: String| s.to_uppercase());
+        interpreter.register_function("not", |b: bool| !b);
+        interpreter.register_function("sqrt", |x: f64| x.sqrt());
+        interpreter.register_function("sum", |xs: Vec<i64>| xs.iter().sum::<i64>());
+        interpreter.register_function("keys", |h: HashMap<String, Object>| {
+            let mut keys: Vec<String> = h.into_keys().collect();
+            keys.sort();
+            keys
+        });
+        interpreter.register_function("lookup", |h: BTreeMap<HashKey, i64>, k: i64| {
+            h.get(&HashKey::Integer(k)).copied()
+        });
+
+        let tests = vec![
+            ("answer()", "42"),
+            ("add(1, 2)", "3"),
+            ("shout(\"hi\")", "HI"),
+            ("not(false)", "true"),
+            ("sqrt(2.25)", "1.5"),
+            ("sqrt(4)", "2.0"),
+            ("sum([1, 2, 3])", "6"),
+            ("keys({\"b\": 1, \"a\": [2]})", "[a, b]"),
+            ("lookup({1: 10}, 1)", "10"),
+            ("lookup({1: 10}, 2)", "null"),
+            ("let f = fn(x) { add(x, x) }; f(21)", "42"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(eval(&mut interpreter, input), expected, "input {}", input);
+        }
+    }
+
+    #[test]
+    fn test_argument_errors() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register_function("add", |a: i64, b: i64| a + b);
+        interpreter.register_function("shout", |s: String| s.to_uppercase());
+        interpreter.register_function("sum", |xs: Vec<i64>| xs.iter().sum::<i64>());
+
+        let tests = vec![
+            (
+                "add(1)",
+                "wrong number of arguments to `add`: want=2, got=1",
+            ),
+            (
+                "add(1, true)",
+                "argument 2 to `add` must be INTEGER, got BOOLEAN",
+            ),
+            (
+                "shout(1)",
+                "argument to `shout` must be STRING, got INTEGER",
+            ),
+            (
+                "sum([1, \"two\"])",
+                "argument to `sum` must be ARRAY of INTEGER, got ARRAY",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(
+                eval(&mut interpreter, input),
+                format!("ERROR: {}", expected),
+                "input {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_error_propagation() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register_function("parse", |s: String| s.parse::<i64>());
+
+        assert_eq!(eval(&mut interpreter, "parse(\"12\") + 1"), "13");
+        assert_eq!(
+            eval(&mut interpreter, "let x = parse(\"twelve\"); x + 1"),
+            "ERROR: invalid digit found in string"
+        );
+        assert_eq!(interpreter.get_global("x"), None);
+    }
+
+    #[test]
+    fn test_builtin_object() {
+        let double = builtin("double", |x: i64| x * 2);
+        assert_eq!((double.func)(vec![Object::Integer(4)]), Object::Integer(8));
+        assert_eq!(Object::Builtin(double).inspect(), "builtin function double");
+    }
+}
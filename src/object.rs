@@ -0,0 +1,247 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::ast::{BlockStatement, Identifier, Node};
+use crate::code::{Instructions, SourceMap};
+use crate::environment::Env;
+use crate::token::Span;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Object {
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    String(String),
+    ReturnValue(Box<Object>),
+    Error(RuntimeError),
+    Function(Rc<Function>),
+    Builtin(Builtin),
+    Array(Rc<Vec<Object>>),
+    Hash(Rc<BTreeMap<HashKey, Object>>),
+    CompiledFunction(Rc<CompiledFunction>),
+    Closure(Rc<Closure>),
+    /// A VM local captured by a closure, shared so that assignments through
+    /// either are seen by both. Only ever held in a local slot or in
+    /// `Closure::free`; reads go through to the value inside.
+    Cell(Rc<RefCell<Object>>),
+    Null,
+}
+
+impl Object {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Object::Integer(_) => "INTEGER",
+            Object::Float(_) => "FLOAT",
+            Object::Boolean(_) => "BOOLEAN",
+            Object::String(_) => "STRING",
+            Object::ReturnValue(_) => "RETURN_VALUE",
+            Object::Error(_) => "ERROR",
+            Object::Function(_) => "FUNCTION",
+            Object::Builtin(_) => "BUILTIN",
+            Object::Array(_) => "ARRAY",
+            Object::Hash(_) => "HASH",
+            Object::CompiledFunction(_) => "COMPILED_FUNCTION",
+            // Closures are how the VM represents every function value.
+            Object::Closure(_) => "FUNCTION",
+            Object::Cell(_) => "CELL",
+            Object::Null => "NULL",
+        }
+    }
+
+    pub fn inspect(&self) -> String {
+        match self {
+            Object::Integer(value) => value.to_string(),
+            // Debug formatting keeps the `.0` of whole numbers, so `2.0` doesn't look like `2`.
+            Object::Float(value) => format!("{:?}", value),
+            Object::Boolean(value) => value.to_string(),
+            Object::String(value) => value.clone(),
+            Object::ReturnValue(value) => value.inspect(),
+            Object::Error(error) => format!("ERROR: {}", error.message),
+            Object::Function(function) => function.inspect(),
+            Object::Builtin(builtin) => format!("builtin function {}", builtin.name),
+            Object::Array(elements) => {
+                let elements: Vec<String> = elements.iter().map(|e| e.inspect()).collect();
+                format!("[{}]", elements.join(", "))
+            }
+            Object::Hash(pairs) => {
+                let pairs: Vec<String> = pairs
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", key, value.inspect()))
+                    .collect();
+                format!("{{{}}}", pairs.join(", "))
+            }
+            Object::CompiledFunction(function) => function.inspect(),
+            Object::Closure(closure) => closure.func.inspect(),
+            Object::Cell(cell) => cell.borrow().inspect(),
+            Object::Null => String::from("null"),
+        }
+    }
+
+    /// The key this value is stored under in a hash, or `None` if it can't be one.
+    pub fn hash_key(&self) -> Option<HashKey> {
+        match self {
+            Object::Integer(value) => Some(HashKey::Integer(*value)),
+            Object::Boolean(value) => Some(HashKey::Boolean(*value)),
+            Object::String(value) => Some(HashKey::String(value.clone())),
+            _ => None,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, Object::Error(_))
+    }
+
+    /// Everything except `null` and `false` counts as true in a condition.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Object::Null | Object::Boolean(false))
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.inspect())
+    }
+}
+
+/// The hashable subset of objects. Hashes are ordered by key, integers
+/// first, then booleans, then strings.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum HashKey {
+    Integer(i64),
+    Boolean(bool),
+    String(String),
+}
+
+impl HashKey {
+    pub fn to_object(&self) -> Object {
+        match self {
+            HashKey::Integer(value) => Object::Integer(*value),
+            HashKey::Boolean(value) => Object::Boolean(*value),
+            HashKey::String(value) => Object::String(value.clone()),
+        }
+    }
+}
+
+impl fmt::Display for HashKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_object().inspect())
+    }
+}
+
+/// An error raised while evaluating. The span points at the expression that
+/// failed and is filled in as the error leaves it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+impl RuntimeError {
+    pub fn new(message: String) -> Self {
+        Self {
+            message,
+            span: None,
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+pub type BuiltinFunction = fn(Vec<Object>) -> Object;
+
+/// A function implemented in Rust: one of `builtins`, or one registered by
+/// the host, see `native`.
+#[derive(Clone)]
+pub struct Builtin {
+    pub name: Rc<str>,
+    pub func: Rc<dyn Fn(Vec<Object>) -> Object>,
+}
+
+impl Builtin {
+    pub fn new(name: &str, func: impl Fn(Vec<Object>) -> Object + 'static) -> Self {
+        Self {
+            name: Rc::from(name),
+            func: Rc::new(func),
+        }
+    }
+}
+
+impl fmt::Debug for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Builtin({})", self.name)
+    }
+}
+
+impl PartialEq for Builtin {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+/// A user defined function closing over the environment it was created in.
+pub struct Function {
+    pub parameters: Vec<Identifier>,
+    pub body: BlockStatement,
+    pub env: Env,
+}
+
+impl Function {
+    pub fn inspect(&self) -> String {
+        let params: Vec<String> = self
+            .parameters
+            .iter()
+            .map(|param| param.print_string())
+            .collect();
+
+        format!("fn({}) {}", params.join(", "), self.body.print_string())
+    }
+}
+
+// The captured environment usually contains the function itself, so both
+// impls stay away from it to avoid recursing through the cycle.
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Function({})", self.inspect())
+    }
+}
+
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// A function body lowered to bytecode by the compiler.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CompiledFunction {
+    pub instructions: Instructions,
+    /// Parameters plus locals, the stack slots reserved for each call.
+    pub num_locals: usize,
+    pub num_parameters: usize,
+    /// The name of the `let` binding the function was defined in, if any.
+    pub name: Option<String>,
+    /// Where each instruction came from, empty when debug info was stripped.
+    pub spans: SourceMap,
+}
+
+impl CompiledFunction {
+    pub fn inspect(&self) -> String {
+        match &self.name {
+            Some(name) => format!("fn {}/{}", name, self.num_parameters),
+            None => format!("fn <anonymous>/{}", self.num_parameters),
+        }
+    }
+}
+
+/// A compiled function together with the free variables it captured.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Closure {
+    pub func: Rc<CompiledFunction>,
+    pub free: Vec<Object>,
+}
@@ -1,42 +1,787 @@
-use crate::{ast::Program, lexer::Lexer, token::Token};
+use std::{collections::HashMap, fmt};
 
-pub struct Parser {
-  lexer: Lexer,
-  curr_token: Token,
-  peek_token: Token,
+use crate::{
+  ast::{
+    ArrayLiteral, AssignExpression, BlockStatement, Boolean, CallExpression, ExpressionNode,
+    ExpressionStatement, FloatLiteral, FunctionLiteral, HashLiteral, Identifier, IfExpression,
+    IndexExpression, InfixExpression, IntegerLiteral, LetStatement, Node, PrefixExpression,
+    Program, ReturnStatement, StatementNode, StringLiteral,
+  },
+  lexer::{BorrowedToken, LexError, Lexer, TokenStream},
+  token::{Span, Token, TokenKind},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+  /// A specific token was required but a different one was found.
+  UnexpectedToken { expected: TokenKind, found: Token },
+  /// The input ended while a construct was still open.
+  UnexpectedEof { expected: Option<TokenKind>, span: Span },
+  /// Two statements were written back to back without a `;` between them.
+  MissingSemicolon { found: Token },
+  /// A token that cannot start an expression was found where one was required.
+  ExpectedExpression { found: Token },
+  /// The lexer rejected part of the input.
+  Lex(LexError),
+  /// An integer literal that doesn't fit in an `i64`.
+  InvalidInteger { token: Token },
+  /// A float literal too large to be represented as a finite `f64`.
+  InvalidFloat { token: Token },
+  /// The left side of a compound assignment such as `+=` isn't a name.
+  InvalidAssignmentTarget { operator: TokenKind, span: Span },
+}
+
+impl ParseError {
+  pub fn span(&self) -> Span {
+    match self {
+      ParseError::UnexpectedToken { found, .. } => found.span,
+      ParseError::UnexpectedEof { span, .. } => *span,
+      ParseError::MissingSemicolon { found } => found.span,
+      ParseError::ExpectedExpression { found } => found.span,
+      ParseError::Lex(error) => error.span(),
+      ParseError::InvalidInteger { token } => token.span,
+      ParseError::InvalidFloat { token } => token.span,
+      ParseError::InvalidAssignmentTarget { span, .. } => *span,
+    }
+  }
+
+  pub fn is_unexpected_eof(&self) -> bool {
+    matches!(self, ParseError::UnexpectedEof { .. })
+  }
+}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ParseError::UnexpectedToken { expected, found } => {
+        write!(f, "expected {}, found {}", expected, found.kind)
+      }
+      ParseError::UnexpectedEof {
+        expected: Some(expected),
+        ..
+      } => write!(f, "unexpected end of input, expected {}", expected),
+      ParseError::UnexpectedEof { expected: None, .. } => write!(f, "unexpected end of input"),
+      ParseError::MissingSemicolon { found } => {
+        write!(f, "expected `;` after statement, found {}", found.kind)
+      }
+      ParseError::ExpectedExpression { found } => {
+        write!(f, "expected an expression, found {}", found.kind)
+      }
+      ParseError::Lex(error) => write!(f, "{}", error),
+      ParseError::InvalidInteger { token } => {
+        write!(f, "integer literal `{}` is out of range", token.literal)
+      }
+      ParseError::InvalidFloat { token } => {
+        write!(f, "float literal `{}` is out of range", token.literal)
+      }
+      ParseError::InvalidAssignmentTarget { operator, .. } => {
+        write!(f, "left side of {} must be an identifier", operator)
+      }
+    }
+  }
+}
+
+type PrefixParseFn<'a> = fn(&mut Parser<'a>) -> Option<ExpressionNode>;
+type InfixParseFn<'a> = fn(&mut Parser<'a>, ExpressionNode) -> Option<ExpressionNode>;
+
+#[derive(PartialEq, PartialOrd, Debug, Clone, Copy)]
+enum Precedence {
+  Lowest,
+  Assign,      // x += 1
+  Or,          // ||
+  And,         // &&
+  Equals,      // ==
+  LessGreater, // > or <
+  Sum,         // +
+  Product,     // *
+  Prefix,      // -X or !X
+  Power,       // 2 ** 3
+  Call,        // myFunction(X)
+  Index,       // array[index]
 }
 
-impl Parser {
+fn precedence_of(kind: &TokenKind) -> Precedence {
+  match kind {
+    TokenKind::PlusAssign
+    | TokenKind::MinusAssign
+    | TokenKind::AsteriskAssign
+    | TokenKind::SlashAssign => Precedence::Assign,
+    TokenKind::Or => Precedence::Or,
+    TokenKind::And => Precedence::And,
+    TokenKind::Eq | TokenKind::NotEq => Precedence::Equals,
+    TokenKind::Lt | TokenKind::Gt | TokenKind::LtEq | TokenKind::GtEq => Precedence::LessGreater,
+    TokenKind::Plus | TokenKind::Minus => Precedence::Sum,
+    TokenKind::Slash | TokenKind::Asterisk | TokenKind::Percent => Precedence::Product,
+    TokenKind::Power => Precedence::Power,
+    TokenKind::Lparen => Precedence::Call,
+    TokenKind::Lbracket => Precedence::Index,
+    _ => Precedence::Lowest,
+  }
+}
 
-   pub fn new(lexer: Lexer) -> Self {
-   let mut parser = Self {
-      lexer: lexer,
+/// The value of an integer literal as the lexer accepted it, with an
+/// optional radix prefix and `_` separators, or `None` if it overflows.
+fn integer_value(literal: &str) -> Option<i64> {
+  let digits = literal.replace('_', "");
+  let (radix, digits) = match digits.get(..2) {
+    Some("0x") => (16, &digits[2..]),
+    Some("0b") => (2, &digits[2..]),
+    Some("0o") => (8, &digits[2..]),
+    _ => (10, &digits[..]),
+  };
+  i64::from_str_radix(digits, radix).ok()
+}
+
+pub struct Parser<'a> {
+  tokens: TokenStream<'a>,
+  curr_token: BorrowedToken<'a>,
+  errors: Vec<ParseError>,
+  /// Number of `{` before `curr_token` that are still open, used for error recovery.
+  brace_depth: usize,
+  prefix_parse_fns: HashMap<TokenKind, PrefixParseFn<'a>>,
+  infix_parse_fns: HashMap<TokenKind, InfixParseFn<'a>>,
+}
+
+impl<'a> Parser<'a> {
+  pub fn new(lexer: Lexer<'a>) -> Self {
+    let mut parser = Self {
+      tokens: TokenStream::new(lexer),
       curr_token: Default::default(),
-      peek_token: Default::default(),
+      errors: vec![],
+      brace_depth: 0,
+      prefix_parse_fns: HashMap::new(),
+      infix_parse_fns: HashMap::new(),
     };
 
+    parser.register_prefix(TokenKind::Ident, Self::parse_identifier);
+    parser.register_prefix(TokenKind::Int, Self::parse_integer_literal);
+    parser.register_prefix(TokenKind::Float, Self::parse_float_literal);
+    parser.register_prefix(TokenKind::String, Self::parse_string_literal);
+    parser.register_prefix(TokenKind::True, Self::parse_boolean);
+    parser.register_prefix(TokenKind::False, Self::parse_boolean);
+    parser.register_prefix(TokenKind::Bang, Self::parse_prefix_expression);
+    parser.register_prefix(TokenKind::Minus, Self::parse_prefix_expression);
+    parser.register_prefix(TokenKind::Lparen, Self::parse_grouped_expression);
+    parser.register_prefix(TokenKind::If, Self::parse_if_expression);
+    parser.register_prefix(TokenKind::Function, Self::parse_function_literal);
+    parser.register_prefix(TokenKind::Lbracket, Self::parse_array_literal);
+    // Blocks are only parsed where `if`, `else` and `fn` expect one, so a `{`
+    // reached through expression parsing always opens a hash literal.
+    parser.register_prefix(TokenKind::Lbrace, Self::parse_hash_literal);
+
+    for kind in [
+      TokenKind::Plus,
+      TokenKind::Minus,
+      TokenKind::Slash,
+      TokenKind::Asterisk,
+      TokenKind::Percent,
+      TokenKind::Power,
+      TokenKind::Eq,
+      TokenKind::NotEq,
+      TokenKind::Lt,
+      TokenKind::Gt,
+      TokenKind::LtEq,
+      TokenKind::GtEq,
+      TokenKind::And,
+      TokenKind::Or,
+    ] {
+      parser.register_infix(kind, Self::parse_infix_expression);
+    }
+    for kind in [
+      TokenKind::PlusAssign,
+      TokenKind::MinusAssign,
+      TokenKind::AsteriskAssign,
+      TokenKind::SlashAssign,
+    ] {
+      parser.register_infix(kind, Self::parse_assign_expression);
+    }
+    parser.register_infix(TokenKind::Lparen, Self::parse_call_expression);
+    parser.register_infix(TokenKind::Lbracket, Self::parse_index_expression);
+
     parser.next_token();
-    parser.next_token();
-    
+
     parser
-   }
+  }
+
+  fn register_prefix(&mut self, kind: TokenKind, func: PrefixParseFn<'a>) {
+    self.prefix_parse_fns.insert(kind, func);
+  }
+
+  fn register_infix(&mut self, kind: TokenKind, func: InfixParseFn<'a>) {
+    self.infix_parse_fns.insert(kind, func);
+  }
+
+  fn next_token(&mut self) {
+    self.brace_depth = self.depth_after_curr();
+    self.curr_token = self.tokens.next_token();
+
+    for error in self.tokens.take_errors() {
+      self.errors.push(ParseError::Lex(error));
+    }
+  }
+
+  fn curr_token_is(&self, kind: &TokenKind) -> bool {
+    &self.curr_token.kind == kind
+  }
+
+  fn peek_token_is(&self, kind: &TokenKind) -> bool {
+    &self.tokens.peek().kind == kind
+  }
+
+  fn expect_peek(&mut self, kind: TokenKind) -> bool {
+    if self.peek_token_is(&kind) {
+      self.next_token();
+      return true;
+    }
+    self.peek_error(kind);
+    false
+  }
+
+  fn peek_error(&mut self, kind: TokenKind) {
+    let error = match self.tokens.peek().kind {
+      TokenKind::Eof => ParseError::UnexpectedEof {
+        expected: Some(kind),
+        span: self.tokens.peek().span,
+      },
+      _ => ParseError::UnexpectedToken {
+        expected: kind,
+        found: self.tokens.peek().to_token(),
+      },
+    };
+    self.errors.push(error);
+  }
+
+  fn no_prefix_parse_fn_error(&mut self) {
+    let found = self.curr_token.to_token();
+    let error = match found.kind {
+      TokenKind::Eof => ParseError::UnexpectedEof {
+        expected: None,
+        span: found.span,
+      },
+      // The lexer has already reported why the token is illegal.
+      TokenKind::Illegal => return,
+      _ => ParseError::ExpectedExpression { found },
+    };
+    self.errors.push(error);
+  }
+
+  /// Consumes the `;` ending the current statement. It may only be left out
+  /// before a `}` or the end of input, or after a statement ending in a block.
+  fn expect_statement_end(&mut self) {
+    if self.peek_token_is(&TokenKind::Semicolon) {
+      self.next_token();
+      return;
+    }
+
+    match self.tokens.peek().kind {
+      TokenKind::Rbrace | TokenKind::Eof | TokenKind::Illegal => {}
+      _ if self.curr_token_is(&TokenKind::Rbrace) => {}
+      _ => self.errors.push(ParseError::MissingSemicolon {
+        found: self.tokens.peek().to_token(),
+      }),
+    }
+  }
+
+  /// Number of `{` left open once the current token has been consumed.
+  fn depth_after_curr(&self) -> usize {
+    match self.curr_token.kind {
+      TokenKind::Lbrace => self.brace_depth + 1,
+      TokenKind::Rbrace => self.brace_depth.saturating_sub(1),
+      _ => self.brace_depth,
+    }
+  }
+
+  /// Skips the rest of a broken statement that started `depth` braces deep, so
+  /// parsing can resume at the next one. Once back at that depth it stops on the
+  /// statement's `;`, or before a `let`, `return` or `}` that starts something new.
+  fn synchronize(&mut self, depth: usize) {
+    while !self.curr_token_is(&TokenKind::Eof) {
+      if self.depth_after_curr() <= depth {
+        if self.curr_token_is(&TokenKind::Semicolon) {
+          return;
+        }
+        if matches!(
+          self.tokens.peek().kind,
+          TokenKind::Let | TokenKind::Return | TokenKind::Rbrace
+        ) {
+          return;
+        }
+      }
+
+      if self.peek_token_is(&TokenKind::Eof) {
+        return;
+      }
+      self.next_token();
+    }
+  }
+
+  fn peek_precedence(&self) -> Precedence {
+    precedence_of(&self.tokens.peek().kind)
+  }
+
+  fn curr_precedence(&self) -> Precedence {
+    precedence_of(&self.curr_token.kind)
+  }
+
+  /// Parses the whole input. Statements that fail to parse are reported and
+  /// skipped, so the returned program holds everything that did parse.
+  pub fn parse_program(&mut self) -> (Program, Vec<ParseError>) {
+    let mut program = Program { statements: vec![] };
+
+    while !self.curr_token_is(&TokenKind::Eof) {
+      let depth = self.brace_depth;
+      match self.parse_statement() {
+        Some(stmt) => program.statements.push(stmt),
+        None => self.synchronize(depth),
+      }
+      self.next_token();
+    }
+
+    (program, std::mem::take(&mut self.errors))
+  }
+
+  fn parse_statement(&mut self) -> Option<StatementNode> {
+    match self.curr_token.kind {
+      TokenKind::Let => self.parse_let_statement().map(StatementNode::Let),
+      TokenKind::Return => self.parse_return_statement().map(StatementNode::Return),
+      _ => self.parse_expression_statement().map(StatementNode::Expression),
+    }
+  }
+
+  fn parse_let_statement(&mut self) -> Option<LetStatement> {
+    let token = self.curr_token.to_token();
+    let doc = self.tokens.take_doc();
+
+    if !self.expect_peek(TokenKind::Ident) {
+      return None;
+    }
+
+    let name = Identifier {
+      token: self.curr_token.to_token(),
+      value: self.curr_token.literal.to_string(),
+    };
+
+    if !self.expect_peek(TokenKind::Assign) {
+      return None;
+    }
+
+    self.next_token();
+    let mut value = self.parse_expression(Precedence::Lowest)?;
+    self.expect_statement_end();
+
+    if let ExpressionNode::Function(function) = &mut value {
+      function.name = Some(name.value.clone());
+    }
+
+    Some(LetStatement { doc, token, name, value })
+  }
+
+  fn parse_return_statement(&mut self) -> Option<ReturnStatement> {
+    let token = self.curr_token.to_token();
+
+    self.next_token();
+    let return_value = self.parse_expression(Precedence::Lowest)?;
+    self.expect_statement_end();
+
+    Some(ReturnStatement {
+      token,
+      return_value,
+    })
+  }
+
+  fn parse_expression_statement(&mut self) -> Option<ExpressionStatement> {
+    let token = self.curr_token.to_token();
+    let expression = self.parse_expression(Precedence::Lowest)?;
+    self.expect_statement_end();
+
+    Some(ExpressionStatement { token, expression })
+  }
+
+  fn parse_expression(&mut self, precedence: Precedence) -> Option<ExpressionNode> {
+    let prefix = match self.prefix_parse_fns.get(&self.curr_token.kind) {
+      Some(prefix) => *prefix,
+      None => {
+        self.no_prefix_parse_fn_error();
+        return None;
+      }
+    };
+
+    let mut left = prefix(self)?;
+
+    while !self.peek_token_is(&TokenKind::Semicolon) && precedence < self.peek_precedence() {
+      let infix = match self.infix_parse_fns.get(&self.tokens.peek().kind) {
+        Some(infix) => *infix,
+        None => return Some(left),
+      };
+
+      self.next_token();
+      left = infix(self, left)?;
+    }
+
+    Some(left)
+  }
+
+  fn parse_identifier(&mut self) -> Option<ExpressionNode> {
+    Some(ExpressionNode::IdentifierNode(Identifier {
+      token: self.curr_token.to_token(),
+      value: self.curr_token.literal.to_string(),
+    }))
+  }
+
+  fn parse_integer_literal(&mut self) -> Option<ExpressionNode> {
+    match integer_value(&self.curr_token.literal) {
+      Some(value) => Some(ExpressionNode::Integer(IntegerLiteral {
+        token: self.curr_token.to_token(),
+        value,
+      })),
+      None => {
+        self.errors.push(ParseError::InvalidInteger {
+          token: self.curr_token.to_token(),
+        });
+        None
+      }
+    }
+  }
+
+  fn parse_float_literal(&mut self) -> Option<ExpressionNode> {
+    let digits = self.curr_token.literal.replace('_', "");
+    match digits.parse::<f64>() {
+      Ok(value) if value.is_finite() => Some(ExpressionNode::Float(FloatLiteral {
+        token: self.curr_token.to_token(),
+        value,
+      })),
+      _ => {
+        self.errors.push(ParseError::InvalidFloat {
+          token: self.curr_token.to_token(),
+        });
+        None
+      }
+    }
+  }
+
+  fn parse_string_literal(&mut self) -> Option<ExpressionNode> {
+    Some(ExpressionNode::StringNode(StringLiteral {
+      token: self.curr_token.to_token(),
+      value: self.curr_token.literal.to_string(),
+    }))
+  }
+
+  fn parse_boolean(&mut self) -> Option<ExpressionNode> {
+    Some(ExpressionNode::BooleanNode(Boolean {
+      token: self.curr_token.to_token(),
+      value: self.curr_token_is(&TokenKind::True),
+    }))
+  }
+
+  fn parse_prefix_expression(&mut self) -> Option<ExpressionNode> {
+    let token = self.curr_token.to_token();
+    let operator = self.curr_token.literal.to_string();
+
+    self.next_token();
+    let right = self.parse_expression(Precedence::Prefix)?;
+
+    Some(ExpressionNode::Prefix(PrefixExpression {
+      token,
+      operator,
+      right: Box::new(right),
+    }))
+  }
+
+  fn parse_infix_expression(&mut self, left: ExpressionNode) -> Option<ExpressionNode> {
+    let token = self.curr_token.to_token();
+    let operator = self.curr_token.literal.to_string();
+
+    // `**` is right associative, so its right side may hold another `**`.
+    let precedence = match self.curr_precedence() {
+      Precedence::Power => Precedence::Prefix,
+      precedence => precedence,
+    };
+    self.next_token();
+    let right = self.parse_expression(precedence)?;
+
+    Some(ExpressionNode::Infix(InfixExpression {
+      token,
+      left: Box::new(left),
+      operator,
+      right: Box::new(right),
+    }))
+  }
+
+  fn parse_assign_expression(&mut self, left: ExpressionNode) -> Option<ExpressionNode> {
+    let token = self.curr_token.to_token();
+    let name = match left {
+      ExpressionNode::IdentifierNode(name) => name,
+      _ => {
+        self.errors.push(ParseError::InvalidAssignmentTarget {
+          operator: token.kind,
+          span: left.span(),
+        });
+        return None;
+      }
+    };
+
+    // Parsed below assignment precedence so `a += b += 1` assigns right to left.
+    self.next_token();
+    let value = self.parse_expression(Precedence::Lowest)?;
+
+    Some(ExpressionNode::Assign(AssignExpression {
+      operator: token.literal.clone(),
+      token,
+      name,
+      value: Box::new(value),
+    }))
+  }
+
+  fn parse_grouped_expression(&mut self) -> Option<ExpressionNode> {
+    self.next_token();
+    let expression = self.parse_expression(Precedence::Lowest)?;
+
+    if !self.expect_peek(TokenKind::Rparen) {
+      return None;
+    }
+
+    Some(expression)
+  }
+
+  fn parse_if_expression(&mut self) -> Option<ExpressionNode> {
+    let token = self.curr_token.to_token();
+
+    if !self.expect_peek(TokenKind::Lparen) {
+      return None;
+    }
+
+    self.next_token();
+    let condition = self.parse_expression(Precedence::Lowest)?;
+
+    if !self.expect_peek(TokenKind::Rparen) {
+      return None;
+    }
+
+    if !self.expect_peek(TokenKind::Lbrace) {
+      return None;
+    }
+
+    let consequence = self.parse_block_statement()?;
+
+    let mut alternative = None;
+    if self.peek_token_is(&TokenKind::Else) {
+      self.next_token();
+
+      if !self.expect_peek(TokenKind::Lbrace) {
+        return None;
+      }
+
+      alternative = Some(self.parse_block_statement()?);
+    }
+
+    Some(ExpressionNode::IfExpressionNode(IfExpression {
+      token,
+      condition: Box::new(condition),
+      consequence,
+      alternative,
+    }))
+  }
+
+  fn parse_block_statement(&mut self) -> Option<BlockStatement> {
+    let token = self.curr_token.to_token();
+    let mut statements = vec![];
+
+    self.next_token();
+
+    while !self.curr_token_is(&TokenKind::Rbrace) {
+      if self.curr_token_is(&TokenKind::Eof) {
+        self.errors.push(ParseError::UnexpectedEof {
+          expected: Some(TokenKind::Rbrace),
+          span: self.curr_token.span,
+        });
+        return None;
+      }
+
+      let depth = self.brace_depth;
+      match self.parse_statement() {
+        Some(stmt) => statements.push(stmt),
+        None => self.synchronize(depth),
+      }
+      self.next_token();
+    }
+
+    let span = token.span.merge(self.curr_token.span);
+    Some(BlockStatement {
+      token,
+      statements,
+      span,
+    })
+  }
 
-   fn next_token(&mut self){ 
-    self.curr_token = self.peek_token.clone();
-    self.peek_token = self.lexer.next_token();
-   }
+  fn parse_function_literal(&mut self) -> Option<ExpressionNode> {
+    let token = self.curr_token.to_token();
 
-   pub fn parse_program(&mut self) -> Option<Program> {
-    None
-   }
+    if !self.expect_peek(TokenKind::Lparen) {
+      return None;
+    }
+
+    let parameters = self.parse_function_parameters()?;
+
+    if !self.expect_peek(TokenKind::Lbrace) {
+      return None;
+    }
+
+    let body = self.parse_block_statement()?;
+
+    Some(ExpressionNode::Function(FunctionLiteral {
+      token,
+      parameters,
+      body,
+      name: None,
+    }))
+  }
+
+  fn parse_function_parameters(&mut self) -> Option<Vec<Identifier>> {
+    let mut identifiers = vec![];
+
+    if self.peek_token_is(&TokenKind::Rparen) {
+      self.next_token();
+      return Some(identifiers);
+    }
+
+    if !self.expect_peek(TokenKind::Ident) {
+      return None;
+    }
+    identifiers.push(Identifier {
+      token: self.curr_token.to_token(),
+      value: self.curr_token.literal.to_string(),
+    });
+
+    while self.peek_token_is(&TokenKind::Comma) {
+      self.next_token();
+      if !self.expect_peek(TokenKind::Ident) {
+        return None;
+      }
+      identifiers.push(Identifier {
+        token: self.curr_token.to_token(),
+        value: self.curr_token.literal.to_string(),
+      });
+    }
+
+    if !self.expect_peek(TokenKind::Rparen) {
+      return None;
+    }
+
+    Some(identifiers)
+  }
+
+  fn parse_call_expression(&mut self, function: ExpressionNode) -> Option<ExpressionNode> {
+    let token = self.curr_token.to_token();
+    let arguments = self.parse_expression_list(TokenKind::Rparen)?;
+    let span = function.span().merge(self.curr_token.span);
+
+    Some(ExpressionNode::Call(CallExpression {
+      token,
+      function: Box::new(function),
+      arguments,
+      span,
+    }))
+  }
+
+  fn parse_array_literal(&mut self) -> Option<ExpressionNode> {
+    let token = self.curr_token.to_token();
+    let elements = self.parse_expression_list(TokenKind::Rbracket)?;
+    let span = token.span.merge(self.curr_token.span);
+
+    Some(ExpressionNode::Array(ArrayLiteral {
+      token,
+      elements,
+      span,
+    }))
+  }
+
+  fn parse_hash_literal(&mut self) -> Option<ExpressionNode> {
+    let token = self.curr_token.to_token();
+    let mut pairs = vec![];
+
+    while !self.peek_token_is(&TokenKind::Rbrace) {
+      self.next_token();
+      let key = self.parse_expression(Precedence::Lowest)?;
+
+      if !self.expect_peek(TokenKind::Colon) {
+        return None;
+      }
+
+      self.next_token();
+      let value = self.parse_expression(Precedence::Lowest)?;
+      pairs.push((key, value));
+
+      if !self.peek_token_is(&TokenKind::Rbrace) && !self.expect_peek(TokenKind::Comma) {
+        return None;
+      }
+    }
+
+    if !self.expect_peek(TokenKind::Rbrace) {
+      return None;
+    }
+
+    let span = token.span.merge(self.curr_token.span);
+    Some(ExpressionNode::Hash(HashLiteral {
+      token,
+      pairs,
+      span,
+    }))
+  }
+
+  fn parse_index_expression(&mut self, left: ExpressionNode) -> Option<ExpressionNode> {
+    let token = self.curr_token.to_token();
+
+    self.next_token();
+    let index = self.parse_expression(Precedence::Lowest)?;
+
+    if !self.expect_peek(TokenKind::Rbracket) {
+      return None;
+    }
+
+    let span = left.span().merge(self.curr_token.span);
+    Some(ExpressionNode::Index(IndexExpression {
+      token,
+      left: Box::new(left),
+      index: Box::new(index),
+      span,
+    }))
+  }
+
+  /// Parses comma separated expressions up to and including `end`.
+  fn parse_expression_list(&mut self, end: TokenKind) -> Option<Vec<ExpressionNode>> {
+    let mut list = vec![];
+
+    if self.peek_token_is(&end) {
+      self.next_token();
+      return Some(list);
+    }
+
+    self.next_token();
+    list.push(self.parse_expression(Precedence::Lowest)?);
+
+    while self.peek_token_is(&TokenKind::Comma) {
+      self.next_token();
+      self.next_token();
+      list.push(self.parse_expression(Precedence::Lowest)?);
+    }
+
+    if !self.expect_peek(end) {
+      return None;
+    }
+
+    Some(list)
+  }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::{ast::{Node, StatementNode}, lexer::Lexer};
-
-    use super::Parser;
+  use crate::{
+    ast::{ExpressionNode, Node, Program, StatementNode},
+    lexer::Lexer,
+    token::{Span, TokenKind},
+  };
 
+  use super::{ParseError, Parser};
 
   #[test]
   fn test_let_statements() {
@@ -47,30 +792,498 @@ mod test {
     "#;
     let lexer = Lexer::new(input);
     let mut parser = Parser::new(lexer);
-    let program = parser.parse_program();
-    match program {
-      Some(prog) => {
-      assert_eq!(prog.statements.len(), 3);
+    let (prog, errors) = parser.parse_program();
+    check_parser_errors(&errors);
+    assert_eq!(prog.statements.len(), 3);
+
+    let expected = vec!["x", "y", "foobar"];
+
+    for (idx, exp) in expected.into_iter().enumerate() {
+      let stmt = &prog.statements[idx];
+      test_let_statement(stmt, exp);
+    }
+  }
 
-      let expected = vec!["x", "y", "foobar"];
+  #[test]
+  fn test_let_statement_values() {
+    let tests = vec![
+      ("let x = 5;", "x", "5"),
+      ("let y = true;", "y", "true"),
+      ("let foobar = y;", "foobar", "y"),
+    ];
 
-      for (idx, exp) in expected.into_iter().enumerate() {
-        let stmt = &prog.statements[idx];
-        test_let_statement(stmt, exp);
+    for (input, name, value) in tests {
+      let program = parse(input);
+      assert_eq!(program.statements.len(), 1);
+      test_let_statement(&program.statements[0], name);
+      match &program.statements[0] {
+        StatementNode::Let(let_stmt) => {
+          assert_eq!(let_stmt.value.print_string(), value);
+        }
+        _ => panic!("Statement is not let statement"),
       }
-    },
-    None => panic!("Expected a program but got None"),
+    }
   }
-}
 
-fn test_let_statement(stmt: &StatementNode, expected: &str) {
-  assert_eq!(stmt.token_literal(), "let", "Token literal not let");
-  match stmt {
-    StatementNode::Let(let_stmt) => {
-      assert_eq!(let_stmt.name.value, expected, "Name not {}", expected);
-    },
-    _ => panic!("Statement is not let statement")
+  #[test]
+  fn test_return_statements() {
+    let program = parse("return 5; return 10; return 993322;");
+    assert_eq!(program.statements.len(), 3);
+
+    for stmt in program.statements {
+      match stmt {
+        StatementNode::Return(ret) => assert_eq!(ret.token_literal(), "return"),
+        _ => panic!("Statement is not return statement"),
+      }
+    }
+  }
+
+  #[test]
+  fn test_identifier_and_literal_expressions() {
+    let program = parse("foobar; 5; true; false; \"hello world\";");
+    assert_eq!(program.statements.len(), 5);
+
+    match expression_of(&program.statements[0]) {
+      ExpressionNode::IdentifierNode(ident) => assert_eq!(ident.value, "foobar"),
+      other => panic!("expected identifier, got {:?}", other),
+    }
+    match expression_of(&program.statements[1]) {
+      ExpressionNode::Integer(int) => assert_eq!(int.value, 5),
+      other => panic!("expected integer, got {:?}", other),
+    }
+    match expression_of(&program.statements[2]) {
+      ExpressionNode::BooleanNode(boolean) => assert!(boolean.value),
+      other => panic!("expected boolean, got {:?}", other),
+    }
+    match expression_of(&program.statements[3]) {
+      ExpressionNode::BooleanNode(boolean) => assert!(!boolean.value),
+      other => panic!("expected boolean, got {:?}", other),
+    }
+    match expression_of(&program.statements[4]) {
+      ExpressionNode::StringNode(string) => assert_eq!(string.value, "hello world"),
+      other => panic!("expected string, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn test_number_literals() {
+    let integers = vec![
+      ("1_000_000", 1_000_000),
+      ("0xff", 255),
+      ("0b1010", 10),
+      ("0o17", 15),
+      ("0x7fff_ffff_ffff_ffff", i64::MAX),
+    ];
+    for (input, expected) in integers {
+      match expression_of(&parse(input).statements[0]) {
+        ExpressionNode::Integer(int) => assert_eq!(int.value, expected, "input {}", input),
+        other => panic!("expected integer for {}, got {:?}", input, other),
+      }
+    }
+
+    let floats = vec![("3.25", 3.25), ("1e-9", 1e-9), ("1_0.5e1_0", 10.5e10), ("2E3", 2000.0)];
+    for (input, expected) in floats {
+      match expression_of(&parse(input).statements[0]) {
+        ExpressionNode::Float(float) => assert_eq!(float.value, expected, "input {}", input),
+        other => panic!("expected float for {}, got {:?}", input, other),
+      }
+    }
+  }
+
+  #[test]
+  fn test_prefix_and_infix_expressions() {
+    let tests = vec![
+      ("!5;", "!", "5"),
+      ("-15;", "-", "15"),
+      ("!true;", "!", "true"),
+    ];
+
+    for (input, operator, right) in tests {
+      let program = parse(input);
+      match expression_of(&program.statements[0]) {
+        ExpressionNode::Prefix(prefix) => {
+          assert_eq!(prefix.operator, operator);
+          assert_eq!(prefix.right.print_string(), right);
+        }
+        other => panic!("expected prefix expression, got {:?}", other),
+      }
+    }
+
+    let tests = vec![
+      ("5 + 5;", "5", "+", "5"),
+      ("5 - 5;", "5", "-", "5"),
+      ("5 * 5;", "5", "*", "5"),
+      ("5 / 5;", "5", "/", "5"),
+      ("5 > 5;", "5", ">", "5"),
+      ("5 < 5;", "5", "<", "5"),
+      ("5 == 5;", "5", "==", "5"),
+      ("5 != 5;", "5", "!=", "5"),
+      ("true == false", "true", "==", "false"),
+    ];
+
+    for (input, left, operator, right) in tests {
+      let program = parse(input);
+      match expression_of(&program.statements[0]) {
+        ExpressionNode::Infix(infix) => {
+          assert_eq!(infix.left.print_string(), left);
+          assert_eq!(infix.operator, operator);
+          assert_eq!(infix.right.print_string(), right);
+        }
+        other => panic!("expected infix expression, got {:?}", other),
+      }
+    }
+  }
+
+  #[test]
+  fn test_operator_precedence_parsing() {
+    let tests = vec![
+      ("-a * b", "((-a) * b)"),
+      ("!-a", "(!(-a))"),
+      ("a + b + c", "((a + b) + c)"),
+      ("a + b - c", "((a + b) - c)"),
+      ("a * b * c", "((a * b) * c)"),
+      ("a * b / c", "((a * b) / c)"),
+      ("a + b / c", "(a + (b / c))"),
+      ("a + b * c + d / e - f", "(((a + (b * c)) + (d / e)) - f)"),
+      ("3 + 4; -5 * 5", "(3 + 4);((-5) * 5);"),
+      ("5 > 4 == 3 < 4", "((5 > 4) == (3 < 4))"),
+      ("5 < 4 != 3 > 4", "((5 < 4) != (3 > 4))"),
+      ("3 + 4 * 5 == 3 * 1 + 4 * 5", "((3 + (4 * 5)) == ((3 * 1) + (4 * 5)))"),
+      ("true", "true"),
+      ("3 > 5 == false", "((3 > 5) == false)"),
+      ("1 + (2 + 3) + 4", "((1 + (2 + 3)) + 4)"),
+      ("(5 + 5) * 2", "((5 + 5) * 2)"),
+      ("2 / (5 + 5)", "(2 / (5 + 5))"),
+      ("-(5 + 5)", "(-(5 + 5))"),
+      ("!(true == true)", "(!(true == true))"),
+      ("a + add(b * c) + d", "((a + add((b * c))) + d)"),
+      (
+        "add(a, b, 1, 2 * 3, 4 + 5, add(6, 7 * 8))",
+        "add(a, b, 1, (2 * 3), (4 + 5), add(6, (7 * 8)))",
+      ),
+      ("add(a + b + c * d / f + g)", "add((((a + b) + ((c * d) / f)) + g))"),
+      ("a * [1, 2, 3, 4][b * c] * d", "((a * ([1, 2, 3, 4][(b * c)])) * d)"),
+      ("add(a * b[2], b[1], 2 * [1, 2][1])", "add((a * (b[2])), (b[1]), (2 * ([1, 2][1])))"),
+      ("f(x)[0]", "(f(x)[0])"),
+      ("a <= b == b >= a", "((a <= b) == (b >= a))"),
+      ("a || b && c", "(a || (b && c))"),
+      ("a && b || c && d", "((a && b) || (c && d))"),
+      ("a == b && c != d", "((a == b) && (c != d))"),
+      ("a + b % c * d", "(a + ((b % c) * d))"),
+      ("2 ** 3 ** 2", "(2 ** (3 ** 2))"),
+      ("a * b ** c", "(a * (b ** c))"),
+      ("-a ** b", "(-(a ** b))"),
+      ("a ** -b", "(a ** (-b))"),
+      ("x += 1 + 2", "(x += (1 + 2))"),
+      ("x -= y *= 2", "(x -= (y *= 2))"),
+      ("x /= a || b", "(x /= (a || b))"),
+    ];
+
+    for (input, expected) in tests {
+      let program = parse(input);
+      let actual = program.print_string();
+      let expected = if expected.ends_with(';') {
+        expected.to_string()
+      } else {
+        format!("{};", expected)
+      };
+      assert_eq!(actual, expected, "input {}", input);
+    }
+  }
+
+  #[test]
+  fn test_if_expressions() {
+    let program = parse("if (x < y) { x } else { y }");
+    assert_eq!(program.statements.len(), 1);
+
+    match expression_of(&program.statements[0]) {
+      ExpressionNode::IfExpressionNode(if_exp) => {
+        assert_eq!(if_exp.condition.print_string(), "(x < y)");
+        assert_eq!(if_exp.consequence.statements.len(), 1);
+        assert_eq!(if_exp.consequence.print_string(), "{ x; }");
+        let alternative = if_exp.alternative.as_ref().expect("alternative missing");
+        assert_eq!(alternative.print_string(), "{ y; }");
+      }
+      other => panic!("expected if expression, got {:?}", other),
+    }
+
+    let program = parse("if (x < y) { x }");
+    match expression_of(&program.statements[0]) {
+      ExpressionNode::IfExpressionNode(if_exp) => assert!(if_exp.alternative.is_none()),
+      other => panic!("expected if expression, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn test_function_literals() {
+    let program = parse("fn(x, y) { x + y; }");
+
+    match expression_of(&program.statements[0]) {
+      ExpressionNode::Function(function) => {
+        let params: Vec<&str> = function.parameters.iter().map(|p| p.value.as_str()).collect();
+        assert_eq!(params, vec!["x", "y"]);
+        assert_eq!(function.body.print_string(), "{ (x + y); }");
+      }
+      other => panic!("expected function literal, got {:?}", other),
+    }
+
+    let tests = vec![
+      ("fn() {};", vec![]),
+      ("fn(x) {};", vec!["x"]),
+      ("fn(x, y, z) {};", vec!["x", "y", "z"]),
+    ];
+
+    for (input, expected) in tests {
+      let program = parse(input);
+      match expression_of(&program.statements[0]) {
+        ExpressionNode::Function(function) => {
+          let params: Vec<&str> = function.parameters.iter().map(|p| p.value.as_str()).collect();
+          assert_eq!(params, expected);
+        }
+        other => panic!("expected function literal, got {:?}", other),
+      }
+    }
+  }
+
+  #[test]
+  fn test_function_literal_with_name() {
+    let program = parse("let myFunction = fn() { };");
+
+    match &program.statements[0] {
+      StatementNode::Let(let_stmt) => match &let_stmt.value {
+        ExpressionNode::Function(function) => {
+          assert_eq!(function.name.as_deref(), Some("myFunction"))
+        }
+        other => panic!("expected function literal, got {:?}", other),
+      },
+      other => panic!("expected let statement, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn test_call_expressions() {
+    let program = parse("add(1, 2 * 3, 4 + 5);");
+
+    match expression_of(&program.statements[0]) {
+      ExpressionNode::Call(call) => {
+        assert_eq!(call.function.print_string(), "add");
+        let args: Vec<String> = call.arguments.iter().map(|a| a.print_string()).collect();
+        assert_eq!(args, vec!["1", "(2 * 3)", "(4 + 5)"]);
+      }
+      other => panic!("expected call expression, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn test_array_and_index_expressions() {
+    let program = parse("[1, 2 * 2, 3 + 3]");
+    match expression_of(&program.statements[0]) {
+      ExpressionNode::Array(array) => {
+        let elements: Vec<String> = array.elements.iter().map(|e| e.print_string()).collect();
+        assert_eq!(elements, vec!["1", "(2 * 2)", "(3 + 3)"]);
+      }
+      other => panic!("expected array literal, got {:?}", other),
+    }
+
+    let program = parse("[]");
+    match expression_of(&program.statements[0]) {
+      ExpressionNode::Array(array) => assert!(array.elements.is_empty()),
+      other => panic!("expected array literal, got {:?}", other),
+    }
+
+    let program = parse("myArray[1 + 1]");
+    match expression_of(&program.statements[0]) {
+      ExpressionNode::Index(index) => {
+        assert_eq!(index.left.print_string(), "myArray");
+        assert_eq!(index.index.print_string(), "(1 + 1)");
+      }
+      other => panic!("expected index expression, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn test_hash_literals() {
+    let tests = vec![
+      (r#"{"one": 1, "two": 2, "three": 3}"#, vec![("\"one\"", "1"), ("\"two\"", "2"), ("\"three\"", "3")]),
+      ("{}", vec![]),
+      ("{1: true, false: 2, a: b + 1,}", vec![("1", "true"), ("false", "2"), ("a", "(b + 1)")]),
+      (r#"{"one": 0 + 1, "two": 10 - 8}"#, vec![("\"one\"", "(0 + 1)"), ("\"two\"", "(10 - 8)")]),
+    ];
+
+    for (input, expected) in tests {
+      let program = parse(input);
+      match expression_of(&program.statements[0]) {
+        ExpressionNode::Hash(hash) => {
+          let pairs: Vec<(String, String)> = hash
+            .pairs
+            .iter()
+            .map(|(key, value)| (key.print_string(), value.print_string()))
+            .collect();
+          let expected: Vec<(String, String)> = expected
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+          assert_eq!(pairs, expected, "input {}", input);
+        }
+        other => panic!("expected hash literal, got {:?}", other),
+      }
+    }
+
+    // A block after `if` is still a block, while a `{` in expression position is a hash.
+    let program = parse("if (x) { y } else { {} }");
+    match expression_of(&program.statements[0]) {
+      ExpressionNode::IfExpressionNode(if_exp) => {
+        assert_eq!(if_exp.consequence.print_string(), "{ y; }");
+        let alternative = if_exp.alternative.as_ref().expect("alternative missing");
+        assert_eq!(alternative.print_string(), "{ {}; }");
+      }
+      other => panic!("expected if expression, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn test_parser_errors() {
+    let tests = vec![
+      ("let = 5;", vec!["expected identifier, found `=`"]),
+      ("let x 10;", vec!["expected `=`, found integer"]),
+      ("let x = ;", vec!["expected an expression, found `;`"]),
+      ("(1 + 2", vec!["unexpected end of input, expected `)`"]),
+      ("()", vec!["expected an expression, found `)`"]),
+      ("fn(x) { x", vec!["unexpected end of input, expected `}`"]),
+      ("let x = 5 let y = 6;", vec!["expected `;` after statement, found `let`"]),
+      ("fn(1) {}", vec!["expected identifier, found integer"]),
+      ("5 @ 5", vec!["unexpected character `@`"]),
+      ("let s = \"abc", vec!["unterminated string literal"]),
+      ("let s = \"a\\qb\";", vec!["invalid escape sequence `\\q`"]),
+      ("99999999999999999999", vec!["integer literal `99999999999999999999` is out of range"]),
+      ("0x8000_0000_0000_0000", vec!["integer literal `0x8000_0000_0000_0000` is out of range"]),
+      ("1e309", vec!["float literal `1e309` is out of range"]),
+      ("0b2", vec!["invalid number literal `0b2`"]),
+      ("-", vec!["unexpected end of input"]),
+      ("[1, 2", vec!["unexpected end of input, expected `]`"]),
+      ("a[1", vec!["unexpected end of input, expected `]`"]),
+      ("{1 2}", vec!["expected `:`, found integer"]),
+      ("{1: 2 3: 4}", vec!["expected `,`, found integer"]),
+      ("a + b += 1", vec!["left side of `+=` must be an identifier"]),
+      ("f() *= 2", vec!["left side of `*=` must be an identifier"]),
+      (
+        "a => b",
+        vec!["expected `;` after statement, found `=>`", "expected an expression, found `=>`"],
+      ),
+    ];
+
+    for (input, expected) in tests {
+      let (_, errors) = Parser::new(Lexer::new(input)).parse_program();
+      let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+      assert_eq!(messages, expected, "input {}", input);
+    }
+  }
+
+  #[test]
+  fn test_error_spans() {
+    let (_, errors) = Parser::new(Lexer::new("let x = 1;\nlet = 2;")).parse_program();
+
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].span(), Span::new(15, 16, 2, 5));
+    assert!(matches!(
+      &errors[0],
+      ParseError::UnexpectedToken { expected: TokenKind::Ident, .. }
+    ));
+
+    let (_, errors) = Parser::new(Lexer::new("if (x) {")).parse_program();
+    assert!(errors[0].is_unexpected_eof());
+  }
+
+  #[test]
+  fn test_doc_comments() {
+    let input = r#"
+    /// Adds two numbers.
+    ///
+    /// Works on integers only.
+    let add = fn(a, b) { a + b };
+    // Not documentation.
+    let x = 1;
+    /// Dropped: only `let` statements keep their docs.
+    add(x, 2);
+    "#;
+    let (program, errors) = Parser::new(Lexer::new(input)).parse_program();
+    assert!(errors.is_empty(), "errors: {:?}", errors);
+
+    let docs: Vec<Option<&str>> = program
+      .statements
+      .iter()
+      .filter_map(|stmt| match stmt {
+        StatementNode::Let(let_stmt) => Some(let_stmt.doc.as_deref()),
+        _ => None,
+      })
+      .collect();
+    assert_eq!(docs, vec![Some("Adds two numbers.\n\nWorks on integers only."), None]);
+    assert_eq!(
+      program.statements[0].print_string(),
+      "/// Adds two numbers.\n///\n/// Works on integers only.\nlet add = fn(a, b) { (a + b); };"
+    );
+  }
+
+  #[test]
+  fn test_error_recovery() {
+    let input = r#"
+    let a = 1;
+    let = 2;
+    let b = ;
+    let c = 3;
+    let f = fn(x) { let = x; x + 1 };
+    f(c);
+    "#;
+    let (program, errors) = Parser::new(Lexer::new(input)).parse_program();
+
+    assert_eq!(errors.len(), 3, "errors: {:?}", errors);
+    let names: Vec<String> = program
+      .statements
+      .iter()
+      .map(|stmt| match stmt {
+        StatementNode::Let(let_stmt) => let_stmt.name.value.clone(),
+        other => other.print_string(),
+      })
+      .collect();
+    assert_eq!(names, vec!["a", "c", "f", "f(c);"]);
+
+    match &program.statements[2] {
+      StatementNode::Let(let_stmt) => {
+        assert_eq!(let_stmt.value.print_string(), "fn(x) { (x + 1); }")
+      }
+      other => panic!("expected let statement, got {:?}", other),
+    }
+  }
+
+  fn parse(input: &str) -> Program {
+    let lexer = Lexer::new(input);
+    let mut parser = Parser::new(lexer);
+    let (program, errors) = parser.parse_program();
+    check_parser_errors(&errors);
+    program
   }
-}
 
-}
\ No newline at end of file
+  fn check_parser_errors(errors: &[ParseError]) {
+    if errors.is_empty() {
+      return;
+    }
+    panic!("parser has {} errors: {:?}", errors.len(), errors);
+  }
+
+  fn expression_of(stmt: &StatementNode) -> &ExpressionNode {
+    match stmt {
+      StatementNode::Expression(exp_stmt) => &exp_stmt.expression,
+      other => panic!("statement is not an expression statement: {:?}", other),
+    }
+  }
+
+  fn test_let_statement(stmt: &StatementNode, expected: &str) {
+    assert_eq!(stmt.token_literal(), "let", "Token literal not let");
+    match stmt {
+      StatementNode::Let(let_stmt) => {
+        assert_eq!(let_stmt.name.value, expected, "Name not {}", expected);
+      }
+      _ => panic!("Statement is not let statement"),
+    }
+  }
+}
@@ -1,26 +1,647 @@
-use std::io::{Stdin, Stdout, Write};
+use std::{
+    fs,
+    io::{self, BufRead, IsTerminal, Write},
+    time::Instant,
+};
 
-use crate::{lexer::Lexer, token::TokenKind};
+use crate::{
+    ast::{Node, Program, StatementNode},
+    builtins::BUILTINS,
+    compiler::Compiler,
+    diagnostics::{Diagnostic, Renderer},
+    lexer::{LexError, Lexer},
+    line_editor::LineEditor,
+    object::Object,
+    parser::{ParseError, Parser},
+    symbol_table::{SymbolScope, SymbolTable},
+    token::{TokenKind, KEYWORDS},
+    vm::VM,
+    Interpreter,
+};
+
+const HELP: &str = "\
+commands:
+  :tokens <code>   print the tokens of <code>
+  :ast <code>      print the parsed statements of <code>
+  :env             list the bindings made in this session
+  :load <file>     evaluate a file into the session
+  :reset           forget every binding
+  :mode eval|vm    switch between the evaluator and the bytecode VM;
+                   each keeps its own bindings
+  :time [<code>]   time <code>, or toggle timing of every input
+  :help            print this message
+";
+
+/// How a session presents itself.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Printed once before the first prompt.
+    pub banner: String,
+    pub prompt: String,
+    /// Shown instead of `prompt` while a statement spans several lines.
+    pub continuation_prompt: String,
+    /// Whether diagnostics are colored with ANSI escapes.
+    pub color: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            banner: String::from("Hello, world!\nPlease type in the code\n"),
+            prompt: String::from(">> "),
+            continuation_prompt: String::from(".. "),
+            color: false,
+        }
+    }
+}
+
+/// Runs a session reading lines from `input` and writing prompts, results
+/// and errors to `output`, until `input` ends. Nothing is echoed, so any
+/// pair of streams works, like both ends of a socket or pipe.
+///
+/// ```
+/// use interpreter::repl::{self, Config};
+///
+/// let config = Config {
+///     banner: String::new(),
+///     ..Config::default()
+/// };
+/// let mut output = Vec::new();
+/// repl::start("let a = 20;\na * 2 + 2\n".as_bytes(), &mut output, &config).unwrap();
+/// assert_eq!(String::from_utf8(output).unwrap(), ">> >> 42\n>> \n");
+/// ```
+pub fn start(input: impl BufRead, mut output: impl Write, config: &Config) -> io::Result<()> {
+    run(Reader::Plain(input), &mut output, config)
+}
+
+/// Runs a session on the process's standard streams, with line editing and
+/// history when both are a terminal that `stty` can put in raw mode.
+pub fn start_terminal(config: &Config) -> io::Result<()> {
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+
+    let editor = if stdin.is_terminal() && stdout.is_terminal() {
+        let mut editor = match LineEditor::default_history_file() {
+            Some(path) => LineEditor::with_history_file(path),
+            None => LineEditor::new(),
+        };
+        editor.check_terminal().ok().map(|()| editor)
+    } else {
+        None
+    };
+    let reader = match editor {
+        Some(editor) => Reader::Terminal(editor),
+        None => Reader::Plain(stdin.lock()),
+    };
+    run(reader, &mut stdout, config)
+}
+
+/// Where lines come from: the line editor when talking to a terminal,
+/// plain reads otherwise.
+enum Reader<R> {
+    Terminal(LineEditor),
+    Plain(R),
+}
+
+impl<R: BufRead> Reader<R> {
+    /// Shows `prompt` and reads a line, without its newline, or `None` at the
+    /// end of input.
+    fn read_line(
+        &mut self,
+        prompt: &str,
+        session: &Session,
+        output: &mut impl Write,
+    ) -> io::Result<Option<String>> {
+        match self {
+            Reader::Terminal(editor) => {
+                editor.read_line(prompt, &|prefix| session.completions(prefix))
+            }
+            Reader::Plain(input) => {
+                write!(output, "{prompt}")?;
+                output.flush()?;
+
+                let mut line = String::new();
+                if input.read_line(&mut line)? == 0 {
+                    return Ok(None);
+                }
+                let len = line.trim_end_matches(['\r', '\n']).len();
+                line.truncate(len);
+                Ok(Some(line))
+            }
+        }
+    }
+}
+
+fn run<R: BufRead>(
+    mut reader: Reader<R>,
+    output: &mut impl Write,
+    config: &Config,
+) -> io::Result<()> {
+    let renderer = if config.color {
+        Renderer::colored()
+    } else {
+        Renderer::plain()
+    };
+    let mut session = Session::new(renderer);
+    let mut input = String::new();
+
+    write!(output, "{}", config.banner)?;
 
-pub fn start(stdin: Stdin, mut stdout: Stdout) {
     loop {
-        write!(stdout, ">> ").expect(">> should have written prompt string >>");
-        stdout.flush().expect("should have flushed");
+        let prompt = if input.is_empty() {
+            &config.prompt
+        } else {
+            &config.continuation_prompt
+        };
+
+        let line = match reader.read_line(prompt, &session, output) {
+            Ok(Some(line)) => line + "\n",
+            // End of input, e.g. Ctrl-D.
+            Ok(None) => return writeln!(output),
+            // Ctrl-C drops the statement being typed.
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                input.clear();
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+
+        if input.is_empty() && line.trim_start().starts_with(':') {
+            session.run_command(line.trim(), output)?;
+            continue;
+        }
+
+        // An empty line ends a continuation, so a statement that can never be
+        // completed still gets its errors reported instead of trapping the user.
+        let force = !input.is_empty() && line.trim().is_empty();
+        input.push_str(&line);
+
+        let (_, errors) = Parser::new(Lexer::new(&input)).parse_program();
+        if !force && is_incomplete(&input, &errors) {
+            continue;
+        }
+
+        session.eval("<repl>", &input, output)?;
+        input.clear();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Eval,
+    Vm,
+}
+
+/// What the bytecode engine carries from one input to the next. Symbols
+/// and constants are only replaced once an input compiles, so a failed
+/// input doesn't leave half-defined globals behind.
+struct VmState {
+    symbol_table: SymbolTable,
+    constants: Vec<Object>,
+    globals: Vec<Object>,
+}
+
+impl VmState {
+    fn new() -> Self {
+        let (symbol_table, constants) = Compiler::new().into_state();
+        Self {
+            symbol_table,
+            constants,
+            globals: vec![],
+        }
+    }
+
+    fn run(&mut self, program: &Program) -> Result<Object, Diagnostic> {
+        let mut compiler =
+            Compiler::new_with_state(self.symbol_table.clone(), self.constants.clone());
+        compiler
+            .compile_program(program)
+            .map_err(|error| Diagnostic::from(&error))?;
+
+        let mut vm = VM::new_with_globals(compiler.bytecode(), std::mem::take(&mut self.globals));
+        (self.symbol_table, self.constants) = compiler.into_state();
+
+        let result = vm.run().map(|()| vm.last_popped_stack_elem());
+        self.globals = vm.into_globals();
+        result.map_err(|error| Diagnostic::from(&error))
+    }
+
+    fn bindings(&self) -> Vec<(String, Object)> {
+        let mut bindings: Vec<(String, Object)> = self
+            .symbol_table
+            .symbols()
+            .filter(|symbol| symbol.scope == SymbolScope::Global)
+            .map(|symbol| {
+                let value = self.globals.get(symbol.index).cloned();
+                (symbol.name.clone(), value.unwrap_or(Object::Null))
+            })
+            .collect();
+        bindings.sort_by(|a, b| a.0.cmp(&b.0));
+        bindings
+    }
+}
+
+/// The state of an interactive session: bindings of both engines and the
+/// settings changed by meta-commands.
+struct Session {
+    renderer: Renderer,
+    mode: Mode,
+    interpreter: Interpreter,
+    vm: VmState,
+    timing: bool,
+}
+
+impl Session {
+    fn new(renderer: Renderer) -> Self {
+        Self {
+            renderer,
+            mode: Mode::Eval,
+            interpreter: Interpreter::new(),
+            vm: VmState::new(),
+            timing: false,
+        }
+    }
+
+    /// Keywords, builtins and bound names starting with `prefix`.
+    fn completions(&self, prefix: &str) -> Vec<String> {
+        let bindings = match self.mode {
+            Mode::Eval => self.interpreter.globals(),
+            Mode::Vm => self.vm.bindings(),
+        };
+
+        let keywords = KEYWORDS.iter().map(|(keyword, _)| keyword.to_string());
+        let builtins = BUILTINS.iter().map(|(name, _)| name.to_string());
+        let bound = bindings.into_iter().map(|(name, _)| name);
+        keywords
+            .chain(builtins)
+            .chain(bound)
+            .filter(|word| word.starts_with(prefix))
+            .collect()
+    }
+
+    /// Evaluates `source` with the current engine and prints its value.
+    /// `name` is the file shown in diagnostics.
+    fn eval(&mut self, name: &str, source: &str, out: &mut impl Write) -> io::Result<()> {
+        let (program, errors) = Parser::new(Lexer::new(source)).parse_program();
+        if !errors.is_empty() {
+            let diagnostics: Vec<Diagnostic> = errors.iter().map(Diagnostic::from).collect();
+            return self.report(name, source, &diagnostics, out);
+        }
+
+        let started = Instant::now();
+        let result = match self.mode {
+            Mode::Eval => self
+                .interpreter
+                .eval_program(&program)
+                .map_err(|error| error.diagnostics()),
+            Mode::Vm => self.vm.run(&program).map_err(|diagnostic| vec![diagnostic]),
+        };
+        let elapsed = started.elapsed();
 
-        let mut input = String::new();
+        match result {
+            Ok(value) if shows_value(&program) => writeln!(out, "{}", value.inspect())?,
+            Ok(_) => {}
+            Err(diagnostics) => self.report(name, source, &diagnostics, out)?,
+        }
+        if self.timing {
+            writeln!(out, "time: {:?}", elapsed)?;
+        }
+        Ok(())
+    }
+
+    /// Runs a line starting with `:`.
+    fn run_command(&mut self, line: &str, out: &mut impl Write) -> io::Result<()> {
+        let (command, argument) = match line.split_once(char::is_whitespace) {
+            Some((command, argument)) => (command, argument.trim()),
+            None => (line, ""),
+        };
+
+        match (command, argument) {
+            (":tokens", code) => self.print_tokens(code, out),
+            (":ast", code) => {
+                let (program, errors) = Parser::new(Lexer::new(code)).parse_program();
+                if !errors.is_empty() {
+                    let diagnostics: Vec<Diagnostic> =
+                        errors.iter().map(Diagnostic::from).collect();
+                    return self.report("<repl>", code, &diagnostics, out);
+                }
+                for statement in &program.statements {
+                    writeln!(out, "{}", statement.print_string())?;
+                }
+                Ok(())
+            }
+            (":env", "") => {
+                let bindings = match self.mode {
+                    Mode::Eval => self.interpreter.globals(),
+                    Mode::Vm => self.vm.bindings(),
+                };
+                for (name, value) in bindings {
+                    writeln!(out, "{} = {}", name, value.inspect())?;
+                }
+                Ok(())
+            }
+            (":load", "") => self.command_error("usage: :load <file>", out),
+            (":load", path) => match fs::read_to_string(path) {
+                Ok(source) => self.eval(path, &source, out),
+                Err(e) => self.command_error(&format!("could not read {path}: {e}"), out),
+            },
+            (":reset", "") => {
+                self.interpreter = Interpreter::new();
+                self.vm = VmState::new();
+                writeln!(out, "session reset")
+            }
+            (":mode", "") => writeln!(out, "mode: {}", mode_name(self.mode)),
+            (":mode", "eval") => self.set_mode(Mode::Eval, out),
+            (":mode", "vm") => self.set_mode(Mode::Vm, out),
+            (":mode", _) => self.command_error("usage: :mode eval|vm", out),
+            (":time", "") => {
+                self.timing = !self.timing;
+                writeln!(out, "timing {}", if self.timing { "on" } else { "off" })
+            }
+            (":time", code) => {
+                let timing = std::mem::replace(&mut self.timing, true);
+                let result = self.eval("<repl>", code, out);
+                self.timing = timing;
+                result
+            }
+            (":help", "") => write!(out, "{HELP}"),
+            (":env" | ":reset" | ":help", _) => {
+                self.command_error(&format!("`{command}` takes no argument"), out)
+            }
+            _ => self.command_error(&format!("unknown command `{command}`, see :help"), out),
+        }
+    }
+
+    fn print_tokens(&self, code: &str, out: &mut impl Write) -> io::Result<()> {
+        let mut lexer = Lexer::new(code);
+        for token in lexer
+            .by_ref()
+            .take_while(|token| token.kind != TokenKind::Eof)
+        {
+            writeln!(
+                out,
+                "{}:{} {:?} {:?}",
+                token.span.line, token.span.column, token.kind, token.literal
+            )?;
+        }
+
+        let diagnostics: Vec<Diagnostic> = lexer
+            .take_errors()
+            .into_iter()
+            .map(|error| Diagnostic::from(&ParseError::Lex(error)))
+            .collect();
+        self.report("<repl>", code, &diagnostics, out)
+    }
+
+    fn set_mode(&mut self, mode: Mode, out: &mut impl Write) -> io::Result<()> {
+        self.mode = mode;
+        writeln!(out, "mode: {}", mode_name(mode))
+    }
+
+    fn command_error(&self, message: &str, out: &mut impl Write) -> io::Result<()> {
+        self.report(
+            "<repl>",
+            "",
+            &[Diagnostic::error(message.to_string(), None)],
+            out,
+        )
+    }
+
+    fn report(
+        &self,
+        name: &str,
+        source: &str,
+        diagnostics: &[Diagnostic],
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        for diagnostic in diagnostics {
+            write!(out, "{}", self.renderer.render(name, source, diagnostic))?;
+        }
+        Ok(())
+    }
+}
+
+fn mode_name(mode: Mode) -> &'static str {
+    match mode {
+        Mode::Eval => "eval",
+        Mode::Vm => "vm",
+    }
+}
+
+/// Input needs more lines when a bracket or block comment is still open,
+/// or when the parser ran out of tokens in the middle of a statement, as
+/// after `let x =`.
+fn is_incomplete(input: &str, errors: &[ParseError]) -> bool {
+    let mut depth: i32 = 0;
 
-        if let Err(e) = stdin.read_line(&mut input) {
-            writeln!(stdout, "Error : {e}").expect("should have written error message");
-            return;
+    let mut lexer = Lexer::new(input);
+    for token in lexer.by_ref() {
+        match token.kind {
+            TokenKind::Lbrace | TokenKind::Lparen | TokenKind::Lbracket => depth += 1,
+            TokenKind::Rbrace | TokenKind::Rparen | TokenKind::Rbracket => depth -= 1,
+            _ => {}
         }
-        let mut lexer = Lexer::new(input.as_str());
+    }
+
+    let open_comment = lexer
+        .take_errors()
+        .iter()
+        .any(|error| matches!(error, LexError::UnterminatedComment { .. }));
+
+    depth > 0 || open_comment || errors.iter().any(ParseError::is_unexpected_eof)
+}
+
+/// Only expressions and `return` produce a value worth echoing; a `let`
+/// evaluates to null.
+fn shows_value(program: &Program) -> bool {
+    !matches!(
+        program.statements.last(),
+        None | Some(StatementNode::Let(_))
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, process};
+
+    use crate::{diagnostics::Renderer, lexer::Lexer, parser::Parser};
 
-        loop {
-            let token = lexer.next_token();
-            if token.kind == TokenKind::Eof {
-                break;
+    use super::{is_incomplete, start, Config, Session, HELP};
+
+    #[test]
+    fn test_is_incomplete() {
+        let tests = vec![
+            ("let add = fn(a, b) {\n", true),
+            ("let add = fn(a, b) {\n  a + b\n", true),
+            ("let add = fn(a, b) {\n  a + b\n};\n", false),
+            ("add(1,\n", true),
+            ("[1, 2,\n", true),
+            ("let x =\n", true),
+            ("let x = 5;\n", false),
+            ("1 + 2\n", false),
+            ("let x = );\n", false),
+            ("}\n", false),
+            ("1 /* a\n", true),
+            ("1 /* a\n*/\n", false),
+        ];
+
+        for (input, expected) in tests {
+            let (_, errors) = Parser::new(Lexer::new(input)).parse_program();
+            assert_eq!(is_incomplete(input, &errors), expected, "{:?}", input);
+        }
+    }
+
+    /// Feeds each line to the session as the REPL would, returning the output.
+    fn run_session(lines: &[&str]) -> String {
+        let mut session = Session::new(Renderer::plain());
+        let mut out = Vec::new();
+        for line in lines {
+            if line.starts_with(':') {
+                session.run_command(line, &mut out).unwrap();
+            } else {
+                session.eval("<repl>", line, &mut out).unwrap();
             }
-            writeln!(stdout, "{token:?}").expect("should have written out tokens");
         }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_meta_commands() {
+        let tests = vec![
+            (
+                vec![":tokens let x = 1;"],
+                "1:1 Let \"let\"\n1:5 Ident \"x\"\n1:7 Assign \"=\"\n1:9 Int \"1\"\n1:10 Semicolon \";\"\n",
+            ),
+            (vec![":ast 1 + 2 * 3"], "(1 + (2 * 3));\n"),
+            (
+                vec!["let b = 2;", "let a = 1;", ":env"],
+                "a = 1\nb = 2\n",
+            ),
+            (vec!["let a = 1;", ":reset", ":env"], "session reset\n"),
+            (
+                vec![":mode vm", "let a = 2;", "a * 21", ":env"],
+                "mode: vm\n42\na = 2\n",
+            ),
+            (
+                vec!["let a = 1;", ":mode vm", "a"],
+                "mode: vm\nerror: identifier not found: a\n --> <repl>:1:1\n  |\n1 | a\n  | ^\n",
+            ),
+            (vec![":mode lua"], "error: usage: :mode eval|vm\n"),
+            (
+                vec![":load {file}", "a"],
+                "error: identifier not found: missing\n --> {file}:2:5\n  |\n\
+                 2 | a + missing\n  |     ^^^^^^^\n40\n",
+            ),
+            (
+                vec![":load {file}.missing"],
+                "error: could not read {file}.missing: No such file or directory (os error 2)\n",
+            ),
+            (vec![":load"], "error: usage: :load <file>\n"),
+            (vec![":time 1 + 2"], "3\ntime: <elapsed>\n"),
+            (
+                vec![":time", "1 + 1", ":time", "2"],
+                "timing on\n2\ntime: <elapsed>\ntiming off\n2\n",
+            ),
+            (vec![":help"], HELP),
+            (vec![":env x"], "error: `:env` takes no argument\n"),
+            (vec![":help me"], "error: `:help` takes no argument\n"),
+            (vec![":what"], "error: unknown command `:what`, see :help\n"),
+        ];
+
+        let path = env::temp_dir().join(format!("monkey_load_test_{}.mk", process::id()));
+        fs::write(&path, "let a = 40;\na + missing").unwrap();
+        let file = path.to_str().unwrap();
+
+        for (lines, expected) in tests {
+            let lines: Vec<String> = lines
+                .iter()
+                .map(|line| line.replace("{file}", file))
+                .collect();
+            let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+            // Durations differ from run to run.
+            let output: String = run_session(&lines)
+                .lines()
+                .map(|line| {
+                    if line.starts_with("time: ") {
+                        "time: <elapsed>\n".to_string()
+                    } else {
+                        format!("{line}\n")
+                    }
+                })
+                .collect();
+            assert_eq!(output, expected.replace("{file}", file), "{:?}", lines);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    /// Runs a whole session on `input` and returns everything it wrote.
+    fn transcript(input: &str, config: &Config) -> String {
+        let mut output = Vec::new();
+        start(input.as_bytes(), &mut output, config).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_sessions() {
+        let tests = vec![
+            (
+                "let x = 5;\nx * 2\n",
+                "Hello, world!\nPlease type in the code\n>> >> 10\n>> \n",
+            ),
+            (
+                "let add = fn(a, b) {\n  a + b\n};\nadd(1,\n2)\n",
+                "Hello, world!\nPlease type in the code\n>> .. .. >> .. 3\n>> \n",
+            ),
+            (
+                "let x = ;\nx\n",
+                "Hello, world!\nPlease type in the code\n\
+                 >> error: expected an expression, found `;`\n \
+                 --> <repl>:1:9\n  |\n1 | let x = ;\n  |         ^ expected an expression\n\
+                 >> error: identifier not found: x\n --> <repl>:1:1\n  |\n1 | x\n  | ^\n\
+                 >> \n",
+            ),
+            (
+                "let f = fn(x) {\n\n:mode vm\nlet a = 1; a + 1\n",
+                "Hello, world!\nPlease type in the code\n>> .. \
+                 error: unexpected end of input, expected `}`\n \
+                 --> <repl>:1:16\n  |\n1 | let f = fn(x) {\n  |                ^ expected `}`\n\
+                 >> mode: vm\n>> 2\n>> \n",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(
+                transcript(input, &Config::default()),
+                expected,
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_custom_prompts() {
+        let config = Config {
+            banner: String::from("monkey 0.1\n"),
+            prompt: String::from("> "),
+            continuation_prompt: String::from("| "),
+            color: false,
+        };
+        assert_eq!(
+            transcript("if (true) {\n1 }\n", &config),
+            "monkey 0.1\n> | 1\n> \n"
+        );
+    }
+
+    #[test]
+    fn test_completions() {
+        let mut session = Session::new(Renderer::plain());
+        session
+            .eval("<repl>", "let lemon = 1;", &mut Vec::new())
+            .unwrap();
+
+        let mut completions = session.completions("le");
+        completions.sort();
+        assert_eq!(completions, vec!["lemon", "len", "let"]);
     }
 }
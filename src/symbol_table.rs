@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolScope {
+    Global,
+    Local,
+    Builtin,
+    /// A local of an enclosing function, captured by the closure.
+    Free,
+    /// The function currently being compiled, referenced by its own name.
+    Function,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub scope: SymbolScope,
+    pub index: usize,
+}
+
+/// Resolves identifiers to storage slots at compile time. Each function
+/// body gets its own table enclosed by the table of the surrounding code.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    pub outer: Option<Box<SymbolTable>>,
+    store: HashMap<String, Symbol>,
+    pub num_definitions: usize,
+    /// Symbols from enclosing functions used here, in capture order.
+    pub free_symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn new_enclosed(outer: SymbolTable) -> Self {
+        Self {
+            outer: Some(Box::new(outer)),
+            ..Default::default()
+        }
+    }
+
+    pub fn define(&mut self, name: &str) -> Symbol {
+        let scope = if self.outer.is_some() {
+            SymbolScope::Local
+        } else {
+            SymbolScope::Global
+        };
+        let symbol = Symbol {
+            name: name.to_string(),
+            scope,
+            index: self.num_definitions,
+        };
+        self.store.insert(name.to_string(), symbol.clone());
+        self.num_definitions += 1;
+        symbol
+    }
+
+    pub fn define_builtin(&mut self, index: usize, name: &str) -> Symbol {
+        let symbol = Symbol {
+            name: name.to_string(),
+            scope: SymbolScope::Builtin,
+            index,
+        };
+        self.store.insert(name.to_string(), symbol.clone());
+        symbol
+    }
+
+    pub fn define_function_name(&mut self, name: &str) -> Symbol {
+        let symbol = Symbol {
+            name: name.to_string(),
+            scope: SymbolScope::Function,
+            index: 0,
+        };
+        self.store.insert(name.to_string(), symbol.clone());
+        symbol
+    }
+
+    fn define_free(&mut self, original: Symbol) -> Symbol {
+        let symbol = Symbol {
+            name: original.name.clone(),
+            scope: SymbolScope::Free,
+            index: self.free_symbols.len(),
+        };
+        self.free_symbols.push(original);
+        self.store.insert(symbol.name.clone(), symbol.clone());
+        symbol
+    }
+
+    /// Symbols defined in this table, not including those of outer tables.
+    pub fn symbols(&self) -> impl Iterator<Item = &Symbol> {
+        self.store.values()
+    }
+
+    /// Looks `name` up here and then in the enclosing tables. Locals found in
+    /// an enclosing function are turned into free symbols of this one.
+    pub fn resolve(&mut self, name: &str) -> Option<Symbol> {
+        if let Some(symbol) = self.store.get(name) {
+            return Some(symbol.clone());
+        }
+
+        let symbol = self.outer.as_mut()?.resolve(name)?;
+        match symbol.scope {
+            SymbolScope::Global | SymbolScope::Builtin => Some(symbol),
+            _ => Some(self.define_free(symbol)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Symbol, SymbolScope, SymbolTable};
+
+    fn symbol(name: &str, scope: SymbolScope, index: usize) -> Symbol {
+        Symbol {
+            name: name.to_string(),
+            scope,
+            index,
+        }
+    }
+
+    #[test]
+    fn test_define_and_resolve() {
+        let mut global = SymbolTable::new();
+        assert_eq!(global.define("a"), symbol("a", SymbolScope::Global, 0));
+        assert_eq!(global.define("b"), symbol("b", SymbolScope::Global, 1));
+
+        let mut local = SymbolTable::new_enclosed(global);
+        assert_eq!(local.define("c"), symbol("c", SymbolScope::Local, 0));
+
+        assert_eq!(
+            local.resolve("a"),
+            Some(symbol("a", SymbolScope::Global, 0))
+        );
+        assert_eq!(local.resolve("c"), Some(symbol("c", SymbolScope::Local, 0)));
+        assert_eq!(local.resolve("d"), None);
+        assert!(local.free_symbols.is_empty());
+    }
+
+    #[test]
+    fn test_resolve_builtins_and_free() {
+        let mut global = SymbolTable::new();
+        global.define_builtin(0, "len");
+        global.define("a");
+
+        let mut first = SymbolTable::new_enclosed(global);
+        first.define("b");
+
+        let mut second = SymbolTable::new_enclosed(first);
+        second.define("c");
+
+        assert_eq!(
+            second.resolve("len"),
+            Some(symbol("len", SymbolScope::Builtin, 0))
+        );
+        assert_eq!(
+            second.resolve("a"),
+            Some(symbol("a", SymbolScope::Global, 0))
+        );
+        assert_eq!(second.resolve("b"), Some(symbol("b", SymbolScope::Free, 0)));
+        assert_eq!(
+            second.resolve("c"),
+            Some(symbol("c", SymbolScope::Local, 0))
+        );
+        assert_eq!(
+            second.free_symbols,
+            vec![symbol("b", SymbolScope::Local, 0)]
+        );
+    }
+
+    #[test]
+    fn test_shadowing_function_name() {
+        let mut global = SymbolTable::new();
+        global.define_function_name("a");
+        assert_eq!(
+            global.resolve("a"),
+            Some(symbol("a", SymbolScope::Function, 0))
+        );
+
+        global.define("a");
+        assert_eq!(
+            global.resolve("a"),
+            Some(symbol("a", SymbolScope::Global, 0))
+        );
+    }
+}
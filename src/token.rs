@@ -1,12 +1,57 @@
-use std::{fmt::write, str::FromStr};
+use std::str::FromStr;
 
 #[derive(PartialEq, Debug, Default, Clone)]
 pub struct Token {
     pub kind: TokenKind,
     pub literal: String,
+    pub span: Span,
 }
 
-#[derive(PartialEq, Debug, Default, Clone)]
+/// Location of a piece of source text. `start` and `end` are byte offsets
+/// (end exclusive), `line` and `column` are 1-based and point at `start`.
+#[derive(PartialEq, Eq, Debug, Default, Clone, Copy)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, line: usize, column: usize) -> Self {
+        Self {
+            start,
+            end,
+            line,
+            column,
+        }
+    }
+
+    /// Smallest span covering both `self` and `other`.
+    pub fn merge(&self, other: Span) -> Span {
+        let first = if other.start < self.start {
+            other
+        } else {
+            *self
+        };
+        Span {
+            start: first.start,
+            end: self.end.max(other.end),
+            line: first.line,
+            column: first.column,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Default, Clone)]
 pub enum TokenKind {
     #[default]
     Illegal,
@@ -14,6 +59,8 @@ pub enum TokenKind {
 
     Ident,
     Int,
+    Float,
+    String,
 
     Assign,
     Plus,
@@ -21,20 +68,38 @@ pub enum TokenKind {
     Bang,
     Asterisk,
     Slash,
+    Percent,
+    Power,
 
     Lt,
     Gt,
+    LtEq,
+    GtEq,
 
     Eq,
     NotEq,
 
+    And,
+    Or,
+
+    PlusAssign,
+    MinusAssign,
+    AsteriskAssign,
+    SlashAssign,
+
+    Arrow,
+    FatArrow,
+
     Comma,
     Semicolon,
+    Colon,
 
     Lparen,
     Rparen,
     Lbrace,
     Rbrace,
+    Lbracket,
+    Rbracket,
 
     Function,
     Let,
@@ -45,69 +110,138 @@ pub enum TokenKind {
     Return,
 }
 
-impl TokenKind {
-    pub fn lookup_ident(str: &str) -> TokenKind {
-        match str {
-            "fn" => TokenKind::Function,
-            "let" => TokenKind::Let,
-            "if" => TokenKind::If,
-            "else" => TokenKind::Else,
-            "return" => TokenKind::Return,
-            "true" => TokenKind::True,
-            "false" => TokenKind::False,
-            _ => TokenKind::Ident,
-        }
-    }
+/// Reserved words and the tokens they lex to.
+pub const KEYWORDS: &[(&str, TokenKind)] = &[
+    ("fn", TokenKind::Function),
+    ("let", TokenKind::Let),
+    ("if", TokenKind::If),
+    ("else", TokenKind::Else),
+    ("return", TokenKind::Return),
+    ("true", TokenKind::True),
+    ("false", TokenKind::False),
+];
 
-    pub fn to_literal(&self, literal: char) -> String {
-        if &Self::Eq == self {
-            return "==".to_string();
-        } else if &Self::NotEq == self {
-            return "!=".to_string();
-        }
-        literal.to_string()
+/// Operators and punctuation. The lexer takes the longest entry the input
+/// starts with, so `<=` is never lexed as `<` followed by `=`.
+pub const OPERATORS: &[(&str, TokenKind)] = &[
+    ("=", TokenKind::Assign),
+    ("+", TokenKind::Plus),
+    ("-", TokenKind::Minus),
+    ("!", TokenKind::Bang),
+    ("*", TokenKind::Asterisk),
+    ("/", TokenKind::Slash),
+    ("%", TokenKind::Percent),
+    ("**", TokenKind::Power),
+    ("<", TokenKind::Lt),
+    (">", TokenKind::Gt),
+    ("<=", TokenKind::LtEq),
+    (">=", TokenKind::GtEq),
+    ("==", TokenKind::Eq),
+    ("!=", TokenKind::NotEq),
+    ("&&", TokenKind::And),
+    ("||", TokenKind::Or),
+    ("+=", TokenKind::PlusAssign),
+    ("-=", TokenKind::MinusAssign),
+    ("*=", TokenKind::AsteriskAssign),
+    ("/=", TokenKind::SlashAssign),
+    ("->", TokenKind::Arrow),
+    ("=>", TokenKind::FatArrow),
+    (",", TokenKind::Comma),
+    (";", TokenKind::Semicolon),
+    (":", TokenKind::Colon),
+    ("(", TokenKind::Lparen),
+    (")", TokenKind::Rparen),
+    ("{", TokenKind::Lbrace),
+    ("}", TokenKind::Rbrace),
+    ("[", TokenKind::Lbracket),
+    ("]", TokenKind::Rbracket),
+];
+
+/// Bit `i` of the entry for a byte is set when `OPERATORS[i]` starts with
+/// that byte, so a lookup only compares the operators that could match.
+const OPERATOR_CANDIDATES: [u64; 256] = {
+    assert!(OPERATORS.len() <= 64);
+    let mut candidates = [0; 256];
+    let mut i = 0;
+    while i < OPERATORS.len() {
+        candidates[OPERATORS[i].0.as_bytes()[0] as usize] |= 1 << i;
+        i += 1;
     }
+    candidates
+};
 
-    pub fn to_tok(s: &str, next_char: &str) -> Self {
-        match s {
-            "=" => {
-                if next_char == "=" {
-                    return self::TokenKind::Eq;
-                }
-                self::TokenKind::Assign
-            }
-            "+" => self::TokenKind::Plus,
-            "(" => self::TokenKind::Lparen,
-            ")" => self::TokenKind::Rparen,
-            "{" => self::TokenKind::Lbrace,
-            "}" => self::TokenKind::Rbrace,
-            "fn" => self::TokenKind::Function,
-            "let" => self::TokenKind::Let,
-            "if" => self::TokenKind::If,
-            "else" => self::TokenKind::Else,
-            "return" => self::TokenKind::Return,
-            "," => self::TokenKind::Comma,
-            ";" => self::TokenKind::Semicolon,
-            "\0" => self::TokenKind::Eof,
-            "/" => self::TokenKind::Slash,
-            "-" => self::TokenKind::Minus,
-            "!" => {
-                if next_char == "=" {
-                    return self::TokenKind::NotEq;
-                }
-                self::TokenKind::Bang
+impl TokenKind {
+    /// The longest operator at the start of `input` and its length in bytes.
+    pub fn lookup_operator(input: &str) -> Option<(TokenKind, usize)> {
+        let mut candidates = OPERATOR_CANDIDATES[*input.as_bytes().first()? as usize];
+        let mut longest: Option<&(&str, TokenKind)> = None;
+        while candidates != 0 {
+            let entry = &OPERATORS[candidates.trailing_zeros() as usize];
+            candidates &= candidates - 1;
+            if input.starts_with(entry.0) && entry.0.len() > longest.map_or(0, |l| l.0.len()) {
+                longest = Some(entry);
             }
-            "*" => self::TokenKind::Asterisk,
-            "<" => self::TokenKind::Lt,
-            ">" => self::TokenKind::Gt,
-            _ => self::TokenKind::Illegal,
         }
+        longest.map(|(symbol, kind)| (kind.clone(), symbol.len()))
+    }
+
+    pub fn lookup_ident(str: &str) -> TokenKind {
+        KEYWORDS
+            .iter()
+            .find(|(keyword, _)| *keyword == str)
+            .map_or(TokenKind::Ident, |(_, kind)| kind.clone())
     }
 }
 
 impl std::fmt::Display for TokenKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.to_string())
+        let symbol = match self {
+            TokenKind::Illegal => return write!(f, "illegal token"),
+            TokenKind::Eof => return write!(f, "end of input"),
+            TokenKind::Ident => return write!(f, "identifier"),
+            TokenKind::Int => return write!(f, "integer"),
+            TokenKind::Float => return write!(f, "float"),
+            TokenKind::String => return write!(f, "string"),
+            TokenKind::Assign => "=",
+            TokenKind::Plus => "+",
+            TokenKind::Minus => "-",
+            TokenKind::Bang => "!",
+            TokenKind::Asterisk => "*",
+            TokenKind::Slash => "/",
+            TokenKind::Percent => "%",
+            TokenKind::Power => "**",
+            TokenKind::Lt => "<",
+            TokenKind::Gt => ">",
+            TokenKind::LtEq => "<=",
+            TokenKind::GtEq => ">=",
+            TokenKind::Eq => "==",
+            TokenKind::NotEq => "!=",
+            TokenKind::And => "&&",
+            TokenKind::Or => "||",
+            TokenKind::PlusAssign => "+=",
+            TokenKind::MinusAssign => "-=",
+            TokenKind::AsteriskAssign => "*=",
+            TokenKind::SlashAssign => "/=",
+            TokenKind::Arrow => "->",
+            TokenKind::FatArrow => "=>",
+            TokenKind::Comma => ",",
+            TokenKind::Semicolon => ";",
+            TokenKind::Colon => ":",
+            TokenKind::Lparen => "(",
+            TokenKind::Rparen => ")",
+            TokenKind::Lbrace => "{",
+            TokenKind::Rbrace => "}",
+            TokenKind::Lbracket => "[",
+            TokenKind::Rbracket => "]",
+            TokenKind::Function => "fn",
+            TokenKind::Let => "let",
+            TokenKind::True => "true",
+            TokenKind::False => "false",
+            TokenKind::If => "if",
+            TokenKind::Else => "else",
+            TokenKind::Return => "return",
+        };
+        write!(f, "`{}`", symbol)
     }
 }
 
@@ -115,30 +249,15 @@ impl FromStr for TokenKind {
     type Err = ();
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "=" => Ok(self::TokenKind::Assign),
-            "==" => Ok(self::TokenKind::Eq),
-            "!=" => Ok(self::TokenKind::NotEq),
-            "+" => Ok(self::TokenKind::Plus),
-            "(" => Ok(self::TokenKind::Lparen),
-            ")" => Ok(self::TokenKind::Rparen),
-            "{" => Ok(self::TokenKind::Lbrace),
-            "}" => Ok(self::TokenKind::Rbrace),
-            "fn" => Ok(self::TokenKind::Function),
-            "let" => Ok(self::TokenKind::Let),
-            "if" => Ok(self::TokenKind::If),
-            "else" => Ok(self::TokenKind::Else),
-            "return" => Ok(self::TokenKind::Return),
-            "," => Ok(self::TokenKind::Comma),
-            ";" => Ok(self::TokenKind::Semicolon),
-            "\0" => Ok(self::TokenKind::Eof),
-            "/" => Ok(self::TokenKind::Slash),
-            "-" => Ok(self::TokenKind::Minus),
-            "!" => Ok(self::TokenKind::Bang),
-            "*" => Ok(self::TokenKind::Asterisk),
-            "<" => Ok(self::TokenKind::Lt),
-            ">" => Ok(self::TokenKind::Gt),
-            _ => Ok(self::TokenKind::Illegal),
-        }
+        let kind = OPERATORS
+            .iter()
+            .chain(KEYWORDS)
+            .find(|(symbol, _)| *symbol == s)
+            .map(|(_, kind)| kind.clone());
+        Ok(match kind {
+            Some(kind) => kind,
+            None if s == "\0" => TokenKind::Eof,
+            None => TokenKind::Illegal,
+        })
     }
 }
@@ -0,0 +1,904 @@
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    io::{self, Write},
+    rc::Rc,
+};
+
+use crate::{
+    builtins::BUILTINS,
+    code::{read_u16, read_u8, span_at, Opcode},
+    compiler::Bytecode,
+    disassembler::format_instruction,
+    object::{Builtin, Closure, CompiledFunction, Object, RuntimeError},
+};
+
+pub const STACK_SIZE: usize = 2048;
+pub const MAX_FRAMES: usize = 1024;
+
+/// A function call in progress. `base_pointer` is the stack slot of the
+/// first local, with the callee itself just below it.
+struct Frame {
+    closure: Rc<Closure>,
+    ip: usize,
+    base_pointer: usize,
+}
+
+impl Frame {
+    fn new(closure: Rc<Closure>, base_pointer: usize) -> Self {
+        Self {
+            closure,
+            ip: 0,
+            base_pointer,
+        }
+    }
+}
+
+pub struct VM {
+    constants: Vec<Object>,
+    stack: Vec<Object>,
+    /// Next free slot; the top of the stack is `stack[sp - 1]`.
+    sp: usize,
+    globals: Vec<Object>,
+    frames: Vec<Frame>,
+    /// `builtins::BUILTINS` as objects, indexed by `OpGetBuiltin`.
+    builtins: Vec<Object>,
+    /// Receives a line per executed instruction when tracing is on.
+    tracer: Option<Box<dyn io::Write>>,
+}
+
+impl VM {
+    pub fn new(bytecode: Bytecode) -> Self {
+        let main = CompiledFunction {
+            instructions: bytecode.instructions,
+            spans: bytecode.spans,
+            ..Default::default()
+        };
+        let main = Closure {
+            func: Rc::new(main),
+            free: vec![],
+        };
+
+        Self {
+            constants: bytecode.constants,
+            stack: vec![Object::Null; STACK_SIZE],
+            sp: 0,
+            globals: vec![],
+            frames: vec![Frame::new(Rc::new(main), 0)],
+            builtins: BUILTINS
+                .iter()
+                .map(|(name, func)| Object::Builtin(Builtin::new(name, *func)))
+                .collect(),
+            tracer: None,
+        }
+    }
+
+    /// Runs `bytecode` against the globals left by an earlier VM, as
+    /// returned by `into_globals`.
+    pub fn new_with_globals(bytecode: Bytecode, globals: Vec<Object>) -> Self {
+        Self {
+            globals,
+            ..Self::new(bytecode)
+        }
+    }
+
+    pub fn into_globals(self) -> Vec<Object> {
+        self.globals
+    }
+
+    /// Logs every instruction to `tracer` before it runs, indented by call
+    /// depth and followed by the contents of the stack.
+    pub fn set_tracer(&mut self, tracer: Box<dyn io::Write>) {
+        self.tracer = Some(tracer);
+    }
+
+    /// The value of the last expression statement, which has just been
+    /// popped but is still in its slot.
+    pub fn last_popped_stack_elem(&self) -> Object {
+        self.stack.get(self.sp).cloned().unwrap_or(Object::Null)
+    }
+
+    /// Runs the program to completion. Errors point at the source of the
+    /// failing instruction when the bytecode carries debug info.
+    pub fn run(&mut self) -> Result<(), RuntimeError> {
+        self.execute().map_err(|mut error| {
+            if error.span.is_none() {
+                let frame = self.current_frame();
+                // The instruction pointer has moved past the failing instruction's opcode.
+                error.span = span_at(&frame.closure.func.spans, frame.ip.saturating_sub(1));
+            }
+            error
+        })
+    }
+
+    fn execute(&mut self) -> Result<(), RuntimeError> {
+        while let Some(op) = self.fetch()? {
+            match op {
+                Opcode::Constant => {
+                    let idx = self.read_u16();
+                    self.push(self.constants[idx].clone())?;
+                }
+                Opcode::Pop => {
+                    self.pop();
+                }
+                Opcode::Add
+                | Opcode::Sub
+                | Opcode::Mul
+                | Opcode::Div
+                | Opcode::Mod
+                | Opcode::Pow
+                | Opcode::Equal
+                | Opcode::NotEqual
+                | Opcode::GreaterThan
+                | Opcode::LessThan
+                | Opcode::GreaterEqual
+                | Opcode::LessEqual => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.push(execute_binary_operation(op, left, right)?)?;
+                }
+                Opcode::True => self.push(Object::Boolean(true))?,
+                Opcode::False => self.push(Object::Boolean(false))?,
+                Opcode::Null => self.push(Object::Null)?,
+                Opcode::Minus => {
+                    let operand = self.pop();
+                    let result = match operand {
+                        Object::Integer(value) => match value.checked_neg() {
+                            Some(negated) => Object::Integer(negated),
+                            None => return Err(error(format!("integer overflow: -{}", value))),
+                        },
+                        Object::Float(value) => Object::Float(-value),
+                        other => {
+                            return Err(error(format!("unknown operator: -{}", other.type_name())))
+                        }
+                    };
+                    self.push(result)?;
+                }
+                Opcode::Bang => {
+                    let operand = self.pop();
+                    self.push(Object::Boolean(!operand.is_truthy()))?;
+                }
+                Opcode::Jump => {
+                    let target = self.read_u16();
+                    self.current_frame_mut().ip = target;
+                }
+                Opcode::JumpNotTruthy => {
+                    let target = self.read_u16();
+                    if !self.pop().is_truthy() {
+                        self.current_frame_mut().ip = target;
+                    }
+                }
+                Opcode::SetGlobal => {
+                    let idx = self.read_u16();
+                    let value = self.pop();
+                    if idx >= self.globals.len() {
+                        self.globals.resize(idx + 1, Object::Null);
+                    }
+                    self.globals[idx] = value;
+                }
+                Opcode::GetGlobal => {
+                    let idx = self.read_u16();
+                    let value = self.globals.get(idx).cloned().unwrap_or(Object::Null);
+                    self.push(value)?;
+                }
+                Opcode::SetLocal => {
+                    let idx = self.read_u8();
+                    let base_pointer = self.current_frame().base_pointer;
+                    let value = self.pop();
+                    match &self.stack[base_pointer + idx] {
+                        Object::Cell(cell) => *cell.borrow_mut() = value,
+                        _ => self.stack[base_pointer + idx] = value,
+                    }
+                }
+                Opcode::GetLocal => {
+                    let idx = self.read_u8();
+                    let base_pointer = self.current_frame().base_pointer;
+                    self.push(cell_value(&self.stack[base_pointer + idx]))?;
+                }
+                Opcode::GetBuiltin => {
+                    let idx = self.read_u8();
+                    self.push(self.builtins[idx].clone())?;
+                }
+                Opcode::GetFree => {
+                    let idx = self.read_u8();
+                    let value = cell_value(&self.current_frame().closure.free[idx]);
+                    self.push(value)?;
+                }
+                Opcode::SetFree => {
+                    let idx = self.read_u8();
+                    let value = self.pop();
+                    match &self.current_frame().closure.free[idx] {
+                        Object::Cell(cell) => *cell.borrow_mut() = value,
+                        // Only a function's own name is captured without a
+                        // cell, and assigning to it always fails first.
+                        _ => return Err(error(format!("free variable {} is not assignable", idx))),
+                    }
+                }
+                Opcode::CurrentClosure => {
+                    let closure = Rc::clone(&self.current_frame().closure);
+                    self.push(Object::Closure(closure))?;
+                }
+                Opcode::CaptureLocal => {
+                    let idx = self.read_u8();
+                    let slot = self.current_frame().base_pointer + idx;
+                    if !matches!(self.stack[slot], Object::Cell(_)) {
+                        let value = std::mem::replace(&mut self.stack[slot], Object::Null);
+                        self.stack[slot] = Object::Cell(Rc::new(RefCell::new(value)));
+                    }
+                    self.push(self.stack[slot].clone())?;
+                }
+                Opcode::CaptureFree => {
+                    let idx = self.read_u8();
+                    let value = self.current_frame().closure.free[idx].clone();
+                    self.push(value)?;
+                }
+                Opcode::Array => {
+                    let len = self.read_u16();
+                    let elements = self.stack[self.sp - len..self.sp].to_vec();
+                    self.sp -= len;
+                    self.push(Object::Array(Rc::new(elements)))?;
+                }
+                Opcode::Hash => {
+                    let len = self.read_u16();
+                    let hash = self.build_hash(self.sp - len, self.sp)?;
+                    self.sp -= len;
+                    self.push(hash)?;
+                }
+                Opcode::Index => {
+                    let index = self.pop();
+                    let left = self.pop();
+                    self.push(execute_index_expression(left, index)?)?;
+                }
+                Opcode::Call => {
+                    let num_args = self.read_u8();
+                    self.call(num_args)?;
+                }
+                Opcode::ReturnValue => {
+                    let value = self.pop();
+                    if !self.return_from_frame(value)? {
+                        return Ok(());
+                    }
+                }
+                Opcode::Return => {
+                    if !self.return_from_frame(Object::Null)? {
+                        return Ok(());
+                    }
+                }
+                Opcode::Closure => {
+                    let idx = self.read_u16();
+                    let num_free = self.read_u8();
+                    self.push_closure(idx, num_free)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the next opcode of the current frame, or `None` once the
+    /// top-level instructions run out.
+    fn fetch(&mut self) -> Result<Option<Opcode>, RuntimeError> {
+        let frame = self.current_frame();
+        let byte = match frame.closure.func.instructions.get(frame.ip) {
+            Some(byte) => *byte,
+            None => return Ok(None),
+        };
+
+        if self.tracer.is_some() {
+            self.trace();
+        }
+
+        let frame = self.current_frame_mut();
+        frame.ip += 1;
+
+        match Opcode::from_byte(byte) {
+            Some(op) => Ok(Some(op)),
+            None => Err(error(format!("unknown opcode {:#04x}", byte))),
+        }
+    }
+
+    fn trace(&mut self) {
+        let frame = self.current_frame();
+        let (instruction, _) =
+            format_instruction(&frame.closure.func.instructions, frame.ip, &self.constants);
+        let stack: Vec<String> = self.stack[..self.sp]
+            .iter()
+            .map(|obj| obj.inspect())
+            .collect();
+        let line = format!(
+            "{}{:04} {:<32} [{}]",
+            "  ".repeat(self.frames.len() - 1),
+            frame.ip,
+            instruction,
+            stack.join(", ")
+        );
+
+        if let Some(tracer) = self.tracer.as_mut() {
+            // Tracing is a debugging aid, so a failed write doesn't stop the program.
+            let _ = writeln!(tracer, "{}", line);
+        }
+    }
+
+    fn read_u16(&mut self) -> usize {
+        let frame = self.current_frame_mut();
+        let operand = read_u16(&frame.closure.func.instructions, frame.ip);
+        frame.ip += 2;
+        operand as usize
+    }
+
+    fn read_u8(&mut self) -> usize {
+        let frame = self.current_frame_mut();
+        let operand = read_u8(&frame.closure.func.instructions, frame.ip);
+        frame.ip += 1;
+        operand as usize
+    }
+
+    fn call(&mut self, num_args: usize) -> Result<(), RuntimeError> {
+        let callee = self.stack[self.sp - 1 - num_args].clone();
+
+        match callee {
+            Object::Closure(closure) => {
+                if closure.func.num_parameters != num_args {
+                    return Err(error(format!(
+                        "wrong number of arguments: want={}, got={}",
+                        closure.func.num_parameters, num_args
+                    )));
+                }
+                if self.frames.len() >= MAX_FRAMES {
+                    return Err(error(String::from("stack overflow")));
+                }
+
+                let base_pointer = self.sp - num_args;
+                let sp = base_pointer + closure.func.num_locals;
+                if sp >= STACK_SIZE {
+                    return Err(error(String::from("stack overflow")));
+                }
+                // Slots past the arguments may hold values from an earlier call.
+                self.stack[self.sp..sp].fill(Object::Null);
+
+                self.frames.push(Frame::new(closure, base_pointer));
+                self.sp = sp;
+                Ok(())
+            }
+            Object::Builtin(builtin) => {
+                let args = self.stack[self.sp - num_args..self.sp].to_vec();
+                let result = (builtin.func)(args);
+                self.sp -= num_args + 1;
+
+                match result {
+                    Object::Error(error) => Err(error),
+                    value => self.push(value),
+                }
+            }
+            other => Err(error(format!("not a function: {}", other.type_name()))),
+        }
+    }
+
+    /// Pops the current frame and its callee off the stack and pushes the
+    /// returned value. A return at the top level ends the program instead,
+    /// leaving the value where `last_popped_stack_elem` finds it.
+    fn return_from_frame(&mut self, value: Object) -> Result<bool, RuntimeError> {
+        if self.frames.len() == 1 {
+            self.push(value)?;
+            self.sp -= 1;
+            return Ok(false);
+        }
+
+        let frame = self.frames.pop().expect("returning from a called frame");
+        self.sp = frame.base_pointer - 1;
+        self.push(value)?;
+        Ok(true)
+    }
+
+    fn push_closure(&mut self, idx: usize, num_free: usize) -> Result<(), RuntimeError> {
+        let func = match &self.constants[idx] {
+            Object::CompiledFunction(func) => Rc::clone(func),
+            other => return Err(error(format!("not a function: {}", other.type_name()))),
+        };
+
+        let free = self.stack[self.sp - num_free..self.sp].to_vec();
+        self.sp -= num_free;
+        self.push(Object::Closure(Rc::new(Closure { func, free })))
+    }
+
+    fn build_hash(&self, start: usize, end: usize) -> Result<Object, RuntimeError> {
+        let mut pairs = BTreeMap::new();
+
+        for pair in self.stack[start..end].chunks(2) {
+            let (key, value) = (&pair[0], &pair[1]);
+            let hash_key = key.hash_key().ok_or_else(|| unusable_hash_key(key))?;
+            pairs.insert(hash_key, value.clone());
+        }
+
+        Ok(Object::Hash(Rc::new(pairs)))
+    }
+
+    fn current_frame(&self) -> &Frame {
+        self.frames.last().expect("the main frame is never popped")
+    }
+
+    fn current_frame_mut(&mut self) -> &mut Frame {
+        self.frames
+            .last_mut()
+            .expect("the main frame is never popped")
+    }
+
+    fn push(&mut self, obj: Object) -> Result<(), RuntimeError> {
+        if self.sp >= STACK_SIZE {
+            return Err(error(String::from("stack overflow")));
+        }
+        self.stack[self.sp] = obj;
+        self.sp += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Object {
+        self.sp -= 1;
+        self.stack[self.sp].clone()
+    }
+}
+
+fn execute_binary_operation(
+    op: Opcode,
+    left: Object,
+    right: Object,
+) -> Result<Object, RuntimeError> {
+    let operator = match op {
+        Opcode::Add => "+",
+        Opcode::Sub => "-",
+        Opcode::Mul => "*",
+        Opcode::Div => "/",
+        Opcode::Mod => "%",
+        Opcode::Pow => "**",
+        Opcode::Equal => "==",
+        Opcode::NotEqual => "!=",
+        Opcode::GreaterThan => ">",
+        Opcode::LessThan => "<",
+        Opcode::GreaterEqual => ">=",
+        Opcode::LessEqual => "<=",
+        _ => unreachable!("{:?} is not a binary operator", op),
+    };
+
+    match (&left, &right) {
+        (Object::Integer(l), Object::Integer(r)) => execute_integer_operation(operator, *l, *r),
+        (Object::Float(l), Object::Float(r)) => execute_float_operation(operator, *l, *r),
+        (Object::Integer(l), Object::Float(r)) => execute_float_operation(operator, *l as f64, *r),
+        (Object::Float(l), Object::Integer(r)) => execute_float_operation(operator, *l, *r as f64),
+        (Object::String(l), Object::String(r)) => match operator {
+            "+" => Ok(Object::String(format!("{}{}", l, r))),
+            "==" => Ok(Object::Boolean(l == r)),
+            "!=" => Ok(Object::Boolean(l != r)),
+            _ => Err(error(format!(
+                "unknown operator: STRING {} STRING",
+                operator
+            ))),
+        },
+        (Object::Boolean(l), Object::Boolean(r)) if matches!(operator, "==" | "!=") => {
+            Ok(Object::Boolean((l == r) == (operator == "==")))
+        }
+        _ if left.type_name() != right.type_name() => Err(error(format!(
+            "type mismatch: {} {} {}",
+            left.type_name(),
+            operator,
+            right.type_name()
+        ))),
+        _ => Err(error(format!(
+            "unknown operator: {} {} {}",
+            left.type_name(),
+            operator,
+            right.type_name()
+        ))),
+    }
+}
+
+fn execute_integer_operation(
+    operator: &str,
+    left: i64,
+    right: i64,
+) -> Result<Object, RuntimeError> {
+    let result = match operator {
+        "+" => left.checked_add(right),
+        "-" => left.checked_sub(right),
+        "*" => left.checked_mul(right),
+        "/" => {
+            if right == 0 {
+                return Err(error(String::from("division by zero")));
+            }
+            left.checked_div(right)
+        }
+        "%" => {
+            if right == 0 {
+                return Err(error(String::from("division by zero")));
+            }
+            left.checked_rem(right)
+        }
+        "**" => {
+            if right < 0 {
+                return Err(error(format!("negative exponent: {} ** {}", left, right)));
+            }
+            u32::try_from(right)
+                .ok()
+                .and_then(|exponent| left.checked_pow(exponent))
+        }
+        "<" => return Ok(Object::Boolean(left < right)),
+        ">" => return Ok(Object::Boolean(left > right)),
+        "<=" => return Ok(Object::Boolean(left <= right)),
+        ">=" => return Ok(Object::Boolean(left >= right)),
+        "==" => return Ok(Object::Boolean(left == right)),
+        "!=" => return Ok(Object::Boolean(left != right)),
+        _ => unreachable!("{} is not an integer operator", operator),
+    };
+
+    match result {
+        Some(value) => Ok(Object::Integer(value)),
+        None => Err(error(format!(
+            "integer overflow: {} {} {}",
+            left, operator, right
+        ))),
+    }
+}
+
+fn execute_float_operation(operator: &str, left: f64, right: f64) -> Result<Object, RuntimeError> {
+    let result = match operator {
+        "+" => left + right,
+        "-" => left - right,
+        "*" => left * right,
+        "/" | "%" if right == 0.0 => return Err(error(String::from("division by zero"))),
+        "/" => left / right,
+        "%" => left % right,
+        "**" => left.powf(right),
+        "<" => return Ok(Object::Boolean(left < right)),
+        ">" => return Ok(Object::Boolean(left > right)),
+        "<=" => return Ok(Object::Boolean(left <= right)),
+        ">=" => return Ok(Object::Boolean(left >= right)),
+        "==" => return Ok(Object::Boolean(left == right)),
+        "!=" => return Ok(Object::Boolean(left != right)),
+        _ => unreachable!("{} is not a float operator", operator),
+    };
+    Ok(Object::Float(result))
+}
+
+/// Same rules as the evaluator: out of range indexes and missing keys give null.
+fn execute_index_expression(left: Object, index: Object) -> Result<Object, RuntimeError> {
+    match (&left, &index) {
+        (Object::Array(elements), Object::Integer(idx)) => Ok(usize::try_from(*idx)
+            .ok()
+            .and_then(|idx| elements.get(idx))
+            .cloned()
+            .unwrap_or(Object::Null)),
+        (Object::Hash(pairs), _) => match index.hash_key() {
+            Some(key) => Ok(pairs.get(&key).cloned().unwrap_or(Object::Null)),
+            None => Err(unusable_hash_key(&index)),
+        },
+        _ => Err(error(format!(
+            "index operator not supported: {}[{}]",
+            left.type_name(),
+            index.type_name()
+        ))),
+    }
+}
+
+/// The value of a variable, looking through the cell of a captured one.
+fn cell_value(slot: &Object) -> Object {
+    match slot {
+        Object::Cell(cell) => cell.borrow().clone(),
+        other => other.clone(),
+    }
+}
+
+fn unusable_hash_key(key: &Object) -> RuntimeError {
+    error(format!("unusable as hash key: {}", key.type_name()))
+}
+
+fn error(message: String) -> RuntimeError {
+    RuntimeError::new(message)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        cell::RefCell,
+        io::{self, Write},
+        rc::Rc,
+        thread,
+    };
+
+    use crate::{
+        compiler::Compiler, environment::Environment, evaluator::eval_program, lexer::Lexer,
+        object::Object, parser::Parser,
+    };
+
+    use super::VM;
+
+    /// Compiles and runs `input`, returning the final value or the message of
+    /// the compile or runtime error.
+    fn run(input: &str) -> Result<Object, String> {
+        let (program, errors) = Parser::new(Lexer::new(input)).parse_program();
+        assert!(errors.is_empty(), "parser errors: {:?}", errors);
+
+        let mut compiler = Compiler::new();
+        compiler.compile_program(&program).map_err(|e| e.message)?;
+
+        let mut vm = VM::new(compiler.bytecode());
+        vm.run().map_err(|e| e.message)?;
+        Ok(vm.last_popped_stack_elem())
+    }
+
+    fn eval(input: &str) -> Result<Object, String> {
+        let (program, _) = Parser::new(Lexer::new(input)).parse_program();
+        let env = Rc::new(RefCell::new(Environment::new()));
+        match eval_program(&program, &env) {
+            Object::Error(error) => Err(error.message),
+            value => Ok(value),
+        }
+    }
+
+    fn assert_runs(tests: &[(&str, &str)]) {
+        for (input, expected) in tests {
+            match run(input) {
+                Ok(value) => assert_eq!(value.inspect(), *expected, "input {}", input),
+                Err(message) => panic!("unexpected error for {}: {}", input, message),
+            }
+        }
+    }
+
+    #[test]
+    fn test_integer_arithmetic() {
+        assert_runs(&[
+            ("1", "1"),
+            ("1 + 2", "3"),
+            ("50 / 2 * 2 + 10 - 5", "55"),
+            ("5 * (2 + 10)", "60"),
+            ("-50 + 100 + -50", "0"),
+            ("(5 + 10 * 2 + 15 / 3) * 2 + -10", "50"),
+            ("17 % 5 + 2 ** 3 ** 2", "514"),
+            ("let x = 2; x *= 10; x -= 1; x", "19"),
+            ("let f = fn(n) { let i = 0; n += 1; i += n; i }; f(4)", "5"),
+            ("1.5 * 2", "3.0"),
+            ("-0.25 + 1e-1", "-0.15"),
+            ("let x = 1; x /= 4.0; x", "0.25"),
+        ]);
+    }
+
+    #[test]
+    fn test_conditionals_and_globals() {
+        assert_runs(&[
+            ("if (true) { 10 }", "10"),
+            ("if (false) { 10 }", "null"),
+            ("if (1 > 2) { 10 } else { 20 }", "20"),
+            ("!(if (false) { 5; })", "true"),
+            ("if ((if (false) { 10 })) { 10 } else { 20 }", "20"),
+            ("let one = 1; let two = one + one; one + two", "3"),
+        ]);
+    }
+
+    #[test]
+    fn test_functions_and_closures() {
+        assert_runs(&[
+            ("let f = fn() { 5 + 10; }; f()", "15"),
+            ("let f = fn() { return 99; 100; }; f()", "99"),
+            ("let f = fn() { }; f()", "null"),
+            ("let f = fn(a, b) { let c = a + b; c }; f(1, 2) + f(3, 4)", "10"),
+            (
+                "let newAdder = fn(a) { fn(b) { a + b } }; let addTwo = newAdder(2); addTwo(3)",
+                "5",
+            ),
+            (
+                "let f = fn(a) { fn(b) { fn(c) { a + b + c } } }; f(1)(2)(3)",
+                "6",
+            ),
+            (
+                "let fib = fn(x) { if (x < 2) { x } else { fib(x - 1) + fib(x - 2) } }; fib(15)",
+                "610",
+            ),
+            (
+                "let wrapper = fn() { let inner = fn(x) { if (x == 0) { 0 } else { inner(x - 1) } }; inner(3) }; wrapper()",
+                "0",
+            ),
+            ("let f = fn(x) { x }; f", "fn f/1"),
+        ]);
+    }
+
+    /// Collects trace output in memory so the test can read it back.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_trace() {
+        let (program, _) = Parser::new(Lexer::new("let f = fn(a) { a * 2 }; f(3)")).parse_program();
+        let mut compiler = Compiler::new();
+        compiler.compile_program(&program).unwrap();
+
+        let buffer = SharedBuffer::default();
+        let mut vm = VM::new(compiler.bytecode());
+        vm.set_tracer(Box::new(buffer.clone()));
+        vm.run().unwrap();
+
+        let trace = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        let lines: Vec<&str> = trace.lines().map(|line| line.trim_end()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                format!("0000 {:<32} []", "OpClosure 1 0 (fn f/1)"),
+                format!("0004 {:<32} [fn f/1]", "OpSetGlobal 0"),
+                format!("0007 {:<32} []", "OpGetGlobal 0"),
+                format!("0010 {:<32} [fn f/1]", "OpConstant 2 (3)"),
+                format!("0013 {:<32} [fn f/1, 3]", "OpCall 1"),
+                format!("  0000 {:<32} [fn f/1, 3]", "OpGetLocal 0"),
+                format!("  0002 {:<32} [fn f/1, 3, 3]", "OpConstant 0 (2)"),
+                format!("  0005 {:<32} [fn f/1, 3, 3, 2]", "OpMul"),
+                format!("  0006 {:<32} [fn f/1, 3, 6]", "OpReturnValue"),
+                format!("0015 {:<32} [6]", "OpPop"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_error_spans() {
+        let tests = vec![
+            ("1 + true", "1 + true"),
+            ("let x = 1;\nx + true", "x + true"),
+            ("let f = fn(a) { -a };\nf(true)", "-a"),
+            ("let f = fn(a) { a };\nf(1, 2)", "f(1, 2)"),
+            ("[1, 2][len(1)]", "len(1)"),
+        ];
+
+        for (input, expected) in tests {
+            let (program, _) = Parser::new(Lexer::new(input)).parse_program();
+            let mut compiler = Compiler::new();
+            compiler.compile_program(&program).unwrap();
+
+            let mut vm = VM::new(compiler.bytecode());
+            let span = vm
+                .run()
+                .unwrap_err()
+                .span
+                .expect("error should carry a span");
+            assert_eq!(&input[span.start..span.end], expected, "input {}", input);
+        }
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        assert_eq!(
+            run("let f = fn(x) { f(x + 1) }; f(0)"),
+            Err("stack overflow".to_string())
+        );
+    }
+
+    /// Every program here must give the same value, or the same error
+    /// message, in the evaluator and the VM.
+    #[test]
+    fn test_matches_evaluator() {
+        let programs = [
+            "5 + 5 * 2 - 10 / 2",
+            "-(3 - 10)",
+            "1 < 2 == true",
+            "!!5",
+            "\"foo\" + \"bar\" == \"foobar\"",
+            "if (1) { 10 }",
+            "if (1 > 2) { 10 }",
+            "return 10; 9;",
+            "9; return 2 * 5; 9;",
+            "if (10 > 1) { if (10 > 1) { return 10; } return 1; }",
+            "let a = 5; let b = a; let c = a + b + 5; c;",
+            "let add = fn(x, y) { x + y; }; add(5 + 5, add(5, 5));",
+            "fn(x) { x; }(5)",
+            "let newAdder = fn(x) { fn(y) { x + y } }; newAdder(2)(2)",
+            "let counter = fn(x) { if (x > 100) { return true; } counter(x + 1); }; counter(0)",
+            "[1, 2 * 2, 3 + 3]",
+            "[[1, 2], [3]][0][1]",
+            "[1, 2, 3][3]",
+            "[1, 2, 3][-1]",
+            "{1: 2, \"a\": true, false: [1]}",
+            "{1: 1, 1: 2}[1]",
+            "{\"one\": 1}[\"two\"]",
+            "len(\"héllo\") + len([1, 2])",
+            "rest(push([1, 2], 3))",
+            "let len = fn(x) { 42 }; len([1])",
+            "let map = fn(arr, f) { if (len(arr) == 0) { [] } else { push(map(rest(arr), f), f(first(arr))) } }; map([1, 2, 3], fn(x) { x * 2 })",
+            "5 + true;",
+            "5 + true; 5;",
+            "-true",
+            "true + false;",
+            "\"a\" - \"b\"",
+            "[1] == [1]",
+            "10 / 0",
+            "9223372036854775807 + 1",
+            "-9223372036854775807 - 2",
+            "if (10 > 1) { true + false; }",
+            "let f = fn(x) { x }; f(1, 2)",
+            "5(1)",
+            "1[0]",
+            "{[1]: 2}",
+            "{1: 2}[fn(x) { x }]",
+            "len(1)",
+            "len(\"one\", \"two\")",
+            "first(1)",
+            "foobar",
+            "1 <= 2 == 3 >= 4",
+            "\"a\" <= \"b\"",
+            "10 % 3 - 2 ** 4",
+            "10 % 0",
+            "2 ** -1",
+            "3 ** 40",
+            "-9223372036854775807 - 1 % -1",
+            "[true && 0, false && 1, 0 || false, false || false]",
+            "false && 1 + true; true || 1 + true",
+            "if (1 > 2 || 2 > 1) { 1 } else { 2 }",
+            "let x = 1; false && (x += 1); true || (x += 10); x",
+            "let x = 1; x += 1; x *= x; x",
+            "let x = 5; x /= 0",
+            "let x = \"a\"; x += \"b\"; x",
+            "let x = 1; x += true",
+            "len += 1",
+            "let a = 1; let b = 2; a += b += 3; [a, b]",
+            "let x = 1; let x = x + 1; x",
+            "let x = 10; let f = fn() { let x = x + 1; x }; [f(), x]",
+            "let f = fn(x) { let x = x * 2; let x = x + 1; x }; f(5)",
+            "let f = fn() { let i = 0; let g = fn() { i += 1; }; g(); i }; f()",
+            "let sum = fn(n) { let total = 0; let i = 0; let go = fn() { if (i < n) { i += 1; total += i; go() } }; go(); total }; sum(4)",
+            "let counter = fn() { let n = 0; fn() { n += 1 } }; let c = counter(); c(); c(); [c(), counter()()]",
+            "let f = fn(a) { let g = fn() { fn() { a *= 2 } }; g()(); g()(); a }; f(3)",
+            "let f = fn() { f += 1 }; f()",
+            "let f = fn() { let g = fn() { g += 1 }; g() }; f()",
+            "let f = fn(x) { f(x) }; f(1)",
+            "let f = fn(x) { 1 + f(x) }; f(1)",
+            "let f = fn(x) { if (x == 0) { 0 } else { 1 + f(x - 1) } }; f(100)",
+            "[0x10 + 0b11 + 0o7, 1_000 * 1e3, 2.5 ** 2, 9 % 2.5, 3.0 / 2, -1.5]",
+            "[1 == 1.0, 1.5 != 1.5, 2 <= 2.0, 0.5 > 1, -0.0 == 0]",
+            "10.5 / 0",
+            "1.5 % 0",
+            "1.5 + \"a\"",
+            "{2.5: 1}",
+            "[1, 2][1.0]",
+            "1e308 * 10",
+        ];
+
+        // The evaluator recurses on the native stack, and unoptimized builds
+        // need more than a test thread's default to reach `MAX_FRAMES` calls.
+        let tests = thread::Builder::new().stack_size(64 << 20).spawn(move || {
+            for program in programs {
+                let expected = eval(program).map(|value| value.inspect());
+                let actual = run(program).map(|value| value.inspect());
+                assert_eq!(actual, expected, "program {}", program);
+            }
+        });
+        tests.unwrap().join().unwrap();
+    }
+
+    #[test]
+    fn test_state_persists_between_runs() {
+        let (mut symbol_table, mut constants) = Compiler::new().into_state();
+        let mut globals = vec![];
+        let mut last = Object::Null;
+
+        for input in [
+            "let a = 5;",
+            "let add = fn(x) { a + x };",
+            "add(len(\"four\"))",
+        ] {
+            let (program, _) = Parser::new(Lexer::new(input)).parse_program();
+            let mut compiler = Compiler::new_with_state(symbol_table, constants);
+            compiler.compile_program(&program).unwrap();
+
+            let mut vm = VM::new_with_globals(compiler.bytecode(), globals);
+            vm.run().unwrap();
+            last = vm.last_popped_stack_elem();
+            globals = vm.into_globals();
+            (symbol_table, constants) = compiler.into_state();
+        }
+
+        assert_eq!(last, Object::Integer(9));
+    }
+}